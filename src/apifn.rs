@@ -1,11 +1,18 @@
+use crate::call::{Calls, nested_logs};
+use crate::codec::HeaderFormat;
+use crate::deterministic::WallClock;
+use crate::fetch::{self, FetchRequest, HttpPolicy};
+use crate::kv::KvStore;
+use crate::secrets::Secrets;
 use anyhow::Result;
+use bytes::Bytes;
 use serde::Deserialize;
 use serde_json::Value;
 use std::{
     process::Command,
     sync::{Arc, Mutex, OnceLock},
 };
-use wasmtime::{Caller, Extern, Linker, Memory};
+use wasmtime::{Caller, Extern, Linker, Memory, ResourceLimiter, StoreLimits};
 use wasmtime_wasi::preview1::WasiP1Ctx;
 
 /// Import module name exposed to guest Wasm code.
@@ -20,12 +27,93 @@ pub struct HostState {
     logs: Arc<Mutex<Vec<String>>>,
     module: String,
     header: Value,
+    allow_exec: bool,
+    limits: StoreLimits,
+    header_format: HeaderFormat,
+    data: Option<Bytes>,
+    http_policy: Option<HttpPolicy>,
+    kv: Option<KvStore>,
+    secrets: Arc<Secrets>,
+    calls: Option<Calls>,
+    clock: Option<WallClock>,
 }
 
 impl HostState {
     /// Create a new host state value for a single guest module run.
     pub fn new(wasi: WasiP1Ctx, logs: Arc<Mutex<Vec<String>>>, module: String, header: Value) -> Self {
-        Self { wasi, logs, module, header }
+        Self {
+            wasi,
+            logs,
+            module,
+            header,
+            allow_exec: true,
+            limits: StoreLimits::default(),
+            header_format: HeaderFormat::Json,
+            data: None,
+            http_policy: None,
+            kv: None,
+            secrets: Arc::new(Secrets::default()),
+            calls: None,
+            clock: None,
+        }
+    }
+
+    /// Set the clock that timestamps log lines.
+    /// Default: none, the host's local time
+    pub fn set_clock(&mut self, clock: Option<WallClock>) {
+        self.clock = clock;
+    }
+
+    /// Set the policy for `api.http_fetch`.
+    /// Default: none, every call is denied
+    pub fn set_http_policy(&mut self, policy: Option<HttpPolicy>) {
+        self.http_policy = policy;
+    }
+
+    /// Set the key/value store served by the `api.kv_*` imports.
+    /// Default: none, every call fails
+    pub fn set_kv(&mut self, kv: Option<KvStore>) {
+        self.kv = kv;
+    }
+
+    /// Set the secrets served by `api.secret_get` and redacted from log lines.
+    /// Default: none
+    pub fn set_secrets(&mut self, secrets: Arc<Secrets>) {
+        self.secrets = secrets;
+    }
+
+    /// Set where `api.call` sends its calls.
+    /// Default: none, every call fails
+    pub(crate) fn set_calls(&mut self, calls: Option<Calls>) {
+        self.calls = calls;
+    }
+
+    /// Buffer a log line, with secret values redacted.
+    pub fn push_log(&self, line: &str) {
+        if let Ok(mut g) = self.logs.lock() {
+            g.push(self.secrets.redact_str(line));
+        }
+    }
+
+    /// Allow or deny the `api.exec` import for this run.
+    /// Default: allowed
+    pub fn set_allow_exec(&mut self, allow: bool) {
+        self.allow_exec = allow;
+    }
+
+    /// Return whether the guest may run host commands through `api.exec`.
+    pub fn allow_exec(&self) -> bool {
+        self.allow_exec
+    }
+
+    /// Set the store resource limits (e.g. maximum linear memory) for this run.
+    pub fn set_store_limits(&mut self, limits: StoreLimits) {
+        self.limits = limits;
+    }
+
+    /// Return the resource limiter installed on the store.
+    pub fn limiter(&mut self) -> &mut dyn ResourceLimiter {
+        &mut self.limits
     }
 
     /// Return the mutable WASI Preview 1 context used by the guest instance.
@@ -50,6 +138,28 @@ impl HostState {
     pub fn header(&self) -> &Value {
         &self.header
     }
+
+    /// Set the encoding the header was written to stdin with.
+    /// Default: JSON
+    pub fn set_header_format(&mut self, format: HeaderFormat) {
+        self.header_format = format;
+    }
+
+    /// Return the encoding the header was written to stdin with.
+    pub fn header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    /// Set the data payload served by `api.data_len` and `api.data_read`.
+    /// Default: none, e.g. when the data is streamed
+    pub fn set_data(&mut self, data: Option<Bytes>) {
+        self.data = data;
+    }
+
+    /// Return the data payload of the current guest run, if it is buffered.
+    pub fn data(&self) -> Option<&Bytes> {
+        self.data.as_ref()
+    }
 }
 
 /// Host `exec` request payload accepted from guest code.
@@ -117,11 +227,24 @@ fn request_string(caller: &Caller<'_, HostState>, mem: &Memory, req_ptr: i32, re
     request_bytes(caller, mem, req_ptr, req_len).and_then(|bytes| std::str::from_utf8(bytes).ok()).map(str::to_string)
 }
 
+/// Format a guest log line, with a timestamp, the level and the module id.
+///
+/// Deterministic runs pass their virtual wall clock and get UTC timestamps
+/// from it, everything else is stamped with the host's local time.
+pub fn log_line(clock: Option<&WallClock>, level: &str, module: &str, msg: &str) -> String {
+    const FORMAT: &str = "%d/%m/%Y %H:%M:%S";
+    let ts = match clock.map(WallClock::now) {
+        Some(now) => chrono::DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos()).unwrap_or_default().format(FORMAT).to_string(),
+        None => chrono::Local::now().format(FORMAT).to_string(),
+    };
+    format!("[{ts}] - {level}: [{module}] {msg}")
+}
+
 /// Register the generic host logging import exposed as `api.log`.
 ///
 /// The guest passes a log level and a UTF-8 message pointer/length pair. The
-/// host prefixes the message with timestamp and module id, then buffers it in
-/// `HostState` for later retrieval by the runtime host.
+/// host prefixes the message with timestamp and module id, redacts secret
+/// values, then buffers it in `HostState` for later retrieval by the runtime host.
 pub fn fn_api_log(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
     linker.func_wrap("api", "log", |mut caller: Caller<'_, HostState>, level: i32, msg_ptr: i32, msg_len: i32| {
         let mem = match caller.get_export("memory") {
@@ -156,13 +279,9 @@ pub fn fn_api_log(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
             _ => "INFO",
         };
 
-        let module = caller.data().module.as_str();
-        let ts = chrono::Local::now().format("%d/%m/%Y %H:%M:%S");
-        let line = format!("[{ts}] - {level_s}: [{module}] {msg}");
-
-        if let Ok(mut g) = caller.data().logs.lock() {
-            g.push(line);
-        }
+        let state = caller.data();
+        let line = log_line(state.clock.as_ref(), level_s, &state.module, msg);
+        state.push_log(&line);
     })?;
     Ok(())
 }
@@ -200,6 +319,20 @@ pub fn fn_api_exec(linker: &mut Linker<HostState>) -> Result<()> {
             return -2;
         }
 
+        if !caller.data().allow_exec() {
+            return write_json(
+                &mem,
+                &mut caller,
+                out_ptr,
+                out_cap,
+                &serde_json::json!({
+                    "exit_code": 126,
+                    "stdout": "",
+                    "stderr": "exec is not permitted for this module",
+                }),
+            );
+        }
+
         let mut cmd = Command::new(&req.argv[0]);
         if req.argv.len() > 1 {
             cmd.args(&req.argv[1..]);
@@ -248,6 +381,10 @@ pub fn fn_api_exec(linker: &mut Linker<HostState>) -> Result<()> {
 /// * `api.header` returns the full header object.
 /// * `api.header_has` checks whether a JSON pointer resolves inside the header.
 /// * `api.header_get` returns the value at a JSON pointer, or `null`.
+/// * `api.header_format` returns the stdin header encoding, see `HeaderFormat::code`.
+///
+/// `api.header` and `api.header_get` always return JSON, whatever the stdin
+/// header encoding is.
 pub fn fn_api_header(linker: &mut Linker<HostState>) -> Result<()> {
     linker
         .func_wrap(API_NAMESPACE, "header", |mut caller: Caller<'_, HostState>, out_ptr: i32, out_cap: i32| -> i32 {
@@ -296,5 +433,332 @@ pub fn fn_api_header(linker: &mut Linker<HostState>) -> Result<()> {
         })
         .map_err(|err| anyhow::anyhow!("Failed to register Wasm header_get helper: {err}"))?;
 
+    linker
+        .func_wrap(API_NAMESPACE, "header_format", |caller: Caller<'_, HostState>| -> i32 { caller.data().header_format().code() })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm header_format helper: {err}"))?;
+
+    Ok(())
+}
+
+/// Register data payload access helpers.
+///
+/// These let guests fetch the data section through the host API instead of
+/// parsing stdin:
+/// * `api.data_len` returns the payload length, or -1 when the data is
+///   streamed and only available on stdin.
+/// * `api.data_read` copies payload bytes from an offset into guest memory and
+///   returns the number of bytes written, 0 at the end of the payload.
+pub fn fn_api_data(linker: &mut Linker<HostState>) -> Result<()> {
+    linker
+        .func_wrap(API_NAMESPACE, "data_len", |caller: Caller<'_, HostState>| -> i64 { caller.data().data().map_or(-1, |data| data.len() as i64) })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm data_len helper: {err}"))?;
+
+    linker
+        .func_wrap(API_NAMESPACE, "data_read", |mut caller: Caller<'_, HostState>, offset: i64, out_ptr: i32, out_cap: i32| -> i32 {
+            let mem: Memory = match caller.get_export("memory") {
+                Some(Extern::Memory(m)) => m,
+                _ => return -2,
+            };
+            let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                return -2;
+            };
+            let Some(data) = caller.data().data().cloned() else {
+                return -1;
+            };
+            let Ok(offset) = usize::try_from(offset) else {
+                return -2;
+            };
+
+            write_bytes(&mem, &mut caller, out_ptr, out_cap, data.get(offset..).unwrap_or_default())
+        })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm data_read helper: {err}"))?;
+
+    Ok(())
+}
+
+/// Register the outbound HTTP import exposed as `api.http_fetch`.
+///
+/// Guests pass a JSON request, `{"url": ..., "method": "GET", "headers": {...},
+/// "body": ...}`, and get back `{"status", "headers", "body"}` (see
+/// `fetch::fetch`) or `{"error": ...}`, truncated to the output capacity.
+/// Every call is checked against the run's `HttpPolicy` and logged.
+pub fn fn_api_http_fetch(linker: &mut Linker<HostState>) -> Result<()> {
+    linker
+        .func_wrap_async(
+            API_NAMESPACE,
+            "http_fetch",
+            |mut caller: Caller<'_, HostState>, (req_ptr, req_len, out_ptr, out_cap): (i32, i32, i32, i32)| {
+                Box::new(async move {
+                    let mem: Memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(m)) => m,
+                        _ => return -2,
+                    };
+                    let Some(req) = request_bytes(&caller, &mem, req_ptr, req_len).and_then(|b| serde_json::from_slice::<FetchRequest>(b).ok())
+                    else {
+                        return -2;
+                    };
+                    let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                        return -2;
+                    };
+
+                    let call = format!("http_fetch {} {}", req.method.to_ascii_uppercase(), req.url);
+                    let result = match caller.data().http_policy.clone() {
+                        Some(policy) => fetch::fetch(&policy, req).await,
+                        None => Err(anyhow::anyhow!("outbound HTTP is not permitted for this module")),
+                    };
+
+                    let state = caller.data();
+                    let line = match &result {
+                        Ok(resp) => log_line(state.clock.as_ref(), "INFO", &state.module, &format!("{call} -> {}", resp["status"])),
+                        Err(err) => log_line(state.clock.as_ref(), "WARN", &state.module, &format!("{call} failed: {err:#}")),
+                    };
+                    state.push_log(&line);
+
+                    match result {
+                        Ok(resp) => write_json(&mem, &mut caller, out_ptr, out_cap, &resp),
+                        Err(err) => write_error(&mem, &mut caller, out_ptr, out_cap, &format!("{err:#}")),
+                    }
+                })
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm http_fetch helper: {err}"))?;
+
+    Ok(())
+}
+
+/// Run a key/value store operation for the guest. Failures, such as an
+/// exceeded quota, are logged as warnings and returned as -3.
+fn kv_call<T>(caller: &Caller<'_, HostState>, op: &str, f: impl FnOnce(&KvStore) -> Result<T>) -> Result<T, i32> {
+    let state = caller.data();
+    let result = match &state.kv {
+        Some(kv) => f(kv),
+        None => Err(anyhow::anyhow!("no key/value store is available")),
+    };
+    result.map_err(|err| {
+        state.push_log(&log_line(state.clock.as_ref(), "WARN", &state.module, &format!("{op} failed: {err:#}")));
+        -3
+    })
+}
+
+/// Register the key/value store imports, namespaced by module id.
+///
+/// Keys are UTF-8 strings of up to 256 bytes, values are bytes. Every import
+/// returns -2 on invalid arguments and -3 when the store fails, e.g. because
+/// the write would exceed the module quota:
+/// * `api.kv_get` copies the value of a key into guest memory and returns its
+///   full length, -1 when the key is not set. A return value larger than the
+///   output capacity means the value was truncated.
+/// * `api.kv_set` sets a key to the given value and returns 0.
+/// * `api.kv_delete` deletes a key and returns 1, or 0 when it was not set.
+/// * `api.kv_list` returns the keys starting with a prefix, possibly empty, as
+///   a JSON array.
+pub fn fn_api_kv(linker: &mut Linker<HostState>) -> Result<()> {
+    linker
+        .func_wrap(API_NAMESPACE, "kv_get", |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, out_ptr: i32, out_cap: i32| -> i32 {
+            let mem: Memory = match caller.get_export("memory") {
+                Some(Extern::Memory(m)) => m,
+                _ => return -2,
+            };
+            let Some(key) = request_string(&caller, &mem, key_ptr, key_len) else {
+                return -2;
+            };
+            let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                return -2;
+            };
+
+            match kv_call(&caller, "kv_get", |kv| kv.get(&key)) {
+                Ok(Some(value)) => {
+                    write_bytes(&mem, &mut caller, out_ptr, out_cap, &value);
+                    value.len() as i32
+                }
+                Ok(None) => -1,
+                Err(code) => code,
+            }
+        })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm kv_get helper: {err}"))?;
+
+    linker
+        .func_wrap(API_NAMESPACE, "kv_set", |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, val_ptr: i32, val_len: i32| -> i32 {
+            let mem: Memory = match caller.get_export("memory") {
+                Some(Extern::Memory(m)) => m,
+                _ => return -2,
+            };
+            let Some(key) = request_string(&caller, &mem, key_ptr, key_len) else {
+                return -2;
+            };
+            // Empty values are allowed, unlike empty requests elsewhere.
+            let value = if val_len == 0 { Some(Vec::new()) } else { request_bytes(&caller, &mem, val_ptr, val_len).map(<[u8]>::to_vec) };
+            let Some(value) = value else {
+                return -2;
+            };
+
+            kv_call(&caller, "kv_set", |kv| kv.set(&key, &value)).map_or_else(|code| code, |()| 0)
+        })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm kv_set helper: {err}"))?;
+
+    linker
+        .func_wrap(API_NAMESPACE, "kv_delete", |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32| -> i32 {
+            let mem: Memory = match caller.get_export("memory") {
+                Some(Extern::Memory(m)) => m,
+                _ => return -2,
+            };
+            let Some(key) = request_string(&caller, &mem, key_ptr, key_len) else {
+                return -2;
+            };
+
+            kv_call(&caller, "kv_delete", |kv| kv.delete(&key)).map_or_else(|code| code, i32::from)
+        })
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm kv_delete helper: {err}"))?;
+
+    linker
+        .func_wrap(
+            API_NAMESPACE,
+            "kv_list",
+            |mut caller: Caller<'_, HostState>, prefix_ptr: i32, prefix_len: i32, out_ptr: i32, out_cap: i32| -> i32 {
+                let mem: Memory = match caller.get_export("memory") {
+                    Some(Extern::Memory(m)) => m,
+                    _ => return -2,
+                };
+                let prefix = if prefix_len == 0 { Some(String::new()) } else { request_string(&caller, &mem, prefix_ptr, prefix_len) };
+                let Some(prefix) = prefix else {
+                    return -2;
+                };
+                let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                    return -2;
+                };
+
+                match kv_call(&caller, "kv_list", |kv| kv.list(&prefix)) {
+                    Ok(keys) => write_json(&mem, &mut caller, out_ptr, out_cap, &serde_json::json!(keys)),
+                    Err(code) => code,
+                }
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm kv_list helper: {err}"))?;
+
+    Ok(())
+}
+
+/// Register the secret lookup import exposed as `api.secret_get`.
+///
+/// The guest passes a secret name and gets its value copied into guest memory.
+/// The full length of the value is returned, a return value larger than the
+/// output capacity means the value was truncated. Secrets the module may not
+/// read return -1 and log a warning, invalid arguments return -2.
+pub fn fn_api_secret_get(linker: &mut Linker<HostState>) -> Result<()> {
+    linker
+        .func_wrap(
+            API_NAMESPACE,
+            "secret_get",
+            |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32, out_ptr: i32, out_cap: i32| -> i32 {
+                let mem: Memory = match caller.get_export("memory") {
+                    Some(Extern::Memory(m)) => m,
+                    _ => return -2,
+                };
+                let Some(name) = request_string(&caller, &mem, name_ptr, name_len) else {
+                    return -2;
+                };
+                let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                    return -2;
+                };
+
+                let secrets = caller.data().secrets.clone();
+                let Some(value) = secrets.get(&name) else {
+                    let state = caller.data();
+                    state.push_log(&log_line(
+                        state.clock.as_ref(),
+                        "WARN",
+                        &state.module,
+                        &format!("secret_get '{name}' denied, the module may not read it"),
+                    ));
+                    return -1;
+                };
+                write_bytes(&mem, &mut caller, out_ptr, out_cap, value.as_bytes());
+                value.len() as i32
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm secret_get helper: {err}"))?;
+
+    Ok(())
+}
+
+/// Register the module composition import exposed as `api.call`.
+///
+/// The guest passes a module id, a JSON header (empty for the default header)
+/// and a data payload (possibly empty). The module runs through the same
+/// runtime, and its output, as returned by `WasmRuntime::run_with_options`, or
+/// `{"error": ...}` is written back, truncated to the output capacity.
+///
+/// Calls may nest up to the configured depth and must not call a module that
+/// is already running further up the chain. The child gets the caller's
+/// remaining fuel and deadline, and the fuel it burns is deducted from the
+/// caller. The child's log lines are nested under the caller's, indented.
+pub fn fn_api_call(linker: &mut Linker<HostState>) -> Result<()> {
+    linker
+        .func_wrap_async(
+            API_NAMESPACE,
+            "call",
+            |mut caller: Caller<'_, HostState>,
+             (id_ptr, id_len, header_ptr, header_len, data_ptr, data_len, out_ptr, out_cap): (i32, i32, i32, i32, i32, i32, i32, i32)| {
+                Box::new(async move {
+                    let mem: Memory = match caller.get_export("memory") {
+                        Some(Extern::Memory(m)) => m,
+                        _ => return -2,
+                    };
+                    let Some(id) = request_string(&caller, &mem, id_ptr, id_len) else {
+                        return -2;
+                    };
+                    let header = match header_len {
+                        0 => Some(serde_json::json!({ "opts": [], "args": {} })),
+                        _ => request_bytes(&caller, &mem, header_ptr, header_len).and_then(|b| serde_json::from_slice(b).ok()),
+                    };
+                    let Some(header) = header else {
+                        return -2;
+                    };
+                    let data = match data_len {
+                        0 => Some(Vec::new()),
+                        _ => request_bytes(&caller, &mem, data_ptr, data_len).map(<[u8]>::to_vec),
+                    };
+                    let Some(data) = data else {
+                        return -2;
+                    };
+                    let Some((out_ptr, out_cap)) = output_region(&caller, &mem, out_ptr, out_cap) else {
+                        return -2;
+                    };
+
+                    let fuel = caller.get_fuel().ok();
+                    let used = mem.data_size(&caller);
+                    let calls = caller.data().calls.clone();
+                    let chain = calls.as_ref().map(|c| c.chain(fuel, used));
+                    let mut result = match (&calls, &chain) {
+                        (Some(calls), Some(chain)) => calls.call(chain, &id, header, data).await,
+                        _ => Err(anyhow::anyhow!("module calls are not available here")),
+                    };
+
+                    let mut lines = Vec::new();
+                    if let Some(chain) = &chain {
+                        if let Some(left) = fuel {
+                            let _ = caller.set_fuel(left.saturating_sub(chain.fuel_used()));
+                        }
+                        lines = nested_logs(result.as_mut().ok(), chain);
+                    }
+
+                    let state = caller.data();
+                    match &result {
+                        Ok(_) => state.push_log(&log_line(state.clock.as_ref(), "INFO", &state.module, &format!("call {id}"))),
+                        Err(err) => state.push_log(&log_line(state.clock.as_ref(), "WARN", &state.module, &format!("call {id} failed: {err:#}"))),
+                    }
+                    for line in &lines {
+                        state.push_log(line);
+                    }
+
+                    match result {
+                        Ok(output) => write_json(&mem, &mut caller, out_ptr, out_cap, &output),
+                        Err(err) => write_error(&mem, &mut caller, out_ptr, out_cap, &format!("{err:#}")),
+                    }
+                })
+            },
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to register Wasm call helper: {err}"))?;
+
     Ok(())
 }
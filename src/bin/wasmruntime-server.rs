@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use wasmruntime::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    server::{self, ServerConfig},
+};
+
+/// Serve the modules of a directory as JSON endpoints over HTTP.
+#[derive(Parser)]
+#[command(name = "wasmruntime-server", version)]
+struct Cli {
+    /// Configuration file (.toml or .json), --rootdir overrides it
+    #[arg(long, value_name = "FILE", env = "WASMRUNTIME_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Directory holding the .wasm and .wat modules, defaults to the current directory
+    #[arg(long)]
+    rootdir: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1:8080]
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+
+    /// Requests served at once, further requests get 503 [default: 16]
+    #[arg(long, value_name = "N")]
+    max_concurrency: Option<usize>,
+
+    /// Deadline of a single request in milliseconds [default: 30000]
+    #[arg(long, value_name = "MS")]
+    timeout_ms: Option<u64>,
+
+    /// Largest accepted request body in bytes [default: 16 MiB]
+    #[arg(long, value_name = "BYTES")]
+    max_body_bytes: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut cfg = match &cli.config {
+        Some(path) => WasmConfig::from_file(path)?,
+        None => WasmConfig::default(),
+    };
+    if let Some(dir) = &cli.rootdir {
+        cfg.set_rootdir(std::path::absolute(dir).with_context(|| format!("resolving rootdir {dir:?}"))?);
+    }
+
+    let defaults = ServerConfig::default();
+    let scfg = ServerConfig {
+        listen: cli.listen.unwrap_or(defaults.listen),
+        max_concurrency: cli.max_concurrency.unwrap_or(defaults.max_concurrency),
+        request_timeout: cli.timeout_ms.map(Duration::from_millis).unwrap_or(defaults.request_timeout),
+        max_body_bytes: cli.max_body_bytes.unwrap_or(defaults.max_body_bytes),
+    };
+
+    eprintln!("serving {:?} on http://{}", cfg.get_root_path(), scfg.listen);
+    let rt = Arc::new(WasmRuntime::new(cfg)?);
+    server::serve(rt, &scfg).await
+}
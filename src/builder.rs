@@ -0,0 +1,275 @@
+use crate::cfg::{AccessMode, Limits, Mount, OutputMode, WasmConfig};
+use crate::codec::{Framing, HeaderFormat};
+use crate::deterministic::Deterministic;
+use crate::fetch::HttpPolicy;
+use crate::kv::KvQuota;
+use crate::manifest::Policy;
+use crate::netpolicy::NetworkPolicy;
+use crate::pipeline::Pipeline;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+/// Chainable construction of a `WasmConfig`, validated by `build`.
+///
+/// ```no_run
+/// # use wasmruntime::cfg::{AccessMode, Mount, WasmConfig};
+/// let cfg = WasmConfig::builder()
+///     .rootdir("/srv/wasm")
+///     .host_path("data")
+///     .guest_path("/data")
+///     .access(AccessMode::ReadOnly)
+///     .mount(Mount::ephemeral("/tmp"))
+///     .build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Each method mirrors the `WasmConfig` setter of the same name. Unlike the
+/// setters, the order of the calls does not matter: relative host and mount
+/// paths resolve against the final rootdir when the configuration is built.
+#[derive(Clone, Debug)]
+pub struct WasmConfigBuilder {
+    cfg: WasmConfig,
+    host_path: PathBuf,
+    mounts: Vec<Mount>,
+    kv_dir: Option<PathBuf>,
+}
+
+impl WasmConfig {
+    /// Start building a configuration from the defaults.
+    pub fn builder() -> WasmConfigBuilder {
+        WasmConfigBuilder::from(WasmConfig::default())
+    }
+}
+
+impl From<WasmConfig> for WasmConfigBuilder {
+    /// Start from an existing configuration, keeping its host path and mounts.
+    fn from(cfg: WasmConfig) -> Self {
+        let mounts = cfg.get_mounts().to_vec();
+        let kv_dir = cfg.get_kv_dir().map(Path::to_path_buf);
+        Self { host_path: cfg.get_host_path().to_path_buf(), mounts, kv_dir, cfg }
+    }
+}
+
+impl WasmConfigBuilder {
+    /// Directory holding the modules
+    /// Default: current working directory on the host system
+    /// Note: must be an absolute path to an existing directory
+    pub fn rootdir<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.cfg.set_rootdir(p);
+        self
+    }
+
+    /// Host directory mapped to the guest path
+    /// Default: current working directory on the host system
+    /// Note: a relative path resolves against the final rootdir
+    pub fn host_path<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.host_path = p.as_ref().to_path_buf();
+        self
+    }
+
+    /// Where guests see the host path
+    /// Default: "."
+    /// Note: must be an absolute path or "."
+    pub fn guest_path<S: AsRef<str>>(mut self, s: S) -> Self {
+        self.cfg.set_guest_path(s);
+        self
+    }
+
+    /// Wasm file extension (without dot) listed by `WasmRuntime::objects`
+    /// Default: "wasm"
+    pub fn wasm_ext<S: AsRef<str>>(mut self, s: S) -> Self {
+        self.cfg.set_wasm_ext(s);
+        self
+    }
+
+    /// Access mode of the host path mapping
+    /// Default: none
+    pub fn access(mut self, access: AccessMode) -> Self {
+        self.cfg.set_access(access);
+        self
+    }
+
+    /// Allow write access to the guest path, shorthand for `access`
+    /// Default: false
+    pub fn allow_write(mut self, allow: bool) -> Self {
+        self.cfg.set_allow_write(allow);
+        self
+    }
+
+    /// Directory permissions, combined with the access mode
+    /// Default: all
+    pub fn dir_perms(mut self, perms: DirPerms) -> Self {
+        self.cfg.set_dir_perms(perms);
+        self
+    }
+
+    /// File permissions, combined with the access mode
+    /// Default: all
+    pub fn file_perms(mut self, perms: FilePerms) -> Self {
+        self.cfg.set_file_perms(perms);
+        self
+    }
+
+    /// Allow network access
+    /// Default: false
+    pub fn allow_network(mut self, allow: bool) -> Self {
+        self.cfg.set_allow_network(allow);
+        self
+    }
+
+    /// Fine-grained network policy for guest sockets
+    /// Default: none
+    /// Only used when network access is allowed, see `allow_network`
+    pub fn network_policy(mut self, policy: NetworkPolicy) -> Self {
+        self.cfg.set_network_policy(policy);
+        self
+    }
+
+    /// Policy for outbound HTTP calls
+    /// Default: none, every call is denied
+    pub fn http_policy(mut self, policy: HttpPolicy) -> Self {
+        self.cfg.set_http_policy(policy);
+        self
+    }
+
+    /// Add a mount
+    /// Default: no mounts
+    /// Note: a relative host path resolves against the final rootdir
+    pub fn mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Replace all mounts
+    /// Default: no mounts
+    pub fn mounts(mut self, mounts: Vec<Mount>) -> Self {
+        self.mounts = mounts;
+        self
+    }
+
+    /// Extra guest command-line arguments, after the module id
+    /// Default: none
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> Self {
+        self.cfg.set_args(args);
+        self
+    }
+
+    /// Set a guest environment variable, replacing a previous value
+    /// Default: no variables
+    pub fn env<K: AsRef<str>, V: AsRef<str>>(mut self, key: K, value: V) -> Self {
+        self.cfg.set_env(key, value);
+        self
+    }
+
+    /// Host environment variables guests inherit
+    /// Default: none
+    pub fn inherit_env<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.cfg.set_inherit_env(names);
+        self
+    }
+
+    /// Append header `opts` to the guest argv
+    /// Default: false
+    pub fn opts_as_argv(mut self, enable: bool) -> Self {
+        self.cfg.set_opts_as_argv(enable);
+        self
+    }
+
+    /// How guest stdout is turned into the run result
+    /// Default: Json
+    pub fn output_mode(mut self, mode: OutputMode) -> Self {
+        self.cfg.set_output_mode(mode);
+        self
+    }
+
+    /// How the header is encoded at the start of guest stdin
+    /// Default: Json
+    pub fn header_format(mut self, format: HeaderFormat) -> Self {
+        self.cfg.set_header_format(format);
+        self
+    }
+
+    /// How the header and data are laid out on guest stdin
+    /// Default: Line
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.cfg.set_framing(framing);
+        self
+    }
+
+    /// Resource limits for every module run
+    /// Default: none
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.cfg.set_limits(limits);
+        self
+    }
+
+    /// Directory of the key/value store
+    /// Default: none, the store is kept in memory
+    /// Note: a relative path resolves against the final rootdir
+    pub fn kv_dir<P: AsRef<Path>>(mut self, p: P) -> Self {
+        self.kv_dir = Some(p.as_ref().to_path_buf());
+        self
+    }
+
+    /// Key/value store quota of every module
+    /// Default: 1024 keys, 1 MiB in total and 64 KiB per value
+    pub fn kv_quota(mut self, quota: KvQuota) -> Self {
+        self.cfg.set_kv_quota(quota);
+        self
+    }
+
+    /// Names of the secrets modules may read
+    /// Default: none
+    pub fn secrets<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.cfg.set_secrets(names);
+        self
+    }
+
+    /// How deep modules may nest `api.call` invocations, 0 disables it
+    /// Default: 4
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.cfg.set_max_call_depth(depth);
+        self
+    }
+
+    /// Module ids modules may run through `api.call`
+    /// Default: none
+    pub fn callees<S: AsRef<str>>(mut self, ids: &[S]) -> Self {
+        self.cfg.set_callees(ids);
+        self
+    }
+
+    /// Declare a named pipeline, replacing any pipeline of the same name
+    /// Default: none
+    pub fn pipeline(mut self, name: &str, pipeline: Pipeline) -> Self {
+        self.cfg.set_pipeline(name, pipeline);
+        self
+    }
+
+    /// Run modules deterministically, with virtual clocks and seeded randomness
+    /// Default: none, guests see the host clocks and random sources
+    pub fn deterministic(mut self, det: Deterministic) -> Self {
+        self.cfg.set_deterministic(det);
+        self
+    }
+
+    /// Host-side capability policy for modules with a capability manifest
+    /// Default: derived from the other settings
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.cfg.set_policy(policy);
+        self
+    }
+
+    /// Resolve relative paths and check the result, see `WasmConfig::validate`
+    pub fn build(self) -> Result<WasmConfig> {
+        let mut cfg = self.cfg;
+        cfg.set_host_path(&self.host_path)?;
+        cfg.set_mounts(self.mounts);
+        if let Some(dir) = &self.kv_dir {
+            cfg.set_kv_dir(dir);
+        }
+        cfg.validate()?;
+        Ok(cfg)
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    builder::WasmConfigBuilder,
+    cfg::{AccessMode, Mount, WasmConfig},
+    netpolicy::NetworkPolicy,
+};
+use std::fs;
+
+#[test]
+fn builder_chains_and_resolves_paths_against_final_rootdir() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::create_dir(root.path().join("data")).expect("create data dir");
+
+    // host_path and the mount come before rootdir, they still resolve against it.
+    let cfg = WasmConfig::builder()
+        .host_path("data")
+        .mount(Mount::new("out", "/out").create(true))
+        .rootdir(root.path())
+        .guest_path("/data")
+        .access(AccessMode::ReadOnly)
+        .args(&["-v"])
+        .build()
+        .expect("config should build");
+
+    assert_eq!(cfg.get_host_path(), root.path().join("data"));
+    assert_eq!(cfg.get_mounts()[0].host_path, root.path().join("out"));
+    assert_eq!(cfg.get_guest_path(), "/data");
+    assert_eq!(cfg.get_access(), AccessMode::ReadOnly);
+    assert_eq!(cfg.get_args(), ["-v"]);
+}
+
+#[test]
+fn builder_starts_from_existing_config() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let mut base = WasmConfig::default();
+    base.set_rootdir(root.path());
+    base.add_mount(Mount::ephemeral("/scratch"));
+
+    let cfg = WasmConfigBuilder::from(base).allow_network(true).build().expect("config should build");
+    assert!(cfg.get_allow_network());
+    assert_eq!(cfg.get_mounts(), [Mount::ephemeral("/scratch")]);
+}
+
+#[test]
+fn builder_rejects_invalid_configs() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let base = || WasmConfig::builder().rootdir(root.path());
+    let fails = |b: WasmConfigBuilder| format!("{:#}", b.build().expect_err("config should be rejected"));
+
+    assert!(fails(WasmConfig::builder().rootdir("relative/wasm")).contains("must be an absolute path"));
+    assert!(fails(WasmConfig::builder().rootdir(root.path().join("missing"))).contains("is not an existing directory"));
+    assert!(fails(base().host_path("")).contains("must not be empty"));
+    assert!(fails(base().host_path("missing").allow_write(true)).contains("required by access mode"));
+    assert!(fails(base().guest_path("data")).contains("must be \".\" or an absolute path"));
+    assert!(fails(base().mount(Mount::ephemeral("../up"))).contains("invalid mount"));
+
+    // Contradictory combinations.
+    assert!(fails(base().network_policy(NetworkPolicy::default())).contains("network access is disabled"));
+    assert!(fails(base().access(AccessMode::ReadOnly).guest_path("/data").mount(Mount::ephemeral("/data"))).contains("preopened twice"));
+    assert!(fails(base().mount(Mount::ephemeral("/a")).mount(Mount::ephemeral("/a"))).contains("preopened twice"));
+    assert!(fails(base().mount(Mount { ephemeral: true, ..Mount::new("out", "/out") })).contains("must not have a host path"));
+    assert!(fails(base().mount(Mount::new("out", "/out").create(true).collect_files(&["result.json"]))).contains("only ephemeral mounts"));
+
+    assert!(base().build().is_ok());
+}
@@ -0,0 +1,133 @@
+use crate::WasmRuntime;
+use crate::overlay::RunOptions;
+use anyhow::{Result, bail};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Default limit of nested `api.call` invocations.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 4;
+
+/// Where a run sits in a chain of `api.call` invocations.
+///
+/// A top-level run uses the default: no callers and no inherited budget. A
+/// child run inherits the fuel and memory its caller had left and the caller's
+/// deadline, and reports the fuel it burnt and its log lines back to the caller.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallChain {
+    /// Module ids of the callers, outermost first.
+    pub(crate) callers: Vec<String>,
+    pub(crate) fuel: Option<u64>,
+    /// Linear memory in bytes the caller had left.
+    pub(crate) memory: Option<usize>,
+    pub(crate) deadline: Option<Instant>,
+    /// Log buffer of the run.
+    pub(crate) logs: Arc<Mutex<Vec<String>>>,
+    pub(crate) fuel_used: Arc<AtomicU64>,
+}
+
+impl CallChain {
+    /// Record the fuel the run burnt.
+    pub(crate) fn record_fuel(&self, used: u64) {
+        self.fuel_used.store(used, Ordering::Release);
+    }
+
+    /// Get the fuel the run burnt, 0 until it is done.
+    pub(crate) fn fuel_used(&self) -> u64 {
+        self.fuel_used.load(Ordering::Acquire)
+    }
+}
+
+/// The `api.call` endpoint of a running guest: where to send calls, and the
+/// chain they extend.
+#[derive(Clone)]
+pub(crate) struct Calls {
+    pub(crate) tx: mpsc::Sender<ChildCall>,
+    /// Module ids from the outermost caller down to the running module.
+    pub(crate) path: Vec<String>,
+    pub(crate) deadline: Option<Instant>,
+    /// Memory limit of the running module in bytes.
+    pub(crate) memory: Option<usize>,
+    pub(crate) max_depth: usize,
+    /// Module ids the running module may call.
+    pub(crate) callees: Vec<String>,
+}
+
+impl Calls {
+    /// Check that the running module may call `id`.
+    pub(crate) fn check(&self, id: &str) -> Result<()> {
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            bail!("invalid module id '{id}'");
+        }
+        if self.path.iter().any(|p| p == id) {
+            bail!("call cycle: {} -> {id}", self.path.join(" -> "));
+        }
+        if !self.callees.iter().any(|c| c == id) {
+            bail!("module '{}' may not call '{id}'", self.path.last().map_or("", String::as_str));
+        }
+        if self.path.len() > self.max_depth {
+            bail!("call depth limit of {} reached: {} -> {id}", self.max_depth, self.path.join(" -> "));
+        }
+        Ok(())
+    }
+
+    /// Start the chain of a call to a child, with the fuel the caller has left
+    /// and the memory left once the caller's `used` bytes are taken.
+    pub(crate) fn chain(&self, fuel: Option<u64>, used: usize) -> CallChain {
+        let memory = self.memory.map(|m| m.saturating_sub(used));
+        CallChain { callers: self.path.clone(), fuel, memory, deadline: self.deadline, ..Default::default() }
+    }
+
+    /// Run `id` as a child in `chain` and wait for its output.
+    pub(crate) async fn call(&self, chain: &CallChain, id: &str, header: Value, data: Vec<u8>) -> Result<Value> {
+        self.check(id)?;
+        let (reply, response) = oneshot::channel();
+        let call = ChildCall { id: id.to_string(), header, data, chain: chain.clone(), reply };
+        if self.tx.send(call).await.is_err() {
+            bail!("module calls are not served");
+        }
+        response.await.unwrap_or_else(|_| Err(anyhow::anyhow!("module call to '{id}' was dropped")))
+    }
+}
+
+/// A call from a guest, served by the `execute` of its caller.
+pub(crate) struct ChildCall {
+    pub(crate) id: String,
+    pub(crate) header: Value,
+    pub(crate) data: Vec<u8>,
+    pub(crate) chain: CallChain,
+    pub(crate) reply: oneshot::Sender<Result<Value>>,
+}
+
+impl WasmRuntime {
+    /// Serve the `api.call` invocations of a running guest until every sender
+    /// is dropped, which is not before the guest returns.
+    ///
+    /// Children run one at a time, on the caller's task, since the calling
+    /// guest waits for the result anyway. Boxed, since the child may serve
+    /// calls of its own.
+    pub(crate) fn serve_calls(&self, mut rx: mpsc::Receiver<ChildCall>) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            while let Some(call) = rx.recv().await {
+                let output = self.run_in_chain(&call.id, call.header, call.data, &RunOptions::default(), &call.chain).await;
+                let _ = call.reply.send(output);
+            }
+        })
+    }
+}
+
+/// Lines of a child run, indented under the caller's lines.
+pub(crate) fn nested_logs(output: Option<&mut Value>, chain: &CallChain) -> Vec<String> {
+    let mut lines: Vec<String> = match output.and_then(|v| v.as_object_mut()).and_then(|o| o.remove("__module-logs")) {
+        Some(Value::Array(items)) => items.into_iter().filter_map(|l| l.as_str().map(str::to_string)).collect(),
+        _ => Vec::new(),
+    };
+    if let Ok(mut g) = chain.logs.lock() {
+        lines.append(&mut g);
+    }
+    lines.into_iter().map(|l| format!("  {l}")).collect()
+}
@@ -0,0 +1,177 @@
+use crate::{
+    WasmRuntime,
+    cfg::{Limits, WasmConfig},
+    overlay::{ConfigOverlay, RunOptions},
+};
+use serde_json::json;
+use std::{collections::HashMap, fs, path::Path};
+
+/// Logs "echo called" and prints its data payload.
+static ECHO_WAT: &str = r#"
+(module
+  (import "api" "data_read" (func $read (param i64 i32 i32) (result i32)))
+  (import "api" "log" (func $log (param i32 i32 i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "echo called")
+  (func (export "_start")
+    (call $log (i32.const 1) (i32.const 100) (i32.const 11))
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (call $read (i64.const 0) (i32.const 1024) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+/// Never returns.
+static SPIN_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (loop $spin (br $spin))))
+"#;
+
+/// Build a module that calls `target` with `{"n":1}` as data, with the
+/// default header, and prints the result.
+fn caller_wat(target: &str) -> String {
+    format!(
+        r#"
+(module
+  (import "api" "call" (func $call (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "{target}")
+  (data (i32.const 200) "{{\"n\":1}}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4)
+      (call $call (i32.const 100) (i32.const {}) (i32.const 0) (i32.const 0) (i32.const 200) (i32.const 7) (i32.const 1024) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#,
+        target.len()
+    )
+}
+
+/// Write `modules` to `root` and configure them as callees of each other.
+fn config(root: &Path, modules: &[(&str, String)]) -> WasmConfig {
+    for (id, wat) in modules {
+        fs::write(root.join(format!("{id}.wat")), wat).expect("write module");
+    }
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root);
+    cfg.set_callees(&modules.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+    cfg
+}
+
+fn runtime(root: &Path, modules: &[(&str, String)]) -> WasmRuntime {
+    WasmRuntime::new(config(root, modules)).expect("runtime should initialize")
+}
+
+#[tokio::test]
+async fn api_call_runs_a_child_and_nests_its_logs() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let rt = runtime(root.path(), &[("echo", ECHO_WAT.to_string()), ("parent", caller_wat("echo")), ("grandparent", caller_wat("parent"))]);
+
+    let out = rt.run("parent", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["n"], json!(1), "unexpected output: {out}");
+    let logs: Vec<&str> = out["__module-logs"].as_array().expect("logs").iter().filter_map(|l| l.as_str()).collect();
+    assert_eq!(logs.len(), 2, "unexpected logs: {logs:?}");
+    assert!(logs[0].ends_with("INFO: [parent] call echo"), "unexpected logs: {logs:?}");
+    assert!(logs[1].starts_with("  [") && logs[1].ends_with("INFO: [echo] echo called"), "unexpected logs: {logs:?}");
+
+    let out = rt.run("grandparent", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["n"], json!(1), "unexpected output: {out}");
+    let logs: Vec<&str> = out["__module-logs"].as_array().expect("logs").iter().filter_map(|l| l.as_str()).collect();
+    assert!(logs[2].starts_with("    [") && logs[2].ends_with("INFO: [echo] echo called"), "unexpected logs: {logs:?}");
+}
+
+#[tokio::test]
+async fn api_call_detects_cycles_and_limits_depth() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let modules = [("a", caller_wat("b")), ("b", caller_wat("a")), ("c", caller_wat("echo")), ("d", caller_wat("c")), ("echo", ECHO_WAT.to_string())];
+    let rt = runtime(root.path(), &modules);
+
+    let out = rt.run("a", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["error"], json!("call cycle: a -> b -> a"), "unexpected output: {out}");
+
+    let out = rt.run("b", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    let logs = out["__module-logs"].to_string();
+    assert!(logs.contains("WARN: [a] call b failed: call cycle: b -> a -> b"), "unexpected logs: {logs}");
+
+    let mut cfg = config(root.path(), &modules);
+    cfg.set_max_call_depth(1);
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let out = rt.run("d", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["error"], json!("call depth limit of 1 reached: d -> c -> echo"), "unexpected output: {out}");
+    let out = rt.run("c", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["n"], json!(1), "unexpected output: {out}");
+}
+
+/// Calls "spin", then calls a function of its own, which checks the fuel left.
+static BURNT_WAT: &str = r#"
+(module
+  (import "api" "call" (func $call (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "spin")
+  (func $after)
+  (func (export "_start")
+    (drop (call $call (i32.const 100) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 1024) (i32.const 4096)))
+    (call $after)))
+"#;
+
+#[tokio::test]
+async fn api_call_deducts_child_fuel_from_the_caller() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_limits(Limits { fuel: Some(1_000_000), ..Default::default() });
+    cfg.set_callees(&["spin"]);
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+    fs::write(root.path().join("parent.wat"), BURNT_WAT).expect("write parent.wat");
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    // The child burns everything the parent had left, so the parent runs out
+    // of fuel at its next function call.
+    let err = rt.run("parent", Vec::new(), HashMap::new(), Vec::new()).await.expect_err("parent should run out of fuel");
+    assert!(format!("{err:?}").contains("fuel"), "unexpected error: {err:?}");
+}
+
+#[tokio::test]
+async fn api_call_needs_the_callee_in_the_allowlist() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let mut cfg = config(root.path(), &[("echo", ECHO_WAT.to_string()), ("parent", caller_wat("echo"))]);
+    cfg.set_callees::<&str>(&[]);
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let out = rt.run("parent", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["error"], json!("module 'parent' may not call 'echo'"), "unexpected output: {out}");
+
+    // The allowlist is the caller's: a per-call override grants it.
+    let opts = RunOptions::with_config(ConfigOverlay { callees: Some(vec!["echo".to_string()]), ..Default::default() });
+    let out = rt.run_with_options("parent", json!({"opts": [], "args": {}}), Vec::new(), &opts).await.expect("module should run");
+    assert_eq!(out["n"], json!(1), "unexpected output: {out}");
+}
+
+/// Needs two pages of memory and prints `{"n":2}`.
+static TWO_PAGES_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 2)
+  (data (i32.const 100) "{\"n\":2}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 100))
+    (i32.store (i32.const 4) (i32.const 7))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+#[tokio::test]
+async fn api_call_deducts_caller_memory_from_the_child() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let mut cfg = config(root.path(), &[("big", TWO_PAGES_WAT.to_string()), ("parent", caller_wat("big"))]);
+    cfg.set_limits(Limits { memory: Some(2 * 65536), ..Default::default() });
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    // Two pages fit the limit on their own, but not next to the caller's page.
+    let out = rt.run("big", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["n"], json!(2), "unexpected output: {out}");
+    let out = rt.run("parent", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    let err = out["error"].as_str().unwrap_or_default();
+    assert!(err.contains("memory"), "unexpected output: {out}");
+}
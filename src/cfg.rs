@@ -1,7 +1,180 @@
-use anyhow::Result;
+use crate::call::DEFAULT_MAX_CALL_DEPTH;
+use crate::cfgfile::perms;
+use crate::codec::{Framing, HeaderFormat};
+use crate::deterministic::Deterministic;
+use crate::fetch::HttpPolicy;
+use crate::kv::KvQuota;
+use crate::manifest::Policy;
+use crate::netpolicy::NetworkPolicy;
+use crate::pipeline::Pipeline;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use wasmtime_wasi::{DirPerms, FilePerms};
 
+/// Resource limits applied to a single module run
+/// Every limit is optional, `None` means unlimited
+/// - fuel: instruction budget, see wasmtime fuel metering. Fuel is metered for every
+///   run, which makes guest code somewhat slower even without a fuel limit
+/// - memory: maximum linear memory size in bytes
+/// - timeout_ms: wall-clock deadline for the whole run in milliseconds, enforced
+///   through epoch interruption
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub fuel: Option<u64>,
+    pub memory: Option<usize>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl Limits {
+    /// Combine two sets of limits, keeping the stricter value of each
+    pub fn intersect(&self, other: &Limits) -> Limits {
+        fn min<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        Limits { fuel: min(self.fuel, other.fuel), memory: min(self.memory, other.memory), timeout_ms: min(self.timeout_ms, other.timeout_ms) }
+    }
+
+    /// Get the run deadline, if any
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Access mode of the host path mapping
+/// - None: the host path is not preopened at all
+/// - ReadOnly: preopened with read access only, writes fail with EPERM, not EACCES
+/// - ReadWrite: preopened with the configured directory and file permissions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    #[default]
+    None,
+    ReadOnly,
+    ReadWrite,
+}
+
+impl AccessMode {
+    /// Map configured permissions onto this access mode
+    /// Returns `None` when nothing should be preopened
+    pub fn perms(&self, dir_perms: DirPerms, file_perms: FilePerms) -> Option<(DirPerms, FilePerms)> {
+        match self {
+            AccessMode::None => None,
+            AccessMode::ReadOnly => Some((dir_perms & DirPerms::READ, file_perms & FilePerms::READ)),
+            AccessMode::ReadWrite => Some((dir_perms, file_perms)),
+        }
+    }
+}
+
+/// How guest stdout is turned into the run result
+/// - Json: parsed as JSON, plain text becomes `{"data": text}`, non-UTF-8 output fails the run
+/// - Raw: not parsed, UTF-8 output becomes `{"data": text}`, anything else `{"base64": ...}`
+/// - Base64: always `{"base64": ...}`
+///
+/// The exact bytes are available from `WasmRuntime::run_output` in every mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    #[default]
+    Json,
+    Raw,
+    Base64,
+}
+
+/// A host directory preopened in the guest
+/// Each mount has its own permissions, independent of the global host path
+/// - host_path: directory on the host, relative paths resolve against rootdir
+/// - guest_path: where the guest sees it, absolute or "."
+/// - dir_perms / file_perms: WASI permissions, default all
+/// - read_only: clamp permissions to read access, default false
+/// - create: create the host directory if it is missing, default false
+/// - ephemeral: back the mount with a fresh temporary directory for every run,
+///   deleted when the run ends, default false. Preopened for modules with a
+///   capability manifest too, since it exposes nothing from the host
+/// - collect: files (relative to the mount) to copy into the run result
+///   before an ephemeral mount is deleted
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mount {
+    #[serde(default)]
+    pub host_path: PathBuf,
+    pub guest_path: String,
+    #[serde(default = "DirPerms::all", with = "perms")]
+    pub dir_perms: DirPerms,
+    #[serde(default = "FilePerms::all", with = "perms")]
+    pub file_perms: FilePerms,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default)]
+    pub ephemeral: bool,
+    #[serde(default)]
+    pub collect: Vec<String>,
+}
+
+impl Mount {
+    /// Create a read-write mount of `host_path` at `guest_path`
+    pub fn new<P: AsRef<Path>, S: AsRef<str>>(host_path: P, guest_path: S) -> Self {
+        Self {
+            host_path: host_path.as_ref().to_path_buf(),
+            guest_path: guest_path.as_ref().to_string(),
+            dir_perms: DirPerms::all(),
+            file_perms: FilePerms::all(),
+            read_only: false,
+            create: false,
+            ephemeral: false,
+            collect: Vec::new(),
+        }
+    }
+
+    /// Create an ephemeral read-write mount at `guest_path`
+    /// Every run gets its own empty temporary directory, so concurrent runs
+    /// never see each other's files. The directory is deleted after the run.
+    pub fn ephemeral<S: AsRef<str>>(guest_path: S) -> Self {
+        Self { ephemeral: true, ..Self::new(PathBuf::new(), guest_path) }
+    }
+
+    /// Set the files to collect into the run result, relative to the mount
+    /// Collected files show up under `__module-files`, keyed by their guest path
+    pub fn collect_files<S: AsRef<str>>(mut self, files: &[S]) -> Self {
+        self.collect = files.iter().map(|f| f.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Set directory and file permissions
+    pub fn perms(mut self, dir_perms: DirPerms, file_perms: FilePerms) -> Self {
+        self.dir_perms = dir_perms;
+        self.file_perms = file_perms;
+        self
+    }
+
+    /// Make the mount read-only
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Create the host directory if it is missing
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    /// Get the permissions the mount is preopened with
+    /// Read-only mounts keep at most read access
+    pub fn effective_perms(&self) -> (DirPerms, FilePerms) {
+        if self.read_only { (self.dir_perms & DirPerms::READ, self.file_perms & FilePerms::READ) } else { (self.dir_perms, self.file_perms) }
+    }
+}
+
 /// Configuration for the WasmRuntime
 /// Includes settings for the WASI environment
 /// such as directory and file permissions, root directory, etc.
@@ -15,8 +188,24 @@ use wasmtime_wasi::{DirPerms, FilePerms};
 /// - root directory: current working directory on the host system (e.g. "/home/user")
 /// - directory permissions: all
 /// - file permissions: all
-/// - allow write access: false
+/// - access mode: none
+/// - network policy: none, network access enables TCP and UDP as a whole
+/// - outbound HTTP policy: none, `api.http_fetch` and outgoing wasi:http requests are denied
 /// - wasm file extension: "wasm"
+/// - additional mounts: none
+/// - guest argv: module id only
+/// - guest environment: empty, nothing inherited from the host
+/// - header opts as argv: false
+/// - output mode: JSON
+/// - header format: JSON
+/// - stdin framing: header line, then data
+/// - resource limits: none
+/// - deterministic execution: off
+/// - secrets: none readable by modules
+/// - module calls: none allowed, up to 4 levels deep once callees are set
+/// - key/value store: in memory, see `set_kv_dir`, with the default `KvQuota` per module
+/// - pipelines: none
+/// - capability policy: derived from the settings above
 #[derive(Clone, Debug)]
 pub struct WasmConfig {
     host_path: PathBuf,
@@ -26,8 +215,28 @@ pub struct WasmConfig {
     rootdir: PathBuf,
     wasm_ext: String,
 
-    allow_write: bool,
+    access: AccessMode,
     allow_network: bool,
+    network_policy: Option<NetworkPolicy>,
+    http_policy: Option<HttpPolicy>,
+
+    mounts: Vec<Mount>,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    inherit_env: Vec<String>,
+    opts_as_argv: bool,
+    output_mode: OutputMode,
+    header_format: HeaderFormat,
+    framing: Framing,
+    limits: Limits,
+    kv_dir: Option<PathBuf>,
+    kv_quota: KvQuota,
+    secrets: Vec<String>,
+    max_call_depth: usize,
+    callees: Vec<String>,
+    pipelines: BTreeMap<String, Pipeline>,
+    deterministic: Option<Deterministic>,
+    policy: Option<Policy>,
 }
 
 impl Default for WasmConfig {
@@ -38,9 +247,28 @@ impl Default for WasmConfig {
             dir_perms: DirPerms::all(),
             file_perms: FilePerms::all(),
             rootdir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
-            allow_write: false,
+            access: AccessMode::None,
             wasm_ext: "wasm".to_string(),
             allow_network: false,
+            network_policy: None,
+            http_policy: None,
+            mounts: Vec::new(),
+            args: Vec::new(),
+            env: Vec::new(),
+            inherit_env: Vec::new(),
+            opts_as_argv: false,
+            output_mode: OutputMode::Json,
+            header_format: HeaderFormat::Json,
+            framing: Framing::Line,
+            limits: Limits::default(),
+            kv_dir: None,
+            kv_quota: KvQuota::default(),
+            secrets: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            callees: Vec::new(),
+            pipelines: BTreeMap::new(),
+            deterministic: None,
+            policy: None,
         }
     }
 }
@@ -58,6 +286,37 @@ impl WasmConfig {
         self.allow_network
     }
 
+    /// Set a fine-grained network policy for guest sockets
+    /// Default: none
+    /// Only used when network access is allowed, see `set_allow_network`.
+    /// Without a policy, allowing network enables TCP and UDP as a whole.
+    pub fn set_network_policy(&mut self, policy: NetworkPolicy) -> &Self {
+        self.network_policy = Some(policy);
+        self
+    }
+
+    /// Get the network policy for guest sockets
+    /// Default: none
+    pub fn get_network_policy(&self) -> Option<&NetworkPolicy> {
+        self.network_policy.as_ref()
+    }
+
+    /// Set the policy for outbound HTTP calls through `api.http_fetch`
+    /// and, for HTTP handler components, `wasi:http/outgoing-handler`
+    /// Default: none, every call is denied
+    /// Independent of `set_allow_network`, which only governs raw sockets,
+    /// except that modules with a capability manifest must be granted network
+    pub fn set_http_policy(&mut self, policy: HttpPolicy) -> &Self {
+        self.http_policy = Some(policy);
+        self
+    }
+
+    /// Get the policy for outbound HTTP calls
+    /// Default: none
+    pub fn get_http_policy(&self) -> Option<&HttpPolicy> {
+        self.http_policy.as_ref()
+    }
+
     /// Create a new WasmConfig with default settings
     /// Default host path: current working directory on the host system (e.g. "/home/user")
     /// Default guest path: "."
@@ -72,8 +331,12 @@ impl WasmConfig {
     /// This is the path on the host system that maps to the guest path
     /// e.g. if host_path is "/tmp" and guest_path is "/data", then
     /// the WASI module will see "/data" as "/tmp" on the host system
-    /// Note: host_path must be an absolute path
+    /// Note: host_path must be an absolute path, a relative path resolves against rootdir
+    /// Fails on an empty path
     pub fn set_host_path<P: AsRef<Path>>(&mut self, p: P) -> Result<&Self> {
+        if p.as_ref().as_os_str().is_empty() {
+            bail!("host path must not be empty");
+        }
         self.host_path = if p.as_ref().is_absolute() { p.as_ref().to_path_buf() } else { self.rootdir.join(p) };
         Ok(self)
     }
@@ -114,14 +377,32 @@ impl WasmConfig {
 
     /// Allow write access to the guest path
     /// Default: false
+    /// Shorthand for `set_access`: true is `AccessMode::ReadWrite`,
+    /// false is `AccessMode::None`
     pub fn set_allow_write(&mut self, allow: bool) -> &Self {
-        self.allow_write = allow;
+        self.access = if allow { AccessMode::ReadWrite } else { AccessMode::None };
         self
     }
 
+    /// Set the access mode of the host path mapping
+    /// Default: none
+    /// `AccessMode::ReadOnly` lets guests read input files without being
+    /// able to create, modify or delete anything
+    pub fn set_access(&mut self, access: AccessMode) -> &Self {
+        self.access = access;
+        self
+    }
+
+    /// Get the access mode of the host path mapping
+    /// Default: none
+    pub fn get_access(&self) -> AccessMode {
+        self.access
+    }
+
     /// Set directory permissions
     /// Default: all
-    /// Caveat: this does not affect write access, see `set_allow_write`
+    /// Used as-is in `AccessMode::ReadWrite`, clamped to `DirPerms::READ`
+    /// in `AccessMode::ReadOnly`, see `set_access`
     pub fn set_dir_perms(&mut self, perms: DirPerms) -> &Self {
         self.dir_perms = perms;
         self
@@ -129,7 +410,8 @@ impl WasmConfig {
 
     /// Set file permissions
     /// Default: all
-    /// Caveat: this does not affect write access, see `set_allow_write`
+    /// Used as-is in `AccessMode::ReadWrite`, clamped to `FilePerms::READ`
+    /// in `AccessMode::ReadOnly`, see `set_access`
     pub fn set_file_perms(&mut self, perms: FilePerms) -> &Self {
         self.file_perms = perms;
         self
@@ -138,7 +420,7 @@ impl WasmConfig {
     /// Get whether write access to the guest path is allowed
     /// Default: false
     pub fn get_allow_write(&self) -> bool {
-        self.allow_write
+        self.access == AccessMode::ReadWrite
     }
 
     /// Get the host path
@@ -172,15 +454,350 @@ impl WasmConfig {
 
     /// Get directory permissions
     /// Default: all
-    /// Caveat: this does not affect write access, see `set_allow_write`
+    /// See `set_dir_perms` for how they combine with the access mode
     pub fn get_dir_perms(&self) -> DirPerms {
         self.dir_perms
     }
 
     /// Get file permissions
     /// Default: all
-    /// Caveat: this does not affect write access, see `set_allow_write`
+    /// See `set_file_perms` for how they combine with the access mode
     pub fn get_file_perms(&self) -> FilePerms {
         self.file_perms
     }
+
+    /// Set the host-side capability policy
+    /// Default: derived from host path, guest path, access mode, mounts and allow_network
+    /// Modules that ship a capability manifest (sidecar `{id}.toml` or the
+    /// `wasmruntime.manifest` custom section) get the intersection of what they
+    /// declare and what this policy grants. Modules without a manifest keep
+    /// using the global settings.
+    pub fn set_policy(&mut self, policy: Policy) -> &Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Get the host-side capability policy
+    /// Returns the explicitly set policy, or one derived from the global settings
+    pub fn get_policy(&self) -> Policy {
+        self.policy.clone().unwrap_or_else(|| Policy::from_config(self))
+    }
+
+    /// Set resource limits for every module run
+    /// Default: none
+    /// Limits declared in a module manifest or granted by the policy are
+    /// combined with these, the stricter value wins
+    pub fn set_limits(&mut self, limits: Limits) -> &Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Get resource limits for every module run
+    /// Default: none
+    pub fn get_limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Keep the key/value store of `api.kv_*` in a directory, one file per module
+    /// Default: none, the store is kept in memory and lost with the runtime
+    /// Note: read by `WasmRuntime::new`, a relative path resolves against rootdir
+    pub fn set_kv_dir<P: AsRef<Path>>(&mut self, p: P) -> &Self {
+        self.kv_dir = Some(if p.as_ref().is_absolute() { p.as_ref().to_path_buf() } else { self.rootdir.join(p) });
+        self
+    }
+
+    /// Get the key/value store directory
+    /// Default: none
+    pub fn get_kv_dir(&self) -> Option<&Path> {
+        self.kv_dir.as_deref()
+    }
+
+    /// Set the key/value store quota of every module
+    /// Default: 1024 keys, 1 MiB in total and 64 KiB per value
+    pub fn set_kv_quota(&mut self, quota: KvQuota) -> &Self {
+        self.kv_quota = quota;
+        self
+    }
+
+    /// Get the key/value store quota of every module
+    /// Default: see `KvQuota`
+    pub fn get_kv_quota(&self) -> &KvQuota {
+        &self.kv_quota
+    }
+
+    /// Set the names of the secrets modules may read through `api.secret_get`
+    /// Default: none
+    /// Every name must be known to the runtime's `SecretsProvider` when a module runs
+    /// Secret values are refused in the header and redacted from logs and output
+    pub fn set_secrets<S: AsRef<str>>(&mut self, names: &[S]) -> &Self {
+        self.secrets = names.iter().map(|n| n.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Get the names of the secrets modules may read
+    /// Default: none
+    pub fn get_secrets(&self) -> &[String] {
+        &self.secrets
+    }
+
+    /// Set how deep modules may nest `api.call` invocations
+    /// Default: 4, a module called by a module called by a top-level run is at depth 2
+    /// 0 disables `api.call`
+    pub fn set_max_call_depth(&mut self, depth: usize) -> &Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Get how deep modules may nest `api.call` invocations
+    /// Default: 4
+    pub fn get_max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    /// Set the module ids modules may run through `api.call`
+    /// Default: none, every call is denied
+    /// A callee runs with its own configuration, so only list modules the callers may act as
+    pub fn set_callees<S: AsRef<str>>(&mut self, ids: &[S]) -> &Self {
+        self.callees = ids.iter().map(|i| i.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Get the module ids modules may run through `api.call`
+    /// Default: none
+    pub fn get_callees(&self) -> &[String] {
+        &self.callees
+    }
+
+    /// Declare a named pipeline, replacing any pipeline of the same name
+    /// Default: none
+    /// Note: run with `WasmRuntime::run_pipeline`
+    pub fn set_pipeline(&mut self, name: &str, pipeline: Pipeline) -> &Self {
+        self.pipelines.insert(name.to_string(), pipeline);
+        self
+    }
+
+    /// Get a named pipeline
+    pub fn get_pipeline(&self, name: &str) -> Option<&Pipeline> {
+        self.pipelines.get(name)
+    }
+
+    /// Get every named pipeline, ordered by name
+    /// Default: none
+    pub fn get_pipelines(&self) -> &BTreeMap<String, Pipeline> {
+        &self.pipelines
+    }
+
+    /// Run modules deterministically, with virtual clocks and seeded randomness
+    /// Default: none, guests see the host clocks and random sources
+    /// Note: NaN canonicalization is an engine setting, it is only enabled when
+    /// the configuration passed to `WasmRuntime::new` is deterministic. Runs
+    /// that turn this on through an overlay or per-call override fail otherwise
+    pub fn set_deterministic(&mut self, det: Deterministic) -> &Self {
+        self.deterministic = Some(det);
+        self
+    }
+
+    /// Get the deterministic execution settings
+    /// Default: none
+    pub fn get_deterministic(&self) -> Option<&Deterministic> {
+        self.deterministic.as_ref()
+    }
+
+    /// Add a mount
+    /// Default: no mounts
+    /// Mounts are preopened in the order they were added, next to the
+    /// host path / guest path mapping enabled by `set_access`
+    /// Note: a relative mount host path resolves against rootdir
+    pub fn add_mount(&mut self, mut mount: Mount) -> &Self {
+        if !mount.ephemeral && !mount.host_path.is_absolute() {
+            mount.host_path = self.rootdir.join(&mount.host_path);
+        }
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Replace all mounts
+    pub fn set_mounts(&mut self, mounts: Vec<Mount>) -> &Self {
+        self.mounts.clear();
+        for m in mounts {
+            self.add_mount(m);
+        }
+        self
+    }
+
+    /// Get the mounts
+    /// Default: no mounts
+    pub fn get_mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Set extra guest command-line arguments
+    /// Default: none
+    /// The guest always sees the module id as argv[0], followed by these
+    pub fn set_args<S: AsRef<str>>(&mut self, args: &[S]) -> &Self {
+        self.args = args.iter().map(|a| a.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Get extra guest command-line arguments
+    /// Default: none
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Append header `opts` to the guest argv
+    /// Default: false
+    /// e.g. a header `{"opts": ["--fast", "-v"]}` makes the guest see
+    /// `[id, ...args, "--fast", "-v"]`
+    pub fn set_opts_as_argv(&mut self, enable: bool) -> &Self {
+        self.opts_as_argv = enable;
+        self
+    }
+
+    /// Get whether header `opts` are appended to the guest argv
+    /// Default: false
+    pub fn get_opts_as_argv(&self) -> bool {
+        self.opts_as_argv
+    }
+
+    /// Set how guest stdout is turned into the run result
+    /// Default: Json
+    pub fn set_output_mode(&mut self, mode: OutputMode) -> &Self {
+        self.output_mode = mode;
+        self
+    }
+
+    /// Get how guest stdout is turned into the run result
+    /// Default: Json
+    pub fn get_output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    /// Set how the header is encoded at the start of guest stdin
+    /// Default: Json
+    /// Typically set per module with `WasmRuntime::set_module_config`, since
+    /// the guest has to be able to decode it
+    pub fn set_header_format(&mut self, format: HeaderFormat) -> &Self {
+        self.header_format = format;
+        self
+    }
+
+    /// Get how the header is encoded at the start of guest stdin
+    /// Default: Json
+    pub fn get_header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    /// Set how the header and data are laid out on guest stdin
+    /// Default: Line
+    /// Whatever the framing, buffered data is also available through
+    /// `api.data_len` and `api.data_read`
+    pub fn set_framing(&mut self, framing: Framing) -> &Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Get how the header and data are laid out on guest stdin
+    /// Default: Line
+    pub fn get_framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Set a guest environment variable, replacing a previous value
+    /// Default: no variables
+    /// Explicit variables win over inherited ones
+    pub fn set_env<K: AsRef<str>, V: AsRef<str>>(&mut self, key: K, value: V) -> &Self {
+        self.env.retain(|(k, _)| k != key.as_ref());
+        self.env.push((key.as_ref().to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    /// Get guest environment variables
+    /// Default: no variables
+    pub fn get_env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Set the host environment variables guests inherit
+    /// Default: none
+    /// Only variables named here and set on the host are passed through
+    /// Modules with a capability manifest get the variables granted by the
+    /// policy instead, see `set_policy`
+    pub fn set_inherit_env<S: AsRef<str>>(&mut self, names: &[S]) -> &Self {
+        self.inherit_env = names.iter().map(|n| n.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Get the host environment variables guests inherit
+    /// Default: none
+    pub fn get_inherit_env(&self) -> &[String] {
+        &self.inherit_env
+    }
+
+    /// Check that the configuration is usable
+    /// - rootdir must be an absolute path to an existing directory
+    /// - the host path must exist when it is preopened, see `set_access`
+    /// - guest paths must be "." or absolute, without ".." components
+    /// - mount host paths must exist, unless the mount is ephemeral or created on demand
+    /// - no two preopens may share a guest path
+    /// - a network policy requires network access
+    /// - ephemeral mounts have no host path, and only they can collect files
+    pub fn validate(&self) -> Result<()> {
+        if !self.rootdir.is_absolute() {
+            bail!("rootdir {:?} must be an absolute path", self.rootdir);
+        }
+        if !self.rootdir.is_dir() {
+            bail!("rootdir {:?} is not an existing directory", self.rootdir);
+        }
+        if self.access != AccessMode::None && !self.host_path.is_dir() {
+            bail!("host path {:?} is not an existing directory, it is required by access mode {:?}", self.host_path, self.access);
+        }
+        check_guest_path(&self.guest_path).context("invalid guest path")?;
+        if self.network_policy.is_some() && !self.allow_network {
+            bail!("a network policy is set but network access is disabled, enable it with allow_network");
+        }
+
+        let mut guest_paths: Vec<&str> = Vec::new();
+        if self.access != AccessMode::None {
+            guest_paths.push(&self.guest_path);
+        }
+        for mount in &self.mounts {
+            check_guest_path(&mount.guest_path).with_context(|| format!("invalid mount of {:?}", mount.host_path))?;
+            if guest_paths.contains(&mount.guest_path.as_str()) {
+                bail!("guest path {} is preopened twice, every mount needs its own guest path", mount.guest_path);
+            }
+            guest_paths.push(&mount.guest_path);
+
+            if mount.ephemeral {
+                if !mount.host_path.as_os_str().is_empty() {
+                    bail!("ephemeral mount {} must not have a host path, it gets a fresh temporary directory", mount.guest_path);
+                }
+            } else {
+                if !mount.collect.is_empty() {
+                    bail!("mount {} collects files but is not ephemeral, only ephemeral mounts can collect files", mount.guest_path);
+                }
+                if !mount.create && !mount.host_path.is_dir() {
+                    bail!(
+                        "mount host path {:?} for {} is not an existing directory, set create = true to create it",
+                        mount.host_path,
+                        mount.guest_path
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_guest_path(path: &str) -> Result<()> {
+    if path == "." {
+        return Ok(());
+    }
+    if !path.starts_with('/') {
+        bail!("guest path '{path}' must be \".\" or an absolute path such as \"/data\"");
+    }
+    if path.split('/').any(|c| c == "..") {
+        bail!("guest path '{path}' must not contain \"..\"");
+    }
+    Ok(())
 }
@@ -1,5 +1,6 @@
-use crate::cfg::WasmConfig;
+use crate::cfg::{AccessMode, Mount, WasmConfig};
 use std::path::PathBuf;
+use wasmtime_wasi::{DirPerms, FilePerms};
 
 #[test]
 fn wasm_config_defaults_are_sane() {
@@ -36,3 +37,37 @@ fn wasm_config_updates_guest_runtime_flags_and_extension() {
     assert!(cfg.get_allow_write());
     assert!(cfg.get_allow_network());
 }
+
+#[test]
+fn wasm_config_mounts_resolve_relative_paths_and_clamp_read_only() {
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir("/tmp/wasmruntime-root");
+    cfg.add_mount(Mount::new("data", "/data").read_only(true));
+    cfg.add_mount(Mount::new("/srv/out", "/out").create(true).perms(DirPerms::all(), FilePerms::WRITE));
+
+    let mounts = cfg.get_mounts();
+    assert_eq!(mounts.len(), 2);
+    assert_eq!(mounts[0].host_path, PathBuf::from("/tmp/wasmruntime-root/data"));
+    assert_eq!(mounts[0].effective_perms(), (DirPerms::READ, FilePerms::READ));
+    assert_eq!(mounts[1].host_path, PathBuf::from("/srv/out"));
+    assert!(mounts[1].create);
+    assert_eq!(mounts[1].effective_perms(), (DirPerms::all(), FilePerms::WRITE));
+}
+
+#[test]
+fn wasm_config_access_mode_maps_permissions() {
+    let mut cfg = WasmConfig::default();
+    assert_eq!(cfg.get_access(), AccessMode::None);
+    assert_eq!(cfg.get_access().perms(cfg.get_dir_perms(), cfg.get_file_perms()), None);
+
+    cfg.set_access(AccessMode::ReadOnly);
+    assert!(!cfg.get_allow_write());
+    assert_eq!(cfg.get_access().perms(cfg.get_dir_perms(), cfg.get_file_perms()), Some((DirPerms::READ, FilePerms::READ)));
+
+    cfg.set_allow_write(true);
+    assert_eq!(cfg.get_access(), AccessMode::ReadWrite);
+    assert_eq!(cfg.get_access().perms(cfg.get_dir_perms(), cfg.get_file_perms()), Some((DirPerms::all(), FilePerms::all())));
+
+    cfg.set_allow_write(false);
+    assert_eq!(cfg.get_access(), AccessMode::None);
+}
@@ -0,0 +1,279 @@
+use crate::cfg::WasmConfig;
+use crate::overlay::ConfigOverlay;
+use crate::pipeline::Pipeline;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+/// Prefix of the environment variables that override configuration files.
+pub const ENV_PREFIX: &str = "WASMRUNTIME_";
+
+/// Top-level keys of configuration files that are not `ConfigOverlay` fields.
+const FILE_KEYS: &[&str] = &["rootdir", "wasm_ext", "kv_dir", "pipelines"];
+
+/// Settings that are tables keyed by name. Names keep their case when set
+/// from the environment.
+const NAMED_TABLES: &[&str] = &["env", "pipelines"];
+
+/// Top-level settings whose values are strings. Variables setting them, or an
+/// `env` entry, are taken as they are instead of being read as JSON.
+const STRING_KEYS: &[&str] =
+    &["rootdir", "wasm_ext", "kv_dir", "host_path", "guest_path", "dir_perms", "file_perms", "access", "output_mode", "header_format", "framing"];
+
+impl WasmConfig {
+    /// Load a configuration file, `.toml` or `.json`, with overrides from
+    /// `WASMRUNTIME_*` environment variables, and validate it.
+    ///
+    /// The file holds `rootdir`, `wasm_ext`, `kv_dir`, named `pipelines` (see
+    /// `Pipeline`) and any field of `ConfigOverlay`.
+    /// Permissions are written as "all", "none" or a list such as "read,write":
+    ///
+    /// ```toml
+    /// rootdir = "/srv/wasm"
+    /// host_path = "/srv/data"
+    /// guest_path = "/data"
+    /// access = "read_only"
+    /// file_perms = "read"
+    ///
+    /// [limits]
+    /// timeout_ms = 5000
+    ///
+    /// [env]
+    /// LANG = "C.UTF-8"
+    ///
+    /// [[mounts]]
+    /// host_path = "/srv/out"
+    /// guest_path = "/out"
+    /// create = true
+    /// ```
+    ///
+    /// Environment variables override the file: `WASMRUNTIME_ALLOW_NETWORK=true`
+    /// sets `allow_network`, and a double underscore reaches into tables, as in
+    /// `WASMRUNTIME_LIMITS__FUEL=1000000`. Names in `env` and `pipelines` keep
+    /// their case, as in `WASMRUNTIME_ENV__LANG=C`. Values of string settings
+    /// and `env` entries are taken as they are, other values are read as JSON
+    /// when they parse as JSON. Variables that name no setting, such as
+    /// `WASMRUNTIME_CONFIG` or `WASMRUNTIME_LOG`, are ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_file_with_env(path, std::env::vars())
+    }
+
+    /// Like `from_file`, with the environment variables given explicitly.
+    pub fn from_file_with_env<P, I>(path: P, vars: I) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading config file {path:?}"))?;
+        let mut doc: Value = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text).with_context(|| format!("parsing config file {path:?}"))?,
+            Some("json") => serde_json::from_str(&text).with_context(|| format!("parsing config file {path:?}"))?,
+            _ => bail!("unsupported config file {path:?}, expected a .toml or .json file"),
+        };
+
+        let applied = apply_env(&mut doc, vars)?;
+        let cfg = Self::from_value(doc);
+        if applied.is_empty() {
+            cfg.with_context(|| format!("in config file {path:?}"))
+        } else {
+            cfg.with_context(|| format!("in config file {path:?} with overrides from {}", applied.join(", ")))
+        }
+    }
+
+    /// Build and validate a configuration from a parsed document, see `from_file`.
+    pub fn from_value(doc: Value) -> Result<Self> {
+        let Value::Object(mut obj) = doc else {
+            bail!("configuration must be a table of settings");
+        };
+
+        let mut cfg = WasmConfig::default();
+        if let Some(root) = obj.remove("rootdir") {
+            cfg.set_rootdir(serde_json::from_value::<PathBuf>(root).context("invalid rootdir")?);
+        }
+        if let Some(ext) = obj.remove("wasm_ext") {
+            cfg.set_wasm_ext(serde_json::from_value::<String>(ext).context("invalid wasm_ext")?);
+        }
+        if let Some(dir) = obj.remove("kv_dir") {
+            cfg.set_kv_dir(serde_json::from_value::<PathBuf>(dir).context("invalid kv_dir")?);
+        }
+        if let Some(pipelines) = obj.remove("pipelines") {
+            let pipelines: BTreeMap<String, Pipeline> = serde_json::from_value(pipelines).context("invalid pipelines")?;
+            for (name, pipeline) in pipelines {
+                if pipeline.stages.is_empty() {
+                    bail!("pipeline '{name}' has no stages");
+                }
+                cfg.set_pipeline(&name, pipeline);
+            }
+        }
+        let overlay: ConfigOverlay = serde_json::from_value(Value::Object(obj))?;
+        let cfg = overlay.apply(&cfg)?;
+
+        cfg.validate()?;
+        Ok(cfg)
+    }
+}
+
+/// Apply `WASMRUNTIME_*` variables to a parsed configuration document.
+/// Returns the names of the variables that were applied.
+///
+/// Only variables whose first key is a setting of configuration files are
+/// applied, the prefix is shared with unrelated variables.
+fn apply_env<I: IntoIterator<Item = (String, String)>>(doc: &mut Value, vars: I) -> Result<Vec<String>> {
+    let overlay = serde_json::to_value(ConfigOverlay::default())?;
+    let is_setting = |key: &str| FILE_KEYS.contains(&key) || overlay.get(key).is_some();
+
+    let mut applied = Vec::new();
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let top = key.split("__").next().unwrap_or_default().to_ascii_lowercase();
+        if !is_setting(&top) {
+            continue;
+        }
+        let named = NAMED_TABLES.contains(&top.as_str());
+        let path: Vec<String> =
+            key.split("__").enumerate().map(|(i, part)| if named && i == 1 { part.to_string() } else { part.to_ascii_lowercase() }).collect();
+        let value = match path.as_slice() {
+            [key] if STRING_KEYS.contains(&key.as_str()) => Value::String(raw),
+            [table, _] if table == "env" => Value::String(raw),
+            _ => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+        };
+
+        let mut node = &mut *doc;
+        let mut parts = path.into_iter().peekable();
+        while let Some(part) = parts.next() {
+            let Value::Object(obj) = node else {
+                bail!("{name} overrides a setting that is not a table");
+            };
+            if parts.peek().is_none() {
+                obj.insert(part, value);
+                break;
+            }
+            node = obj.entry(part).or_insert_with(|| Value::Object(Map::new()));
+        }
+        applied.push(name);
+    }
+    Ok(applied)
+}
+
+/// Textual form of WASI permission flags: "all", "none", or a comma-separated
+/// list of flag names, e.g. "read" or "read,write".
+pub trait PermsText: Sized + Copy + 'static {
+    const FLAGS: &'static [(&'static str, Self)];
+
+    fn all() -> Self;
+    fn empty() -> Self;
+    fn contains(&self, other: Self) -> bool;
+    fn union(self, other: Self) -> Self;
+
+    /// Parse the textual form.
+    fn from_text(text: &str) -> Result<Self> {
+        match text.trim() {
+            "all" => return Ok(Self::all()),
+            "none" | "" => return Ok(Self::empty()),
+            _ => {}
+        }
+        text.split(',').map(str::trim).try_fold(Self::empty(), |acc, name| match Self::FLAGS.iter().find(|(n, _)| *n == name) {
+            Some((_, flag)) => Ok(acc.union(*flag)),
+            None => {
+                let known: Vec<&str> = Self::FLAGS.iter().map(|(n, _)| *n).collect();
+                bail!("unknown permission '{name}', expected \"all\", \"none\" or a list of {}", known.join(", "))
+            }
+        })
+    }
+
+    /// Format the textual form.
+    fn to_text(&self) -> String {
+        if self.contains(Self::all()) {
+            return "all".to_string();
+        }
+        let names: Vec<&str> = Self::FLAGS.iter().filter(|(_, f)| self.contains(*f)).map(|(n, _)| *n).collect();
+        if names.is_empty() { "none".to_string() } else { names.join(",") }
+    }
+}
+
+impl PermsText for DirPerms {
+    const FLAGS: &'static [(&'static str, Self)] = &[("read", DirPerms::READ), ("mutate", DirPerms::MUTATE)];
+
+    fn all() -> Self {
+        DirPerms::all()
+    }
+    fn empty() -> Self {
+        DirPerms::empty()
+    }
+    fn contains(&self, other: Self) -> bool {
+        DirPerms::contains(self, other)
+    }
+    fn union(self, other: Self) -> Self {
+        self | other
+    }
+}
+
+impl PermsText for FilePerms {
+    const FLAGS: &'static [(&'static str, Self)] = &[("read", FilePerms::READ), ("write", FilePerms::WRITE)];
+
+    fn all() -> Self {
+        FilePerms::all()
+    }
+    fn empty() -> Self {
+        FilePerms::empty()
+    }
+    fn contains(&self, other: Self) -> bool {
+        FilePerms::contains(self, other)
+    }
+    fn union(self, other: Self) -> Self {
+        self | other
+    }
+}
+
+/// Serde helpers for permissions in their textual form, for `#[serde(with = ...)]`.
+pub mod perms {
+    use super::*;
+    use serde::de::Error as _;
+
+    pub fn serialize<T: PermsText, S: Serializer>(perms: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&perms.to_text())
+    }
+
+    pub fn deserialize<'de, T: PermsText, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+        T::from_text(&String::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+/// Like `perms`, for optional permissions.
+pub mod opt_perms {
+    use super::*;
+    use serde::de::Error as _;
+
+    pub fn serialize<T: PermsText, S: Serializer>(perms: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+        match perms {
+            Some(p) => s.serialize_some(&p.to_text()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T: PermsText, D: Deserializer<'de>>(d: D) -> Result<Option<T>, D::Error> {
+        Option::<String>::deserialize(d)?.map(|text| T::from_text(&text).map_err(D::Error::custom)).transpose()
+    }
+}
+
+/// Serde helpers for optional environment variables, written as a table.
+pub mod opt_env {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(env: &Option<Vec<(String, String)>>, s: S) -> Result<S::Ok, S::Error> {
+        match env {
+            Some(vars) => s.collect_map(vars.iter().map(|(k, v)| (k, v))),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<(String, String)>>, D::Error> {
+        Ok(Option::<BTreeMap<String, String>>::deserialize(d)?.map(|vars| vars.into_iter().collect()))
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    cfg::{AccessMode, Limits, Mount, OutputMode, WasmConfig},
+    cfgfile::PermsText,
+    codec::HeaderFormat,
+};
+use std::{fs, path::Path};
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+fn no_env() -> Vec<(String, String)> {
+    Vec::new()
+}
+
+fn write_toml(dir: &Path, body: &str) -> std::path::PathBuf {
+    let path = dir.join("wasmruntime.toml");
+    fs::write(&path, format!("rootdir = {:?}\n{body}", dir.to_str().expect("utf-8 tempdir"))).expect("write config");
+    path
+}
+
+#[test]
+fn perms_text_round_trip() {
+    assert_eq!(DirPerms::from_text("all").unwrap(), DirPerms::all());
+    assert_eq!(DirPerms::from_text("none").unwrap(), DirPerms::empty());
+    assert_eq!(FilePerms::from_text("read").unwrap(), FilePerms::READ);
+    assert_eq!(FilePerms::from_text(" read , write ").unwrap(), FilePerms::all());
+
+    assert_eq!(FilePerms::READ.to_text(), "read");
+    assert_eq!(DirPerms::all().to_text(), "all");
+    assert_eq!(DirPerms::empty().to_text(), "none");
+
+    let err = FilePerms::from_text("read,mutate").expect_err("mutate is a directory permission");
+    assert!(err.to_string().contains("unknown permission 'mutate'"), "unexpected message: {err}");
+}
+
+#[test]
+fn config_loads_from_toml() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::create_dir(root.path().join("data")).expect("create data dir");
+    let path = write_toml(
+        root.path(),
+        r#"
+host_path = "data"
+guest_path = "/data"
+access = "read_only"
+file_perms = "read"
+output_mode = "raw"
+header_format = "msgpack"
+
+[limits]
+timeout_ms = 5000
+
+[env]
+LANG = "C.UTF-8"
+
+[[mounts]]
+guest_path = "/scratch"
+ephemeral = true
+
+[[mounts]]
+host_path = "out"
+guest_path = "/out"
+dir_perms = "read"
+create = true
+"#,
+    );
+
+    let cfg = WasmConfig::from_file_with_env(&path, no_env()).expect("config should load");
+    assert_eq!(cfg.get_root_path(), root.path());
+    assert_eq!(cfg.get_host_path(), root.path().join("data"));
+    assert_eq!(cfg.get_guest_path(), "/data");
+    assert_eq!(cfg.get_access(), AccessMode::ReadOnly);
+    assert_eq!(cfg.get_file_perms(), FilePerms::READ);
+    assert_eq!(cfg.get_dir_perms(), DirPerms::all());
+    assert_eq!(cfg.get_output_mode(), OutputMode::Raw);
+    assert_eq!(cfg.get_header_format(), HeaderFormat::MessagePack);
+    assert_eq!(*cfg.get_limits(), Limits { timeout_ms: Some(5000), ..Default::default() });
+    assert_eq!(cfg.get_env(), [("LANG".to_string(), "C.UTF-8".to_string())]);
+
+    let out = Mount::new(root.path().join("out"), "/out").perms(DirPerms::READ, FilePerms::all()).create(true);
+    assert_eq!(cfg.get_mounts(), [Mount::ephemeral("/scratch"), out]);
+}
+
+#[test]
+fn config_loads_from_json() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let path = root.path().join("wasmruntime.json");
+    let doc = serde_json::json!({ "rootdir": root.path(), "wasm_ext": "wat", "allow_network": true, "args": ["-v"] });
+    fs::write(&path, doc.to_string()).expect("write config");
+
+    let cfg = WasmConfig::from_file_with_env(&path, no_env()).expect("config should load");
+    assert_eq!(cfg.get_wasm_ext(), "wat");
+    assert!(cfg.get_allow_network());
+    assert_eq!(cfg.get_args(), ["-v"]);
+}
+
+#[test]
+fn env_overrides_file() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let path = write_toml(root.path(), "allow_network = false\n\n[limits]\nfuel = 10\ntimeout_ms = 100\n");
+    let vars = vec![
+        ("WASMRUNTIME_LIMITS__FUEL".to_string(), "1000000".to_string()),
+        ("WASMRUNTIME_ALLOW_NETWORK".to_string(), "true".to_string()),
+        ("WASMRUNTIME_GUEST_PATH".to_string(), "/work".to_string()),
+        ("OTHER_FUEL".to_string(), "1".to_string()),
+    ];
+
+    let cfg = WasmConfig::from_file_with_env(&path, vars).expect("config should load");
+    assert!(cfg.get_allow_network());
+    assert_eq!(cfg.get_guest_path(), "/work");
+    assert_eq!(*cfg.get_limits(), Limits { fuel: Some(1_000_000), memory: None, timeout_ms: Some(100) });
+
+    let bad = vec![("WASMRUNTIME_LIMITS__GAS".to_string(), "1".to_string())];
+    let err = WasmConfig::from_file_with_env(&path, bad).expect_err("unknown limit should be rejected");
+    assert!(format!("{err:#}").contains("WASMRUNTIME_LIMITS__GAS"), "unexpected message: {err:#}");
+}
+
+#[test]
+fn env_keeps_the_case_of_names() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let path = write_toml(root.path(), "[env]\nHOME = \"/\"\n");
+    let vars = vec![
+        ("WASMRUNTIME_ENV__LANG".to_string(), "C".to_string()),
+        ("WASMRUNTIME_PIPELINES__Wrap__STAGES".to_string(), r#"[{"module": "echo"}]"#.to_string()),
+    ];
+
+    let cfg = WasmConfig::from_file_with_env(&path, vars).expect("config should load");
+    let mut env = cfg.get_env().to_vec();
+    env.sort();
+    assert_eq!(env, [("HOME".to_string(), "/".to_string()), ("LANG".to_string(), "C".to_string())]);
+    assert_eq!(cfg.get_pipeline("Wrap").map(|p| p.stages.len()), Some(1));
+}
+
+#[test]
+fn env_keeps_strings_for_string_settings() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let path = write_toml(root.path(), "");
+    let vars = vec![
+        ("WASMRUNTIME_ENV__PORT".to_string(), "8080".to_string()),
+        ("WASMRUNTIME_ENV__DEBUG".to_string(), "true".to_string()),
+        ("WASMRUNTIME_WASM_EXT".to_string(), "2".to_string()),
+        ("WASMRUNTIME_ARGS".to_string(), r#"["-v"]"#.to_string()),
+        ("WASMRUNTIME_LIMITS__FUEL".to_string(), "10".to_string()),
+    ];
+
+    let cfg = WasmConfig::from_file_with_env(&path, vars).expect("config should load");
+    let mut env = cfg.get_env().to_vec();
+    env.sort();
+    assert_eq!(env, [("DEBUG".to_string(), "true".to_string()), ("PORT".to_string(), "8080".to_string())]);
+    assert_eq!(cfg.get_wasm_ext(), "2");
+    assert_eq!(cfg.get_args(), ["-v"]);
+    assert_eq!(cfg.get_limits().fuel, Some(10));
+}
+
+#[test]
+fn env_ignores_unrelated_variables() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let path = write_toml(
+        root.path(),
+        "allow_network = false
+",
+    );
+    let kv = root.path().join("kv");
+    let vars = vec![
+        ("WASMRUNTIME_CONFIG".to_string(), path.to_str().expect("utf-8 path").to_string()),
+        ("WASMRUNTIME_LOG".to_string(), "debug".to_string()),
+        ("WASMRUNTIME_LOG__LEVEL".to_string(), "debug".to_string()),
+        ("WASMRUNTIME_KV_DIR".to_string(), kv.to_str().expect("utf-8 path").to_string()),
+    ];
+
+    let cfg = WasmConfig::from_file_with_env(&path, vars).expect("unrelated variables should be ignored");
+    assert!(!cfg.get_allow_network());
+    assert_eq!(cfg.get_kv_dir(), Some(kv.as_path()));
+}
+
+#[test]
+fn config_rejects_bad_settings() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let load = |body: &str| {
+        let path = write_toml(root.path(), body);
+        format!("{:#}", WasmConfig::from_file_with_env(&path, no_env()).expect_err("config should be rejected"))
+    };
+
+    assert!(load("allow_netwrok = true").contains("allow_netwrok"));
+    assert!(load("file_perms = \"exec\"").contains("unknown permission 'exec'"));
+    assert!(load("host_path = \"missing\"\naccess = \"read_write\"").contains("is not an existing directory"));
+    assert!(load("guest_path = \"data\"").contains("must be \".\" or an absolute path"));
+    assert!(load("guest_path = \"/data/../etc\"").contains("must not contain \"..\""));
+    assert!(load("[[mounts]]\nhost_path = \"missing\"\nguest_path = \"/m\"").contains("set create = true"));
+
+    let path = root.path().join("relative.toml");
+    fs::write(&path, "rootdir = \"modules\"").expect("write config");
+    let err = WasmConfig::from_file_with_env(&path, no_env()).expect_err("relative rootdir should be rejected");
+    assert!(format!("{err:#}").contains("must be an absolute path"), "unexpected message: {err:#}");
+
+    assert!(WasmConfig::from_file_with_env(root.path().join("config.yaml"), no_env()).is_err());
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Encoding of the header at the start of guest stdin.
+///
+/// - `Json`: one line of JSON terminated by `\n`, the original contract
+/// - `MessagePack`: a single MessagePack map, with no terminator
+/// - `Cbor`: a single CBOR map, with no terminator
+///
+/// MessagePack and CBOR values are self-delimiting, so the data starts right
+/// after the header value. Guests can ask which format is in use through the
+/// `api.header_format` import, which returns `HeaderFormat::code`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderFormat {
+    #[default]
+    Json,
+    #[serde(alias = "msgpack")]
+    MessagePack,
+    Cbor,
+}
+
+impl HeaderFormat {
+    /// Numeric code returned to guests by `api.header_format`.
+    pub fn code(&self) -> i32 {
+        match self {
+            HeaderFormat::Json => 0,
+            HeaderFormat::MessagePack => 1,
+            HeaderFormat::Cbor => 2,
+        }
+    }
+
+    /// Encode a header, including the trailing newline for JSON.
+    pub fn encode(&self, header: &Value) -> Result<Vec<u8>> {
+        let mut buf = self.encode_value(header)?;
+        if *self == HeaderFormat::Json {
+            buf.push(b'\n');
+        }
+        Ok(buf)
+    }
+
+    /// Encode a header without any terminator.
+    fn encode_value(&self, header: &Value) -> Result<Vec<u8>> {
+        match self {
+            HeaderFormat::Json => Ok(serde_json::to_vec(header)?),
+            HeaderFormat::MessagePack => rmp_serde::to_vec_named(header).context("encoding header as MessagePack"),
+            HeaderFormat::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(header, &mut buf).context("encoding header as CBOR")?;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// Magic bytes that open a length-prefixed stdin frame.
+pub const FRAME_MAGIC: &[u8; 4] = b"WRF1";
+
+/// Data length written when the data section is streamed and its size is not
+/// known up front: the data then runs until the end of stdin.
+pub const FRAME_UNKNOWN_LEN: u64 = u64::MAX;
+
+/// How the header and data are laid out on guest stdin.
+///
+/// - `Line`: the encoded header, then the data. A JSON header ends with `\n`.
+/// - `LengthPrefixed`: `FRAME_MAGIC`, the header length as a little-endian
+///   `u32`, the encoded header, the data length as a little-endian `u64`, then
+///   the data. A JSON header has no trailing newline in this mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    #[default]
+    Line,
+    LengthPrefixed,
+}
+
+impl Framing {
+    /// Build everything that goes on stdin before the data.
+    ///
+    /// `data_len` is `None` when the data is streamed, see `FRAME_UNKNOWN_LEN`.
+    pub fn prefix(&self, format: HeaderFormat, header: &Value, data_len: Option<u64>) -> Result<Vec<u8>> {
+        match self {
+            Framing::Line => format.encode(header),
+            Framing::LengthPrefixed => {
+                let encoded = format.encode_value(header)?;
+                let header_len = u32::try_from(encoded.len()).context("header is too large for a length-prefixed frame")?;
+
+                let mut buf = Vec::with_capacity(FRAME_MAGIC.len() + 12 + encoded.len());
+                buf.extend_from_slice(FRAME_MAGIC);
+                buf.extend_from_slice(&header_len.to_le_bytes());
+                buf.extend_from_slice(&encoded);
+                buf.extend_from_slice(&data_len.unwrap_or(FRAME_UNKNOWN_LEN).to_le_bytes());
+                Ok(buf)
+            }
+        }
+    }
+}
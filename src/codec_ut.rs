@@ -0,0 +1,134 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    codec::{FRAME_MAGIC, FRAME_UNKNOWN_LEN, Framing, HeaderFormat},
+    overlay::{ConfigOverlay, RunOptions},
+};
+use serde_json::{Value, json};
+use std::fs;
+
+/// Prints the `api.header_format` code as a digit, then echoes stdin.
+static FORMAT_WAT: &str = r##"
+(module
+  (import "api" "header_format" (func $header_format (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.store8 (i32.const 128) (i32.add (i32.const 48) (call $header_format)))
+    (i32.store (i32.const 0) (i32.const 128))
+    (i32.store (i32.const 4) (i32.const 1))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (i32.const 4096))
+    (i32.store (i32.const 32) (i32.const 1024))
+    (block $eof
+      (loop $copy
+        (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+        (br_if $eof (i32.eqz (i32.load (i32.const 16))))
+        (i32.store (i32.const 36) (i32.load (i32.const 16)))
+        (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 20)))
+        (br $copy)))))
+"##;
+
+/// Copies the payload through `api.data_read` in 3-byte chunks, then echoes
+/// the first 4 bytes of stdin. Prints "-" when `api.data_len` is not 6.
+static DATA_WAT: &str = r##"
+(module
+  (import "api" "data_len" (func $data_len (result i64)))
+  (import "api" "data_read" (func $data_read (param i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 96) "-")
+  (func $print (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+  (func (export "_start")
+    (local $off i32)
+    (local $n i32)
+    (if (i64.ne (call $data_len) (i64.const 6))
+      (then (call $print (i32.const 96) (i32.const 1))))
+    (block $done
+      (loop $copy
+        (local.set $n (call $data_read (i64.extend_i32_u (local.get $off)) (i32.add (i32.const 1024) (local.get $off)) (i32.const 3)))
+        (br_if $done (i32.le_s (local.get $n) (i32.const 0)))
+        (local.set $off (i32.add (local.get $off) (local.get $n)))
+        (br $copy)))
+    (call $print (i32.const 1024) (local.get $off))
+    (i32.store (i32.const 16) (i32.const 2048))
+    (i32.store (i32.const 20) (i32.const 4))
+    (drop (call $fd_read (i32.const 0) (i32.const 16) (i32.const 1) (i32.const 24)))
+    (call $print (i32.const 2048) (i32.load (i32.const 24)))))
+"##;
+
+#[test]
+fn header_codecs_round_trip() {
+    let header = json!({ "opts": ["--fast"], "args": { "name": "world", "n": 3 } });
+
+    let encoded = HeaderFormat::Json.encode(&header).expect("json");
+    assert_eq!(encoded.last(), Some(&b'\n'));
+    assert_eq!(serde_json::from_slice::<Value>(&encoded).expect("decode json"), header);
+
+    let encoded = HeaderFormat::MessagePack.encode(&header).expect("msgpack");
+    assert_eq!(rmp_serde::from_slice::<Value>(&encoded).expect("decode msgpack"), header);
+
+    let encoded = HeaderFormat::Cbor.encode(&header).expect("cbor");
+    assert_eq!(ciborium::from_reader::<Value, _>(encoded.as_slice()).expect("decode cbor"), header);
+}
+
+#[tokio::test]
+async fn runtime_encodes_header_per_module() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("format.wat"), FORMAT_WAT).expect("write format.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let header = json!({ "args": { "k": "v" } });
+
+    let out = rt.run_output("format", header.clone(), b"DATA".to_vec(), &RunOptions::default()).await.expect("module should run");
+    assert_eq!(out.bytes(), [b"0".as_slice(), HeaderFormat::Json.encode(&header).expect("json").as_slice(), b"DATA".as_slice()].concat());
+
+    rt.set_module_config("format", ConfigOverlay { header_format: Some(HeaderFormat::MessagePack), ..Default::default() });
+    let out = rt.run_output("format", header.clone(), b"DATA".to_vec(), &RunOptions::default()).await.expect("module should run");
+    assert_eq!(out.bytes(), [b"1".as_slice(), rmp_serde::to_vec_named(&header).expect("msgpack").as_slice(), b"DATA".as_slice()].concat());
+
+    let opts = RunOptions::with_config(ConfigOverlay { header_format: Some(HeaderFormat::Cbor), ..Default::default() });
+    let out = rt.run_output("format", header.clone(), b"DATA".to_vec(), &opts).await.expect("module should run");
+    assert_eq!(out.bytes()[0], b'2');
+    assert_eq!(ciborium::from_reader::<Value, _>(&out.bytes()[1..]).expect("decode cbor"), header);
+    assert!(out.bytes().ends_with(b"DATA"));
+}
+
+#[test]
+fn length_prefixed_frame_layout() {
+    let header = json!({ "a": 1 });
+    assert_eq!(Framing::Line.prefix(HeaderFormat::Json, &header, Some(3)).expect("line"), b"{\"a\":1}\n");
+
+    let frame = Framing::LengthPrefixed.prefix(HeaderFormat::Json, &header, Some(3)).expect("frame");
+    assert_eq!(&frame[..4], FRAME_MAGIC);
+    assert_eq!(&frame[4..8], 7u32.to_le_bytes());
+    assert_eq!(&frame[8..15], b"{\"a\":1}");
+    assert_eq!(&frame[15..], 3u64.to_le_bytes());
+
+    let frame = Framing::LengthPrefixed.prefix(HeaderFormat::MessagePack, &header, None).expect("frame");
+    let header_len = u32::from_le_bytes(frame[4..8].try_into().expect("u32")) as usize;
+    assert_eq!(rmp_serde::from_slice::<Value>(&frame[8..8 + header_len]).expect("decode msgpack"), header);
+    assert_eq!(&frame[8 + header_len..], FRAME_UNKNOWN_LEN.to_le_bytes());
+}
+
+#[tokio::test]
+async fn runtime_serves_data_through_api_and_framed_stdin() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("data.wat"), DATA_WAT).expect("write data.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_framing(Framing::LengthPrefixed);
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run_output("data", json!({}), b"abcdef".to_vec(), &RunOptions::default()).await.expect("module should run");
+    assert_eq!(out.bytes(), b"abcdefWRF1");
+}
@@ -0,0 +1,117 @@
+use cap_rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+/// Settings for deterministic guest execution.
+///
+/// Replaces the host clocks and random sources of a run with virtual ones, so
+/// that the same module, header and data give byte-identical output:
+///
+/// - the wall clock starts at `epoch` (time since the Unix epoch)
+/// - the monotonic clock starts at zero
+/// - both clocks advance by `tick` every time the guest reads them, a zero
+///   tick gives fixed clocks
+/// - secure and insecure random bytes, and the insecure random seed, are all
+///   derived from `seed`
+///
+/// Floating point NaN bit patterns are canonicalized by the engine when the
+/// runtime configuration is deterministic, see `WasmConfig::set_deterministic`.
+///
+/// ```
+/// use std::time::Duration;
+/// use wasmruntime::deterministic::Deterministic;
+///
+/// // 2024-01-01T00:00:00Z, one millisecond per clock read
+/// let det = Deterministic::new(42).epoch(Duration::from_secs(1_704_067_200)).tick(Duration::from_millis(1));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Deterministic {
+    pub seed: u64,
+    pub epoch: Duration,
+    pub tick: Duration,
+}
+
+impl Deterministic {
+    /// Create deterministic settings with fixed clocks at the Unix epoch.
+    pub fn new(seed: u64) -> Self {
+        Self { seed, ..Default::default() }
+    }
+
+    /// Set the wall clock start time, as time since the Unix epoch.
+    pub fn epoch(mut self, epoch: Duration) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Set how far the clocks advance per read.
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Install virtual clocks and seeded random sources on a WASI context builder.
+    ///
+    /// Returns the virtual wall clock the guest sees, for timestamping host log
+    /// lines of the run.
+    pub fn apply(&self, wb: &mut WasiCtxBuilder) -> WallClock {
+        let tick = self.tick.as_nanos() as u64;
+        let wall = WallClock(Arc::new(VirtualClock::new(self.epoch.as_nanos() as u64, tick)));
+        wb.wall_clock(wall.clone())
+            .monotonic_clock(VirtualClock::new(0, tick))
+            .secure_random(StdRng::seed_from_u64(self.seed))
+            .insecure_random(StdRng::seed_from_u64(self.seed.rotate_left(32)))
+            .insecure_random_seed(u128::from(self.seed));
+        wall
+    }
+}
+
+/// The virtual wall clock of a deterministic run, shared between the guest
+/// and the host.
+#[derive(Clone)]
+pub struct WallClock(Arc<VirtualClock>);
+
+impl WallClock {
+    /// Current time since the Unix epoch. Unlike a guest read, this does not
+    /// advance the clock.
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.now.load(Ordering::Relaxed))
+    }
+}
+
+/// A clock that advances by a fixed step on every read.
+struct VirtualClock {
+    now: AtomicU64,
+    tick: u64,
+}
+
+impl VirtualClock {
+    fn new(start: u64, tick: u64) -> Self {
+        Self { now: AtomicU64::new(start), tick }
+    }
+
+    fn read(&self) -> u64 {
+        self.now.fetch_add(self.tick, Ordering::Relaxed)
+    }
+}
+
+impl HostWallClock for WallClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(self.0.tick.max(1))
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.read())
+    }
+}
+
+impl HostMonotonicClock for VirtualClock {
+    fn resolution(&self) -> u64 {
+        self.tick.max(1)
+    }
+
+    fn now(&self) -> u64 {
+        self.read()
+    }
+}
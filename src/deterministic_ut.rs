@@ -0,0 +1,139 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    deterministic::Deterministic,
+    overlay::{ConfigOverlay, RunOptions},
+};
+use serde_json::json;
+use std::{collections::HashMap, fs, time::Duration};
+
+/// Prints, hex encoded: the realtime clock, the monotonic clock, 16 random
+/// bytes and the monotonic clock again.
+static CLOCKS_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 512) "0123456789abcdef")
+  (func (export "_start")
+    (local $i i32)
+    (local $b i32)
+    (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 64)))
+    (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 72)))
+    (drop (call $random_get (i32.const 80) (i32.const 16)))
+    (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 96)))
+    (block $done
+      (loop $hex
+        (br_if $done (i32.eq (local.get $i) (i32.const 40)))
+        (local.set $b (i32.load8_u (i32.add (i32.const 64) (local.get $i))))
+        (i32.store8 (i32.add (i32.const 256) (i32.shl (local.get $i) (i32.const 1)))
+          (i32.load8_u (i32.add (i32.const 512) (i32.shr_u (local.get $b) (i32.const 4)))))
+        (i32.store8 (i32.add (i32.const 257) (i32.shl (local.get $i) (i32.const 1)))
+          (i32.load8_u (i32.add (i32.const 512) (i32.and (local.get $b) (i32.const 15)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $hex)))
+    (i32.store (i32.const 0) (i32.const 256))
+    (i32.store (i32.const 4) (i32.const 80))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"##;
+
+/// Logs a line through `api.log`.
+static LOGGER_WAT: &str = r#"
+(module
+  (import "api" "log" (func $log (param i32 i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "hello")
+  (func (export "_start")
+    (call $log (i32.const 1) (i32.const 100) (i32.const 5))))
+"#;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+async fn run_clocks(det: Option<Deterministic>) -> String {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("clocks.wat"), CLOCKS_WAT).expect("write clocks.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    if let Some(det) = det {
+        cfg.set_deterministic(det);
+    }
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run("clocks", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    out["data"].as_str().expect("hex output").to_string()
+}
+
+#[tokio::test]
+async fn deterministic_runs_are_byte_identical() {
+    let det = Deterministic::new(7).epoch(Duration::from_secs(1_704_067_200)).tick(Duration::from_millis(1));
+
+    let first = run_clocks(Some(det)).await;
+    let second = run_clocks(Some(det)).await;
+    assert_eq!(first, second);
+
+    let epoch_ns = 1_704_067_200_000_000_000u64;
+    assert_eq!(&first[..16], hex(&epoch_ns.to_le_bytes()));
+    assert_eq!(&first[16..32], hex(&0u64.to_le_bytes()));
+    assert_eq!(&first[64..], hex(&1_000_000u64.to_le_bytes()));
+}
+
+#[tokio::test]
+async fn deterministic_fixed_clocks_and_seeded_random() {
+    let fixed = run_clocks(Some(Deterministic::new(1))).await;
+    assert_eq!(&fixed[..32], "0".repeat(32));
+    assert_eq!(&fixed[64..], "0".repeat(16));
+
+    let reseeded = run_clocks(Some(Deterministic::new(2))).await;
+    assert_ne!(fixed[32..64], reseeded[32..64]);
+}
+
+#[tokio::test]
+async fn non_deterministic_runs_use_host_sources() {
+    let first = run_clocks(None).await;
+    let second = run_clocks(None).await;
+    assert_ne!(first[32..64], second[32..64]);
+    assert_ne!(&first[..16], "0".repeat(16));
+}
+
+#[test]
+fn deterministic_overlay_applies() {
+    let overlay = ConfigOverlay { deterministic: Some(Deterministic::new(3)), ..Default::default() };
+    let cfg = overlay.apply(&WasmConfig::default()).expect("overlay should apply");
+    assert_eq!(cfg.get_deterministic(), Some(&Deterministic::new(3)));
+}
+
+#[tokio::test]
+async fn deterministic_log_lines_use_the_virtual_clock() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("logger.wat"), LOGGER_WAT).expect("write logger.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    // 2024-01-01T00:00:00Z
+    cfg.set_deterministic(Deterministic::new(1).epoch(Duration::from_secs(1_704_067_200)).tick(Duration::from_secs(1)));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run("logger", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["__module-logs"], json!(["[01/01/2024 00:00:00] - INFO: [logger] hello"]));
+}
+
+#[tokio::test]
+async fn deterministic_overrides_need_a_deterministic_runtime() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("clocks.wat"), CLOCKS_WAT).expect("write clocks.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let opts = RunOptions { config: ConfigOverlay { deterministic: Some(Deterministic::new(3)), ..Default::default() } };
+    let err = rt.run_with_options("clocks", json!({}), Vec::new(), &opts).await.expect_err("per-call deterministic should be rejected");
+    assert!(format!("{err:#}").contains("NaN canonicalization"), "unexpected error: {err:#}");
+
+    rt.set_module_config("clocks", opts.config);
+    let err = rt.run("clocks", Vec::new(), HashMap::new(), Vec::new()).await.expect_err("deterministic overlay should be rejected");
+    assert!(format!("{err:#}").contains("NaN canonicalization"), "unexpected error: {err:#}");
+}
@@ -0,0 +1,228 @@
+use anyhow::{Context, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use reqwest::{Method, Url, redirect};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Policy for outbound HTTP calls, made by modules through `api.http_fetch`
+/// and by HTTP handler components through `wasi:http/outgoing-handler`.
+///
+/// A call is permitted when its scheme is http or https, its host matches one
+/// of the allowed hosts and its method is allowed. Hosts are written as
+/// "api.example.com", "*.example.com" for any subdomain, or "localhost:8080"
+/// to also pin the port. No host is allowed by default.
+///
+/// `max_body_bytes` caps request and response bodies of `api.http_fetch`;
+/// components stream their bodies and only get the host, method and timeout
+/// checks.
+///
+/// ```
+/// use wasmruntime::fetch::HttpPolicy;
+///
+/// let policy = HttpPolicy::new().allow_host("api.example.com").allow_method("POST");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpPolicy {
+    pub allow_hosts: Vec<String>,
+    /// Upper-case method names. Default: GET and HEAD.
+    pub allow_methods: Vec<String>,
+    /// Default: 1 MiB.
+    pub max_body_bytes: usize,
+    /// Deadline of a whole call. Default: 10 seconds.
+    pub timeout_ms: u64,
+}
+
+impl Default for HttpPolicy {
+    fn default() -> Self {
+        Self { allow_hosts: Vec::new(), allow_methods: vec!["GET".into(), "HEAD".into()], max_body_bytes: 1024 * 1024, timeout_ms: 10_000 }
+    }
+}
+
+impl HttpPolicy {
+    /// Create a policy that allows GET and HEAD, to no host.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a host pattern, see the type docs.
+    pub fn allow_host(mut self, host: &str) -> Self {
+        self.allow_hosts.push(host.to_ascii_lowercase());
+        self
+    }
+
+    /// Allow a method in addition to the current ones.
+    pub fn allow_method(mut self, method: &str) -> Self {
+        self.allow_methods.push(method.to_ascii_uppercase());
+        self
+    }
+
+    /// Set the body size cap.
+    pub fn max_body_bytes(mut self, max: usize) -> Self {
+        self.max_body_bytes = max;
+        self
+    }
+
+    /// Set the call deadline.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = timeout.as_millis().try_into().unwrap_or(u64::MAX);
+        self
+    }
+
+    /// Get the call deadline.
+    pub fn get_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Check a method against the allowed methods.
+    pub fn check_method(&self, method: &str) -> Result<()> {
+        if self.allow_methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+            Ok(())
+        } else {
+            bail!("method {method} is not allowed, allowed methods: {}", self.allow_methods.join(", "))
+        }
+    }
+
+    /// Check a URL against the allowed schemes and hosts.
+    pub fn check_url(&self, url: &Url) -> Result<()> {
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("scheme '{}' is not allowed, use http or https", url.scheme());
+        }
+        let Some(host) = url.host_str() else {
+            bail!("URL {url} has no host");
+        };
+        let host = host.to_ascii_lowercase();
+        let port = url.port_or_known_default();
+
+        let allowed = self.allow_hosts.iter().any(|pattern| {
+            // A port follows the last colon, unless that colon is part of a bare IPv6 address.
+            let (name, pinned) = match pattern.rsplit_once(':') {
+                Some((name, p)) if name.ends_with(']') || !name.contains(':') => match p.parse::<u16>() {
+                    Ok(p) => (name, Some(p)),
+                    Err(_) => return false,
+                },
+                _ => (pattern.as_str(), None),
+            };
+            let name_ok = match name.strip_prefix("*.") {
+                Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => host == name,
+            };
+            name_ok && pinned.is_none_or(|p| Some(p) == port)
+        });
+        if !allowed {
+            bail!("host {host} is not in the allowed hosts");
+        }
+        Ok(())
+    }
+}
+
+/// An `api.http_fetch` request.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchRequest {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Text body.
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Binary body, base64 encoded.
+    #[serde(default)]
+    pub body_base64: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// Perform an `api.http_fetch` request under `policy`.
+///
+/// Returns `{"status": 200, "headers": {...}, "body": "..."}`, with
+/// `body_base64` instead of `body` when the response is not UTF-8. Header
+/// names are lower case, repeated headers are joined with ", ". Redirects are
+/// followed only to allowed hosts.
+pub async fn fetch(policy: &HttpPolicy, req: FetchRequest) -> Result<Value> {
+    let url = Url::parse(&req.url).with_context(|| format!("invalid URL '{}'", req.url))?;
+    policy.check_url(&url)?;
+    policy.check_method(&req.method)?;
+    let method = Method::from_bytes(req.method.to_ascii_uppercase().as_bytes()).with_context(|| format!("invalid method '{}'", req.method))?;
+
+    let body = match (req.body, req.body_base64) {
+        (Some(_), Some(_)) => bail!("set either body or body_base64, not both"),
+        (Some(text), None) => text.into_bytes(),
+        (None, Some(b64)) => BASE64.decode(b64).context("invalid body_base64")?,
+        (None, None) => Vec::new(),
+    };
+    if body.len() > policy.max_body_bytes {
+        bail!("request body of {} bytes exceeds the limit of {} bytes", body.len(), policy.max_body_bytes);
+    }
+
+    let redirects = policy.clone();
+    let client = reqwest::Client::builder()
+        .timeout(policy.get_timeout())
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 5 {
+                attempt.error("too many redirects")
+            } else if let Err(err) = redirects.check_url(attempt.url()) {
+                attempt.error(format!("redirect denied: {err}"))
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .context("creating HTTP client")?;
+
+    let mut builder = client.request(method, url);
+    for (name, value) in &req.headers {
+        builder = builder.header(name, value);
+    }
+    if !body.is_empty() {
+        builder = builder.body(body);
+    }
+    let mut resp = builder.send().await.map_err(|e| anyhow::anyhow!("{}", error_chain(&e)))?;
+
+    let status = resp.status().as_u16();
+    let mut headers = Map::new();
+    for (name, value) in resp.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match headers.get_mut(name.as_str()) {
+            Some(Value::String(existing)) => {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            }
+            _ => {
+                headers.insert(name.as_str().to_string(), Value::String(value));
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| anyhow::anyhow!("{}", error_chain(&e)))? {
+        if body.len() + chunk.len() > policy.max_body_bytes {
+            bail!("response body exceeds the limit of {} bytes", policy.max_body_bytes);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(match String::from_utf8(body) {
+        Ok(text) => json!({ "status": status, "headers": headers, "body": text }),
+        Err(err) => json!({ "status": status, "headers": headers, "body_base64": BASE64.encode(err.into_bytes()) }),
+    })
+}
+
+/// Describe a request error with its causes, reqwest keeps the interesting
+/// part (refused connection, timeout, denied redirect) in the source chain.
+fn error_chain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        msg.push_str(": ");
+        msg.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    msg
+}
@@ -0,0 +1,152 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    fetch::{FetchRequest, HttpPolicy, fetch},
+};
+use reqwest::Url;
+use serde_json::{Value, json};
+use std::{collections::HashMap, fs, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Start a stand-in HTTP server on localhost. It answers every request with
+/// its method and path, except `/big` which returns 2048 bytes.
+async fn stand_in() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind localhost");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let mut line = request.lines().next().unwrap_or_default().split(' ');
+                let (method, path) = (line.next().unwrap_or_default(), line.next().unwrap_or_default());
+
+                let body = if path == "/big" { "x".repeat(2048) } else { format!("{method} {path}") };
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Stand-In: yes\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(resp.as_bytes()).await;
+            });
+        }
+    });
+    addr
+}
+
+fn request(url: String, method: &str) -> FetchRequest {
+    serde_json::from_value(json!({ "url": url, "method": method })).expect("valid fetch request")
+}
+
+fn url(s: &str) -> Url {
+    Url::parse(s).expect("valid URL")
+}
+
+#[test]
+fn http_policy_matches_hosts_ports_and_methods() {
+    let policy = HttpPolicy::new().allow_host("api.example.com").allow_host("*.internal.test").allow_host("localhost:8080").allow_method("post");
+
+    assert!(policy.check_url(&url("https://api.example.com/v1")).is_ok());
+    assert!(policy.check_url(&url("http://API.example.com:9000/")).is_ok());
+    assert!(policy.check_url(&url("https://svc.internal.test/")).is_ok());
+    assert!(policy.check_url(&url("https://internal.test/")).is_err());
+    assert!(policy.check_url(&url("https://evilinternal.test/")).is_err());
+    assert!(policy.check_url(&url("http://localhost:8080/")).is_ok());
+    assert!(policy.check_url(&url("http://localhost:8081/")).is_err());
+    assert!(policy.check_url(&url("ftp://api.example.com/")).is_err());
+    assert!(HttpPolicy::new().allow_host("[::1]:80").check_url(&url("http://[::1]/")).is_ok());
+
+    assert!(policy.check_method("GET").is_ok());
+    assert!(policy.check_method("post").is_ok());
+    let err = policy.check_method("DELETE").expect_err("DELETE is not allowed");
+    assert!(err.to_string().contains("GET, HEAD, POST"), "unexpected message: {err}");
+}
+
+#[tokio::test]
+async fn fetch_returns_status_headers_and_body() {
+    let addr = stand_in().await;
+    let policy = HttpPolicy::new().allow_host(&addr.to_string()).allow_method("POST").max_body_bytes(1024);
+
+    let resp = fetch(&policy, request(format!("http://{addr}/hello?x=1"), "post")).await.expect("fetch should succeed");
+    assert_eq!(resp["status"], json!(200));
+    assert_eq!(resp["headers"]["x-stand-in"], json!("yes"));
+    assert_eq!(resp["body"], json!("POST /hello?x=1"));
+
+    let err = fetch(&policy, request(format!("http://{addr}/big"), "GET")).await.expect_err("body should exceed the cap");
+    assert!(err.to_string().contains("exceeds the limit"), "unexpected message: {err}");
+
+    let err = fetch(&policy, request(format!("http://localhost:{}/", addr.port()), "GET")).await.expect_err("localhost is not allowed");
+    assert!(err.to_string().contains("not in the allowed hosts"), "unexpected message: {err}");
+    assert!(fetch(&policy, request(format!("http://{addr}/"), "PUT")).await.is_err());
+}
+
+/// Build a guest that fetches `req` and prints the result.
+fn fetch_wat(req: &Value) -> String {
+    let req = req.to_string();
+    format!(
+        r#"
+(module
+  (import "api" "http_fetch" (func $fetch (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 1024) "{}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 8192))
+    (i32.store (i32.const 4) (call $fetch (i32.const 1024) (i32.const {}) (i32.const 8192) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#,
+        req.replace('"', "\\\""),
+        req.len()
+    )
+}
+
+#[tokio::test]
+async fn api_http_fetch_follows_the_module_policy() {
+    let addr = stand_in().await;
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("fetch.wat"), fetch_wat(&json!({ "url": format!("http://{addr}/status") }))).expect("write fetch.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg.clone()).expect("runtime should initialize");
+    let out = rt.run("fetch", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert!(out["error"].as_str().unwrap_or_default().contains("not permitted"), "unexpected output: {out}");
+
+    cfg.set_http_policy(HttpPolicy::new().allow_host(&addr.to_string()));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let out = rt.run("fetch", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["status"], json!(200), "unexpected output: {out}");
+    assert_eq!(out["body"], json!("GET /status"));
+
+    let logs = out["__module-logs"].as_array().expect("logs");
+    assert!(
+        logs.iter().any(|l| l.as_str().unwrap_or_default().contains(&format!("http_fetch GET http://{addr}/status -> 200"))),
+        "unexpected logs: {logs:?}"
+    );
+}
+
+#[tokio::test]
+async fn api_http_fetch_needs_network_in_the_manifest() {
+    let addr = stand_in().await;
+    let root = tempfile::tempdir().expect("tempdir");
+    let wat = fetch_wat(&json!({ "url": format!("http://{addr}/status") }));
+    fs::write(root.path().join("offline.wat"), &wat).expect("write offline.wat");
+    fs::write(root.path().join("offline.toml"), "network = false\n").expect("write offline.toml");
+    fs::write(root.path().join("online.wat"), &wat).expect("write online.wat");
+    fs::write(root.path().join("online.toml"), "network = true\n").expect("write online.toml");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_allow_network(true);
+    cfg.set_http_policy(HttpPolicy::new().allow_host(&addr.to_string()));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run("offline", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert!(out["error"].as_str().unwrap_or_default().contains("not permitted"), "unexpected output: {out}");
+
+    let out = rt.run("online", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["status"], json!(200), "unexpected output: {out}");
+}
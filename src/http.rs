@@ -0,0 +1,348 @@
+use crate::apifn::log_line;
+use crate::deterministic::WallClock;
+use crate::fetch::HttpPolicy;
+use crate::overlay::RunOptions;
+use crate::secrets::Secrets;
+use crate::{WasmRuntime, http_policy, yield_on_epoch};
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Incoming};
+use hyper::header::{CONTENT_TYPE, HOST, HeaderValue};
+use hyper::http::uri::{Authority, PathAndQuery, Scheme as UriScheme};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode, Uri};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Store, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView};
+use wasmtime_wasi_http::bindings::ProxyPre;
+use wasmtime_wasi_http::bindings::http::types::{ErrorCode, Scheme};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::io::TokioIo;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig, default_send_request};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
+
+/// Log lines of HTTP handlers kept for `WasmRuntime::take_logs`. Older lines
+/// are dropped first when nobody drains them.
+pub const MAX_LOG_LINES: usize = 10_000;
+
+/// Store state of an HTTP handler component.
+pub struct HttpState {
+    wasi: WasiCtx,
+    http: WasiHttpCtx,
+    table: ResourceTable,
+    limits: StoreLimits,
+    module: String,
+    logs: Arc<Mutex<Vec<String>>>,
+    http_policy: Option<HttpPolicy>,
+    clock: Option<WallClock>,
+    secrets: Arc<Secrets>,
+}
+
+impl WasiView for HttpState {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        WasiCtxView { ctx: &mut self.wasi, table: &mut self.table }
+    }
+}
+
+impl WasiHttpView for HttpState {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.http
+    }
+
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    /// Check outgoing requests against the `HttpPolicy` of the module, cap
+    /// their timeouts and log them.
+    fn send_request(&mut self, request: Request<HyperOutgoingBody>, mut config: OutgoingRequestConfig) -> HttpResult<HostFutureIncomingResponse> {
+        let call = format!("outgoing {} {}", request.method(), request.uri());
+        let checked = match &self.http_policy {
+            Some(policy) => reqwest::Url::parse(&request.uri().to_string())
+                .map_err(anyhow::Error::from)
+                .and_then(|url| policy.check_url(&url))
+                .and_then(|()| policy.check_method(request.method().as_str()))
+                .map(|()| policy.get_timeout()),
+            None => Err(anyhow!("outbound HTTP is not permitted for this module")),
+        };
+
+        let line = match &checked {
+            Ok(_) => log_line(self.clock.as_ref(), "INFO", &self.module, &call),
+            Err(err) => log_line(self.clock.as_ref(), "WARN", &self.module, &format!("{call} denied: {err:#}")),
+        };
+        push_logs(&self.logs, &self.secrets, [line]);
+
+        let Ok(timeout) = checked else {
+            return Err(ErrorCode::HttpRequestDenied.into());
+        };
+        config.connect_timeout = config.connect_timeout.min(timeout);
+        config.first_byte_timeout = config.first_byte_timeout.min(timeout);
+        config.between_bytes_timeout = config.between_bytes_timeout.min(timeout);
+        Ok(default_send_request(request, config))
+    }
+}
+
+/// Runs components that export `wasi:http/incoming-handler`.
+///
+/// Handlers are looked up by module id in the runtime root directory, like
+/// stdin/stdout modules, and get the same effective configuration: module
+/// overlays, capability manifests, preopens, environment and limits. The run
+/// timeout bounds the whole request, including streaming the response body.
+/// Whatever a handler writes to stdout or stderr ends up in the runtime logs,
+/// with secret values redacted, see `WasmRuntime::take_logs`.
+pub struct HttpHandlers {
+    rt: Arc<WasmRuntime>,
+    linker: Linker<HttpState>,
+    handlers: Mutex<HashMap<String, ProxyPre<HttpState>>>,
+}
+
+impl HttpHandlers {
+    pub fn new(rt: Arc<WasmRuntime>) -> Result<Self> {
+        let mut linker = Linker::new(rt.engine());
+        wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
+        wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+        Ok(Self { rt, linker, handlers: Mutex::new(HashMap::new()) })
+    }
+
+    /// Compile and link the handler component of module `id`, once. Compiling
+    /// blocks, so it runs off the async executor.
+    async fn handler(&self, id: &str) -> Result<ProxyPre<HttpState>> {
+        if let Some(pre) = self.handlers.lock().unwrap().get(id).cloned() {
+            return Ok(pre);
+        }
+
+        let (rt, owned) = (self.rt.clone(), id.to_string());
+        let component = tokio::task::spawn_blocking(move || {
+            let (path, bytes) = rt.module_bytes(&owned)?;
+            Component::new(rt.engine(), &bytes).with_context(|| format!("compiling component {path:?} for module '{owned}'"))
+        })
+        .await
+        .with_context(|| format!("compiling component of module '{id}'"))??;
+        let pre = self.linker.instantiate_pre(&component).with_context(|| format!("linking component of module '{id}'"))?;
+        let pre = ProxyPre::new(pre).with_context(|| format!("module '{id}' does not export wasi:http/incoming-handler"))?;
+
+        self.handlers.lock().unwrap().insert(id.to_string(), pre.clone());
+        Ok(pre)
+    }
+
+    /// Handle one request with the component of module `id`.
+    ///
+    /// Returns once the handler has set the response head. The body is
+    /// streamed by the handler, which keeps running until it is done.
+    pub async fn handle<B>(&self, id: &str, req: Request<B>) -> Result<Response<HyperOutgoingBody>>
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+    {
+        let cfg = self.rt.module_config(id, &RunOptions::default())?;
+        let caps = self.rt.manifest(id)?.map(|m| cfg.get_policy().grant(&m));
+        let limits = caps.as_ref().map_or(*cfg.get_limits(), |c| c.limits.intersect(cfg.get_limits()));
+        let pre = self.handler(id).await?;
+        let secrets = self.rt.run_secrets(id, &cfg)?;
+
+        let output = MemoryOutputPipe::new(64 * 1024);
+        let mut wb = WasiCtxBuilder::new();
+        wb.stdout(output.clone()).stderr(output.clone());
+        let (scratch, clock) = self.rt.configure_wasi(&mut wb, id, &cfg, caps.as_ref(), &Value::Null)?;
+        let scratch: Vec<_> = scratch.into_iter().map(|(dir, _)| dir).collect();
+
+        let state = HttpState {
+            wasi: wb.build(),
+            http: WasiHttpCtx::new(),
+            table: ResourceTable::new(),
+            limits: limits.memory.map_or_else(StoreLimits::default, |m| StoreLimitsBuilder::new().memory_size(m).build()),
+            module: id.to_string(),
+            logs: self.rt.logs.clone(),
+            http_policy: http_policy(&cfg, caps.as_ref()),
+            clock: clock.clone(),
+            secrets: secrets.clone(),
+        };
+        let mut store = Store::new(self.rt.engine(), state);
+        store.limiter(|s| &mut s.limits);
+        store.set_fuel(limits.fuel.unwrap_or(u64::MAX))?;
+        yield_on_epoch(&mut store);
+
+        let (sender, receiver) = oneshot::channel();
+        let req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
+        let out = store.data_mut().new_response_outparam(sender)?;
+
+        let module = id.to_string();
+        let logs = self.rt.logs.clone();
+        let task = tokio::task::spawn(async move {
+            let call = async {
+                let proxy = pre.instantiate_async(&mut store).await?;
+                proxy.wasi_http_incoming_handler().call_handle(&mut store, req, out).await
+            };
+            let res = match limits.timeout() {
+                Some(t) => tokio::time::timeout(t, call).await.unwrap_or_else(|_| Err(anyhow!("module '{module}' timed out after {t:?}"))),
+                None => call.await,
+            };
+            drop(scratch);
+
+            let text = String::from_utf8_lossy(&output.contents()).into_owned();
+            push_logs(&logs, &secrets, text.lines().map(|line| log_line(clock.as_ref(), "INFO", &module, line)));
+            res
+        });
+
+        match receiver.await {
+            Ok(Ok(resp)) => Ok(resp),
+            Ok(Err(code)) => Err(anyhow!("module '{id}' failed to handle the request: {code:?}")),
+            Err(_) => match task.await {
+                Ok(Ok(())) => Err(anyhow!("module '{id}' returned without setting a response")),
+                Ok(Err(err)) => Err(err.context(format!("handling request with module '{id}'"))),
+                Err(err) => Err(anyhow!(err).context(format!("handling request with module '{id}'"))),
+            },
+        }
+    }
+
+    /// Route a request by its first path segment: `/hello/greet?x=1` is
+    /// handled by module `hello` as `/greet?x=1`. Errors become plain text
+    /// responses.
+    async fn route(&self, mut req: Request<Incoming>) -> Response<HyperOutgoingBody> {
+        let Some((id, rest)) = split_route(req.uri().path()) else {
+            return text_response(StatusCode::NOT_FOUND, "no module id in request path".to_string());
+        };
+        let (id, rest) = (id.to_string(), rest.to_string());
+        if self.rt.module_path(&id).is_none() {
+            return text_response(StatusCode::NOT_FOUND, format!("unknown module '{id}'"));
+        }
+
+        match guest_uri(&req, &rest) {
+            Ok(uri) => *req.uri_mut() = uri,
+            Err(err) => return text_response(StatusCode::BAD_REQUEST, format!("{err:#}")),
+        }
+        match self.handle(&id, req).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                // Errors may quote the guest, redact them when the module's secrets resolve.
+                let secrets = self.rt.module_config(&id, &RunOptions::default()).and_then(|cfg| self.rt.run_secrets(&id, &cfg)).unwrap_or_default();
+                let msg = secrets.redact_str(&format!("{err:#}"));
+                push_logs(&self.rt.logs, &Secrets::default(), [log_line(None, "ERROR", &id, &msg)]);
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, msg)
+            }
+        }
+    }
+}
+
+/// Buffer log lines of HTTP handlers with secret values redacted, keeping
+/// the newest `MAX_LOG_LINES`.
+pub(crate) fn push_logs<I: IntoIterator<Item = String>>(logs: &Mutex<Vec<String>>, secrets: &Secrets, lines: I) {
+    if let Ok(mut g) = logs.lock() {
+        g.extend(lines.into_iter().map(|line| secrets.redact_str(&line)));
+        let excess = g.len().saturating_sub(MAX_LOG_LINES);
+        g.drain(..excess);
+    }
+}
+
+/// Split `/{id}/rest` into the module id and the path the guest sees.
+fn split_route(path: &str) -> Option<(&str, &str)> {
+    let path = path.strip_prefix('/')?;
+    let (id, rest) = match path.find('/') {
+        Some(i) => path.split_at(i),
+        None => (path, "/"),
+    };
+    if id.is_empty() { None } else { Some((id, rest)) }
+}
+
+/// Build the absolute URI a handler sees: the request path without the
+/// module id, with the authority taken from the `Host` header.
+fn guest_uri<B>(req: &Request<B>, path: &str) -> Result<Uri> {
+    let path_and_query = match req.uri().query() {
+        Some(q) => format!("{path}?{q}"),
+        None => path.to_string(),
+    };
+    let authority = match req.uri().authority() {
+        Some(a) => a.clone(),
+        None => {
+            let host = req.headers().get(HOST).and_then(|h| h.to_str().ok()).unwrap_or("localhost");
+            host.parse::<Authority>().with_context(|| format!("invalid Host header '{host}'"))?
+        }
+    };
+    Ok(Uri::builder()
+        .scheme(req.uri().scheme().cloned().unwrap_or(UriScheme::HTTP))
+        .authority(authority)
+        .path_and_query(path_and_query.parse::<PathAndQuery>().context("invalid request path")?)
+        .build()?)
+}
+
+fn text_response(status: StatusCode, msg: String) -> Response<HyperOutgoingBody> {
+    let body = Full::new(Bytes::from(msg)).map_err(|never: Infallible| match never {}).boxed();
+    let mut resp = Response::new(body);
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    resp
+}
+
+/// An HTTP server serving `HttpHandlers` in the background.
+pub struct HttpServer {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl HttpServer {
+    /// Listen on `addr` and serve requests with `handlers`, routed by module
+    /// id, until `shutdown` is called.
+    pub async fn start<A: ToSocketAddrs>(handlers: Arc<HttpHandlers>, addr: A) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await.context("binding HTTP listener")?;
+        let addr = listener.local_addr()?;
+        let (shutdown, mut stop) = oneshot::channel();
+
+        let task = tokio::task::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = &mut stop => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(err) => {
+                            eprintln!("accepting HTTP connection: {err}");
+                            continue;
+                        }
+                    },
+                };
+
+                let handlers = handlers.clone();
+                tokio::task::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let handlers = handlers.clone();
+                        async move { Ok::<_, Infallible>(handlers.route(req).await) }
+                    });
+                    if let Err(err) = http1::Builder::new().keep_alive(true).serve_connection(TokioIo::new(stream), service).await {
+                        eprintln!("serving HTTP connection: {err}");
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, shutdown, task })
+    }
+
+    /// Get the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections. Requests in flight are not interrupted.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+impl WasmRuntime {
+    /// Serve the HTTP handler components of this runtime on `addr`, see
+    /// `HttpHandlers` and `HttpServer`.
+    pub async fn serve_http<A: ToSocketAddrs>(self: &Arc<Self>, addr: A) -> Result<HttpServer> {
+        HttpServer::start(Arc::new(HttpHandlers::new(self.clone())?), addr).await
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    WasmRuntime,
+    cfg::{Limits, WasmConfig},
+    http::{MAX_LOG_LINES, push_logs},
+    overlay::ConfigOverlay,
+    secrets::{Secrets, StaticSecrets},
+};
+use std::{
+    fs,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Returns without setting a response.
+static SILENT_WAT: &str = r#"
+(component
+  (import "wasi:http/types@0.2.0" (instance $types
+    (export "incoming-request" (type (sub resource)))
+    (export "response-outparam" (type (sub resource)))))
+  (alias export $types "incoming-request" (type $req))
+  (alias export $types "response-outparam" (type $out))
+  (core module $m
+    (func (export "handle") (param i32 i32)))
+  (core instance $i (instantiate $m))
+  (func $handle (param "request" (own $req)) (param "response-out" (own $out))
+    (canon lift (core func $i "handle")))
+  (instance $handler (export "handle" (func $handle)))
+  (export "wasi:http/incoming-handler@0.2.0" (instance $handler)))
+"#;
+
+/// Never returns from the handler.
+static SPIN_WAT: &str = r#"
+(component
+  (import "wasi:http/types@0.2.0" (instance $types
+    (export "incoming-request" (type (sub resource)))
+    (export "response-outparam" (type (sub resource)))))
+  (alias export $types "incoming-request" (type $req))
+  (alias export $types "response-outparam" (type $out))
+  (core module $m
+    (func (export "handle") (param i32 i32)
+      (loop $spin (br $spin))))
+  (core instance $i (instantiate $m))
+  (func $handle (param "request" (own $req)) (param "response-out" (own $out))
+    (canon lift (core func $i "handle")))
+  (instance $handler (export "handle" (func $handle)))
+  (export "wasi:http/incoming-handler@0.2.0" (instance $handler)))
+"#;
+
+/// Answers 418 with an `x-module: teapot` header and an empty body.
+static TEAPOT_WAT: &str = r#"
+(component
+  (import "wasi:http/types@0.2.0" (instance $types
+    (export "fields" (type $fields (sub resource)))
+    (export "incoming-request" (type (sub resource)))
+    (export "outgoing-response" (type $response (sub resource)))
+    (export "response-outparam" (type $outparam (sub resource)))
+    (type $header-error' (variant (case "invalid-syntax") (case "forbidden") (case "immutable")))
+    (export "header-error" (type $header-error (eq $header-error')))
+    (type $dns' (record (field "rcode" (option string)) (field "info-code" (option u16))))
+    (export "DNS-error-payload" (type $dns (eq $dns')))
+    (type $tls' (record (field "alert-id" (option u8)) (field "alert-message" (option string))))
+    (export "TLS-alert-received-payload" (type $tls (eq $tls')))
+    (type $field-size' (record (field "field-name" (option string)) (field "field-size" (option u32))))
+    (export "field-size-payload" (type $field-size (eq $field-size')))
+    (type $error-code' (variant
+      (case "DNS-timeout")
+      (case "DNS-error" $dns)
+      (case "destination-not-found")
+      (case "destination-unavailable")
+      (case "destination-IP-prohibited")
+      (case "destination-IP-unroutable")
+      (case "connection-refused")
+      (case "connection-terminated")
+      (case "connection-timeout")
+      (case "connection-read-timeout")
+      (case "connection-write-timeout")
+      (case "connection-limit-reached")
+      (case "TLS-protocol-error")
+      (case "TLS-certificate-error")
+      (case "TLS-alert-received" $tls)
+      (case "HTTP-request-denied")
+      (case "HTTP-request-length-required")
+      (case "HTTP-request-body-size" (option u64))
+      (case "HTTP-request-method-invalid")
+      (case "HTTP-request-URI-invalid")
+      (case "HTTP-request-URI-too-long")
+      (case "HTTP-request-header-section-size" (option u32))
+      (case "HTTP-request-header-size" (option $field-size))
+      (case "HTTP-request-trailer-section-size" (option u32))
+      (case "HTTP-request-trailer-size" $field-size)
+      (case "HTTP-response-incomplete")
+      (case "HTTP-response-header-section-size" (option u32))
+      (case "HTTP-response-header-size" $field-size)
+      (case "HTTP-response-body-size" (option u64))
+      (case "HTTP-response-trailer-section-size" (option u32))
+      (case "HTTP-response-trailer-size" $field-size)
+      (case "HTTP-response-transfer-coding" (option string))
+      (case "HTTP-response-content-coding" (option string))
+      (case "HTTP-response-timeout")
+      (case "HTTP-upgrade-failed")
+      (case "HTTP-protocol-error")
+      (case "loop-detected")
+      (case "configuration-error")
+      (case "internal-error" (option string))))
+    (export "error-code" (type $error-code (eq $error-code')))
+    (type $own-fields (own $fields))
+    (type $borrow-fields (borrow $fields))
+    (type $own-response (own $response))
+    (type $borrow-response (borrow $response))
+    (type $own-outparam (own $outparam))
+    (export "[constructor]fields" (func (result $own-fields)))
+    (export "[method]fields.append" (func (param "self" $borrow-fields) (param "name" string) (param "value" (list u8))
+      (result (result (error $header-error)))))
+    (export "[constructor]outgoing-response" (func (param "headers" $own-fields) (result $own-response)))
+    (export "[method]outgoing-response.set-status-code" (func (param "self" $borrow-response) (param "status-code" u16) (result (result))))
+    (export "[static]response-outparam.set" (func (param "param" $own-outparam) (param "response" (result $own-response (error $error-code)))))))
+  (alias export $types "incoming-request" (type $req))
+  (alias export $types "response-outparam" (type $out))
+  (core module $memory
+    (memory (export "memory") 1)
+    (data (i32.const 100) "x-moduleteapot"))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (core func $fields-new (canon lower (func $types "[constructor]fields")))
+  (core func $fields-append (canon lower (func $types "[method]fields.append") (memory $mem)))
+  (core func $response-new (canon lower (func $types "[constructor]outgoing-response")))
+  (core func $set-status (canon lower (func $types "[method]outgoing-response.set-status-code")))
+  (core func $set-response (canon lower (func $types "[static]response-outparam.set") (memory $mem)))
+  (core module $m
+    (import "http" "fields-new" (func $fields-new (result i32)))
+    (import "http" "fields-append" (func $fields-append (param i32 i32 i32 i32 i32 i32)))
+    (import "http" "response-new" (func $response-new (param i32) (result i32)))
+    (import "http" "set-status" (func $set-status (param i32 i32) (result i32)))
+    (import "http" "set-response" (func $set-response (param i32 i32 i32 i32 i64 i32 i32 i32 i32)))
+    (func (export "handle") (param $req i32) (param $out i32)
+      (local $fields i32)
+      (local $resp i32)
+      (local.set $fields (call $fields-new))
+      (call $fields-append (local.get $fields) (i32.const 100) (i32.const 8) (i32.const 108) (i32.const 6) (i32.const 200))
+      (local.set $resp (call $response-new (local.get $fields)))
+      (drop (call $set-status (local.get $resp) (i32.const 418)))
+      (call $set-response (local.get $out)
+        (i32.const 0) (local.get $resp) (i32.const 0) (i64.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))))
+  (core instance $i (instantiate $m
+    (with "http" (instance
+      (export "fields-new" (func $fields-new))
+      (export "fields-append" (func $fields-append))
+      (export "response-new" (func $response-new))
+      (export "set-status" (func $set-status))
+      (export "set-response" (func $set-response))))))
+  (func $handle (param "request" (own $req)) (param "response-out" (own $out))
+    (canon lift (core func $i "handle")))
+  (instance $handler (export "handle" (func $handle)))
+  (export "wasi:http/incoming-handler@0.2.0" (instance $handler)))
+"#;
+
+/// Prints "token=s3cr3t-value" to stderr, then returns without setting a
+/// response.
+static PRINTER_WAT: &str = r#"
+(component $C
+  (import "wasi:io/error@0.2.0" (instance $errors
+    (export "error" (type (sub resource)))))
+  (alias export $errors "error" (type $error))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (alias outer $C $error (type $e))
+    (export "error" (type $err (eq $e)))
+    (export "output-stream" (type $os (sub resource)))
+    (type $own-err (own $err))
+    (type $stream-error' (variant (case "last-operation-failed" $own-err) (case "closed")))
+    (export "stream-error" (type $stream-error (eq $stream-error')))
+    (type $borrow-os (borrow $os))
+    (export "[method]output-stream.blocking-write-and-flush"
+      (func (param "self" $borrow-os) (param "contents" (list u8)) (result (result (error $stream-error)))))))
+  (alias export $streams "output-stream" (type $output))
+  (import "wasi:cli/stderr@0.2.0" (instance $stderr
+    (alias outer $C $output (type $o))
+    (export "output-stream" (type $os (eq $o)))
+    (type $own-os (own $os))
+    (export "get-stderr" (func (result $own-os)))))
+  (import "wasi:http/types@0.2.0" (instance $types
+    (export "incoming-request" (type (sub resource)))
+    (export "response-outparam" (type (sub resource)))))
+  (alias export $types "incoming-request" (type $req))
+  (alias export $types "response-outparam" (type $out))
+  (core module $memory
+    (memory (export "memory") 1)
+    (data (i32.const 100) "token=s3cr3t-value\n"))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (core func $get-stderr (canon lower (func $stderr "get-stderr")))
+  (core func $write (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $mem)))
+  (core module $m
+    (import "io" "get-stderr" (func $get-stderr (result i32)))
+    (import "io" "write" (func $write (param i32 i32 i32 i32)))
+    (func (export "handle") (param $req i32) (param $out i32)
+      (call $write (call $get-stderr) (i32.const 100) (i32.const 19) (i32.const 200))))
+  (core instance $i (instantiate $m
+    (with "io" (instance
+      (export "get-stderr" (func $get-stderr))
+      (export "write" (func $write))))))
+  (func $handle (param "request" (own $req)) (param "response-out" (own $out))
+    (canon lift (core func $i "handle")))
+  (instance $handler (export "handle" (func $handle)))
+  (export "wasi:http/incoming-handler@0.2.0" (instance $handler)))
+"#;
+
+/// A core module, not a component.
+static CORE_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")))
+"#;
+
+/// Send a GET request and return the status and body.
+async fn get(addr: SocketAddr, path: &str) -> (u16, String) {
+    let (status, _, body) = get_with_head(addr, path).await;
+    (status, body)
+}
+
+/// Send a GET request and return the status, the response head and the body.
+async fn get_with_head(addr: SocketAddr, path: &str) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(addr).await.expect("connect to server");
+    let head = format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await.expect("write request");
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await.expect("read response");
+    let text = String::from_utf8(raw).expect("utf-8 response");
+    let (head, body) = text.split_once("\r\n\r\n").expect("response head");
+    let status = head.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("status code");
+    (status, head.to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn http_routes_requests_by_module_id() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("silent.wat"), SILENT_WAT).expect("write silent.wat");
+    fs::write(root.path().join("core.wat"), CORE_WAT).expect("write core.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = Arc::new(WasmRuntime::new(cfg).expect("runtime should initialize"));
+    let server = rt.serve_http("127.0.0.1:0").await.expect("server should start");
+    let addr = server.local_addr();
+
+    let (status, body) = get(addr, "/").await;
+    assert_eq!(status, 404, "unexpected body: {body}");
+    let (status, body) = get(addr, "/missing/x").await;
+    assert_eq!(status, 404);
+    assert!(body.contains("unknown module 'missing'"), "unexpected body: {body}");
+
+    let (status, body) = get(addr, "/core/").await;
+    assert_eq!(status, 500);
+    assert!(body.contains("compiling component"), "unexpected body: {body}");
+
+    // The handler is linked and called, but never answers.
+    let (status, body) = get(addr, "/silent/greet?name=x").await;
+    assert_eq!(status, 500);
+    assert!(body.contains("without setting a response"), "unexpected body: {body}");
+
+    let logs = rt.take_logs();
+    assert!(logs.iter().any(|l| l.contains("ERROR: [core]")), "unexpected logs: {logs:?}");
+    assert!(logs.iter().any(|l| l.contains("ERROR: [silent]")), "unexpected logs: {logs:?}");
+
+    server.shutdown().await;
+    assert!(TcpStream::connect(addr).await.is_err(), "server should stop listening");
+}
+
+#[tokio::test]
+async fn http_handlers_answer_with_their_response() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("teapot.wat"), TEAPOT_WAT).expect("write teapot.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = Arc::new(WasmRuntime::new(cfg).expect("runtime should initialize"));
+
+    let server = rt.serve_http("127.0.0.1:0").await.expect("server should start");
+    let (status, head, body) = get_with_head(server.local_addr(), "/teapot/brew").await;
+    assert_eq!(status, 418, "unexpected response: {head}\r\n\r\n{body}");
+    assert!(head.lines().any(|l| l.eq_ignore_ascii_case("x-module: teapot")), "unexpected head: {head}");
+    assert_eq!(body, "");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn http_handlers_honor_module_timeouts() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = Arc::new(WasmRuntime::new(cfg).expect("runtime should initialize"));
+    rt.set_module_config("spin", ConfigOverlay { limits: Some(Limits { timeout_ms: Some(200), ..Default::default() }), ..Default::default() });
+
+    let server = rt.serve_http("127.0.0.1:0").await.expect("server should start");
+    let (status, body) = get(server.local_addr(), "/spin/").await;
+    assert_eq!(status, 500);
+    assert!(body.contains("timed out"), "unexpected body: {body}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn http_handler_logs_are_redacted() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("printer.wat"), PRINTER_WAT).expect("write printer.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_secrets(&["token"]);
+    let mut rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    rt.set_secrets_provider(Arc::new(StaticSecrets::new().with("token", "s3cr3t-value")));
+    let rt = Arc::new(rt);
+    let server = rt.serve_http("127.0.0.1:0").await.expect("server should start");
+
+    let (status, body) = get(server.local_addr(), "/printer/").await;
+    assert_eq!(status, 500, "unexpected body: {body}");
+    let logs = rt.take_logs();
+    assert!(logs.iter().any(|l| l.ends_with("INFO: [printer] token=[REDACTED]")), "unexpected logs: {logs:?}");
+    assert!(!logs.iter().any(|l| l.contains("s3cr3t")), "unexpected logs: {logs:?}");
+    server.shutdown().await;
+}
+
+#[test]
+fn http_logs_keep_the_newest_lines() {
+    let logs = Mutex::new(Vec::new());
+    push_logs(&logs, &Secrets::default(), (0..MAX_LOG_LINES + 5).map(|i| i.to_string()));
+    push_logs(&logs, &Secrets::default(), ["last".to_string()]);
+    let logs = logs.into_inner().expect("logs");
+    assert_eq!(logs.len(), MAX_LOG_LINES);
+    assert_eq!((logs[0].as_str(), logs[MAX_LOG_LINES - 1].as_str()), ("6", "last"));
+}
@@ -0,0 +1,139 @@
+use serde::Serialize;
+use wasmtime::{ExternType, Module};
+
+/// A single import declared by a guest module.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ImportInfo {
+    pub module: String,
+    pub name: String,
+    pub kind: String,
+    pub ty: String,
+}
+
+/// A single export provided by a guest module.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExportInfo {
+    pub name: String,
+    pub kind: String,
+    pub ty: String,
+}
+
+/// Imports and exports of a guest module, as returned by `WasmRuntime::inspect`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ModuleInfo {
+    pub id: String,
+    pub imports: Vec<ImportInfo>,
+    pub exports: Vec<ExportInfo>,
+}
+
+impl ModuleInfo {
+    /// Collect import and export descriptions from a compiled module.
+    pub fn from_module(id: &str, module: &Module) -> Self {
+        Self {
+            id: id.to_string(),
+            imports: module
+                .imports()
+                .map(|i| {
+                    let ty = i.ty();
+                    ImportInfo { module: i.module().to_string(), name: i.name().to_string(), kind: kind(&ty).to_string(), ty: describe(&ty) }
+                })
+                .collect(),
+            exports: module
+                .exports()
+                .map(|e| {
+                    let ty = e.ty();
+                    ExportInfo { name: e.name().to_string(), kind: kind(&ty).to_string(), ty: describe(&ty) }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A problem found while matching module imports against the runtime linker.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum LinkIssue {
+    /// Nothing is registered in the linker under this module/name pair.
+    Unresolved { module: String, name: String, expected: String },
+    /// Something is registered, but its type does not satisfy the import.
+    Mismatch { module: String, name: String, expected: String, found: String },
+}
+
+/// Result of `WasmRuntime::check`: every import that would fail to link.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LinkReport {
+    pub id: String,
+    pub issues: Vec<LinkIssue>,
+}
+
+impl LinkReport {
+    /// Return true when the module links cleanly against the runtime.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Short kind name of an extern type: "func", "global", "table", "memory" or "tag".
+pub fn kind(ty: &ExternType) -> &'static str {
+    match ty {
+        ExternType::Func(_) => "func",
+        ExternType::Global(_) => "global",
+        ExternType::Table(_) => "table",
+        ExternType::Memory(_) => "memory",
+        ExternType::Tag(_) => "tag",
+    }
+}
+
+/// Render an extern type in a WAT-like textual form.
+pub fn describe(ty: &ExternType) -> String {
+    fn limits(min: u64, max: Option<u64>) -> String {
+        match max {
+            Some(max) => format!("{min} {max}"),
+            None => min.to_string(),
+        }
+    }
+
+    match ty {
+        ExternType::Func(f) => f.to_string(),
+        ExternType::Global(g) => match g.mutability() {
+            wasmtime::Mutability::Var => format!("(global (mut {}))", g.content()),
+            wasmtime::Mutability::Const => format!("(global {})", g.content()),
+        },
+        ExternType::Table(t) => format!("(table {} {})", limits(t.minimum(), t.maximum()), t.element()),
+        ExternType::Memory(m) => format!("(memory{} {})", if m.is_64() { " i64" } else { "" }, limits(m.minimum(), m.maximum())),
+        ExternType::Tag(t) => format!("(tag {})", t.ty()),
+    }
+}
+
+/// Check whether an extern provided by the linker satisfies the type an import expects.
+///
+/// Memories and tables follow the wasm import rules: the provided minimum may
+/// exceed the requested one, and the provided maximum must be set and fit
+/// within the requested maximum, if the import has one.
+pub fn satisfies(found: &ExternType, expected: &ExternType) -> bool {
+    match (found, expected) {
+        (ExternType::Func(found), ExternType::Func(expected)) => found.matches(expected),
+        (ExternType::Memory(found), ExternType::Memory(expected)) => {
+            found.is_64() == expected.is_64()
+                && found.is_shared() == expected.is_shared()
+                && found.page_size() == expected.page_size()
+                && limits_fit((found.minimum(), found.maximum()), (expected.minimum(), expected.maximum()))
+        }
+        (ExternType::Table(found), ExternType::Table(expected)) => {
+            found.is_64() == expected.is_64()
+                && found.element().to_string() == expected.element().to_string()
+                && limits_fit((found.minimum(), found.maximum()), (expected.minimum(), expected.maximum()))
+        }
+        (found, expected) => kind(found) == kind(expected) && describe(found) == describe(expected),
+    }
+}
+
+/// Check provided `(min, max)` limits against the limits an import requests.
+fn limits_fit(found: (u64, Option<u64>), expected: (u64, Option<u64>)) -> bool {
+    found.0 >= expected.0
+        && match (found.1, expected.1) {
+            (_, None) => true,
+            (Some(found), Some(expected)) => found <= expected,
+            (None, Some(_)) => false,
+        }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    inspect::{ExportInfo, ImportInfo, LinkIssue, satisfies},
+};
+use std::fs;
+use tempfile::TempDir;
+use wasmtime::{ExternType, MemoryType, RefType, TableType};
+
+static LINKABLE_WAT: &str = r#"
+(module
+  (import "api" "log" (func (param i32 i32 i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")))
+"#;
+
+static UNLINKABLE_WAT: &str = r#"
+(module
+  (import "api" "log" (func (param i32 i32 i32)))
+  (import "api" "header" (func (param i64) (result i32)))
+  (import "api" "teleport" (func (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")))
+"#;
+
+fn runtime_with(modules: &[(&str, &str)]) -> (TempDir, WasmRuntime) {
+    let root = tempfile::Builder::new().prefix("wasmruntime-inspect-").tempdir().unwrap_or_else(|err| panic!("failed to create runtime root: {err}"));
+    for (id, wat) in modules {
+        fs::write(root.path().join(format!("{id}.wat")), wat).unwrap_or_else(|err| panic!("failed to write {id}.wat: {err}"));
+    }
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    (root, WasmRuntime::new(cfg).expect("runtime should initialize"))
+}
+
+#[test]
+fn inspect_lists_imports_and_exports_with_types() {
+    let (_root, rt) = runtime_with(&[("linkable", LINKABLE_WAT)]);
+    let info = rt.inspect("linkable").expect("inspect should succeed");
+
+    assert_eq!(info.id, "linkable");
+    assert_eq!(
+        info.imports[0],
+        ImportInfo { module: "api".into(), name: "log".into(), kind: "func".into(), ty: "(type (func (param i32 i32 i32)))".into() }
+    );
+    assert_eq!(info.exports[0], ExportInfo { name: "memory".into(), kind: "memory".into(), ty: "(memory 1)".into() });
+    assert_eq!(info.exports[1], ExportInfo { name: "_start".into(), kind: "func".into(), ty: "(type (func))".into() });
+}
+
+#[test]
+fn check_passes_for_module_matching_linker() {
+    let (_root, rt) = runtime_with(&[("linkable", LINKABLE_WAT)]);
+    let report = rt.check("linkable").expect("check should succeed");
+
+    assert!(report.is_ok(), "unexpected issues: {:?}", report.issues);
+}
+
+#[test]
+fn check_reports_unresolved_and_mismatched_imports() {
+    let (_root, rt) = runtime_with(&[("unlinkable", UNLINKABLE_WAT)]);
+    let report = rt.check("unlinkable").expect("check should succeed");
+
+    assert_eq!(
+        report.issues,
+        vec![
+            LinkIssue::Mismatch {
+                module: "api".into(),
+                name: "header".into(),
+                expected: "(type (func (param i64) (result i32)))".into(),
+                found: "(type (func (param i32 i32) (result i32)))".into(),
+            },
+            LinkIssue::Unresolved { module: "api".into(), name: "teleport".into(), expected: "(type (func (param i32)))".into() },
+        ]
+    );
+}
+
+#[test]
+fn check_sees_functions_added_through_extend_linker() {
+    let (_root, mut rt) = runtime_with(&[("unlinkable", UNLINKABLE_WAT)]);
+    rt.extend_linker(|linker| {
+        linker.func_wrap("api", "teleport", |_: i32| {})?;
+        Ok(())
+    })
+    .expect("linker extension should succeed");
+
+    let report = rt.check("unlinkable").expect("check should succeed");
+    assert_eq!(report.issues.len(), 1);
+}
+
+#[test]
+fn satisfies_compares_memory_and_table_limits() {
+    let memory = |min, max| ExternType::Memory(MemoryType::new(min, max));
+    assert!(satisfies(&memory(2, Some(10)), &memory(1, Some(16))), "more pages and a tighter maximum should satisfy the import");
+    assert!(satisfies(&memory(1, None), &memory(1, None)));
+    assert!(satisfies(&memory(1, Some(4)), &memory(1, None)));
+    assert!(!satisfies(&memory(1, None), &memory(2, None)), "fewer pages than requested");
+    assert!(!satisfies(&memory(1, None), &memory(1, Some(4))), "an unbounded memory exceeds a requested maximum");
+    assert!(!satisfies(&memory(1, Some(8)), &memory(1, Some(4))));
+    assert!(!satisfies(&ExternType::Memory(MemoryType::new64(1, None)), &memory(1, None)));
+
+    let table = |min, max| ExternType::Table(TableType::new(RefType::FUNCREF, min, max));
+    assert!(satisfies(&table(10, Some(20)), &table(4, None)));
+    assert!(!satisfies(&table(2, Some(20)), &table(4, None)));
+    assert!(!satisfies(&ExternType::Table(TableType::new(RefType::EXTERNREF, 10, None)), &table(4, None)));
+}
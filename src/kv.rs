@@ -0,0 +1,257 @@
+use anyhow::{Context, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Longest accepted key, in bytes.
+pub const MAX_KEY_BYTES: usize = 256;
+
+/// Storage behind the `api.kv_*` imports.
+///
+/// Every module gets its own namespace, the module id, and never sees the
+/// keys of other modules. Keys are UTF-8 strings, values are bytes. Quotas are
+/// not the backend's concern, `KvStore` checks them before writing.
+pub trait KvBackend: Send + Sync {
+    /// Get the value of a key, `None` when it is not set.
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Set a key, replacing any previous value.
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()>;
+
+    /// Delete a key, returning whether it was set.
+    fn delete(&self, namespace: &str, key: &str) -> Result<bool>;
+
+    /// List the keys starting with `prefix`, in ascending order.
+    fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<String>>;
+
+    /// Count the keys of a namespace and the bytes they take, keys included.
+    fn usage(&self, namespace: &str) -> Result<KvUsage>;
+}
+
+/// Space used by a namespace, see `KvBackend::usage`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KvUsage {
+    pub keys: usize,
+    pub bytes: u64,
+}
+
+impl KvUsage {
+    fn of<'a>(entries: impl Iterator<Item = (&'a String, usize)>) -> Self {
+        entries.fold(Self::default(), |u, (k, len)| Self { keys: u.keys + 1, bytes: u.bytes + (k.len() + len) as u64 })
+    }
+}
+
+/// Per-module limits of the key/value store, `None` means unlimited.
+///
+/// The defaults keep a module to small amounts of state: 1024 keys, 1 MiB in
+/// total and 64 KiB per value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KvQuota {
+    pub max_keys: Option<usize>,
+    /// Keys and values together.
+    pub max_bytes: Option<u64>,
+    pub max_value_bytes: Option<usize>,
+}
+
+impl Default for KvQuota {
+    fn default() -> Self {
+        Self { max_keys: Some(1024), max_bytes: Some(1024 * 1024), max_value_bytes: Some(64 * 1024) }
+    }
+}
+
+impl KvQuota {
+    /// A quota without any limit.
+    pub fn unlimited() -> Self {
+        Self { max_keys: None, max_bytes: None, max_value_bytes: None }
+    }
+}
+
+/// The key/value store of one module: a backend, the module namespace and
+/// its quota.
+///
+/// Quotas are checked before each write. Concurrent runs of the same module
+/// may each get one write past the limit.
+#[derive(Clone)]
+pub struct KvStore {
+    backend: Arc<dyn KvBackend>,
+    namespace: String,
+    quota: KvQuota,
+}
+
+impl KvStore {
+    pub fn new(backend: Arc<dyn KvBackend>, namespace: &str, quota: KvQuota) -> Self {
+        Self { backend, namespace: namespace.to_string(), quota }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        self.backend.get(&self.namespace, key)
+    }
+
+    /// Set a key, failing when the write would exceed the quota.
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_key(key)?;
+        if let Some(max) = self.quota.max_value_bytes
+            && value.len() > max
+        {
+            bail!("value of {} bytes exceeds the limit of {max} bytes per value", value.len());
+        }
+
+        if self.quota.max_keys.is_some() || self.quota.max_bytes.is_some() {
+            let usage = self.backend.usage(&self.namespace)?;
+            let old = self.backend.get(&self.namespace, key)?.map(|v| (key.len() + v.len()) as u64);
+            let keys = usage.keys + usize::from(old.is_none());
+            let bytes = usage.bytes.saturating_sub(old.unwrap_or(0)) + (key.len() + value.len()) as u64;
+            if let Some(max) = self.quota.max_keys
+                && keys > max
+            {
+                bail!("store of module '{}' is full, it holds the maximum of {max} keys", self.namespace);
+            }
+            if let Some(max) = self.quota.max_bytes
+                && bytes > max
+            {
+                bail!("store of module '{}' would grow to {bytes} bytes, over the limit of {max} bytes", self.namespace);
+            }
+        }
+        self.backend.set(&self.namespace, key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<bool> {
+        check_key(key)?;
+        self.backend.delete(&self.namespace, key)
+    }
+
+    pub fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.backend.list(&self.namespace, prefix)
+    }
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if key.is_empty() {
+        bail!("key must not be empty");
+    }
+    if key.len() > MAX_KEY_BYTES {
+        bail!("key of {} bytes exceeds the limit of {MAX_KEY_BYTES} bytes", key.len());
+    }
+    Ok(())
+}
+
+/// A store that lives as long as the runtime. This is the default backend.
+#[derive(Default)]
+pub struct MemoryKv {
+    spaces: Mutex<HashMap<String, BTreeMap<String, Vec<u8>>>>,
+}
+
+impl MemoryKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvBackend for MemoryKv {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.spaces.lock().unwrap().get(namespace).and_then(|s| s.get(key).cloned()))
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        self.spaces.lock().unwrap().entry(namespace.to_string()).or_default().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<bool> {
+        Ok(self.spaces.lock().unwrap().get_mut(namespace).is_some_and(|s| s.remove(key).is_some()))
+    }
+
+    fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let spaces = self.spaces.lock().unwrap();
+        Ok(spaces.get(namespace).map(|s| s.keys().filter(|k| k.starts_with(prefix)).cloned().collect()).unwrap_or_default())
+    }
+
+    fn usage(&self, namespace: &str) -> Result<KvUsage> {
+        Ok(self.spaces.lock().unwrap().get(namespace).map(|s| KvUsage::of(s.iter().map(|(k, v)| (k, v.len())))).unwrap_or_default())
+    }
+}
+
+/// A store kept in a directory, one JSON file per module, `{id}.kv.json`.
+///
+/// Values are base64 encoded in the file. Files are replaced atomically on
+/// every write, which suits the small amounts of state quotas allow.
+pub struct FileKv {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileKv {
+    /// Use `dir` for the store, creating it when it is missing.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).with_context(|| format!("creating key/value directory {dir:?}"))?;
+        Ok(Self { dir, lock: Mutex::new(()) })
+    }
+
+    fn path(&self, namespace: &str) -> Result<PathBuf> {
+        if namespace.is_empty() || namespace.starts_with('.') || namespace.contains(['/', '\\']) {
+            bail!("invalid key/value namespace '{namespace}'");
+        }
+        Ok(self.dir.join(format!("{namespace}.kv.json")))
+    }
+
+    fn load(&self, namespace: &str) -> Result<BTreeMap<String, String>> {
+        let path = self.path(namespace)?;
+        match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).with_context(|| format!("parsing key/value file {path:?}")),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(err).with_context(|| format!("reading key/value file {path:?}")),
+        }
+    }
+
+    fn store(&self, namespace: &str, entries: &BTreeMap<String, String>) -> Result<()> {
+        let path = self.path(namespace)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(entries)?).with_context(|| format!("writing key/value file {tmp:?}"))?;
+        fs::rename(&tmp, &path).with_context(|| format!("replacing key/value file {path:?}"))
+    }
+}
+
+impl KvBackend for FileKv {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let _guard = self.lock.lock().unwrap();
+        match self.load(namespace)?.get(key) {
+            Some(b64) => Ok(Some(BASE64.decode(b64).with_context(|| format!("corrupt value of key '{key}'"))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = self.load(namespace)?;
+        entries.insert(key.to_string(), BASE64.encode(value));
+        self.store(namespace, &entries)
+    }
+
+    fn delete(&self, namespace: &str, key: &str) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = self.load(namespace)?;
+        if entries.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.store(namespace, &entries)?;
+        Ok(true)
+    }
+
+    fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.load(namespace)?.into_keys().filter(|k| k.starts_with(prefix)).collect())
+    }
+
+    fn usage(&self, namespace: &str) -> Result<KvUsage> {
+        let _guard = self.lock.lock().unwrap();
+        let entries = self.load(namespace)?;
+        // Base64 takes 4 bytes for every 3, count the decoded size.
+        Ok(KvUsage::of(entries.iter().map(|(k, v)| (k, v.len() / 4 * 3 - v.bytes().rev().take_while(|&b| b == b'=').count()))))
+    }
+}
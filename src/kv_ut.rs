@@ -0,0 +1,129 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    kv::{FileKv, KvBackend, KvQuota, KvStore, KvUsage, MemoryKv},
+    overlay::ConfigOverlay,
+};
+use serde_json::json;
+use std::{collections::HashMap, fs, sync::Arc};
+
+/// Reads the one-digit counter under "count", increments it, stores it back
+/// and prints `{"count":N}`.
+static COUNTER_WAT: &str = r#"
+(module
+  (import "api" "kv_get" (func $get (param i32 i32 i32 i32) (result i32)))
+  (import "api" "kv_set" (func $set (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "count")
+  (data (i32.const 200) "{\"count\":0}")
+  (func (export "_start")
+    (drop (call $get (i32.const 100) (i32.const 5) (i32.const 209) (i32.const 1)))
+    (i32.store8 (i32.const 209) (i32.add (i32.load8_u (i32.const 209)) (i32.const 1)))
+    (drop (call $set (i32.const 100) (i32.const 5) (i32.const 209) (i32.const 1)))
+    (i32.store (i32.const 0) (i32.const 200))
+    (i32.store (i32.const 4) (i32.const 11))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+fn exercise(backend: &dyn KvBackend) {
+    assert_eq!(backend.get("a", "k1").unwrap(), None);
+    backend.set("a", "k1", b"one").unwrap();
+    backend.set("a", "k2", b"").unwrap();
+    backend.set("a", "other", &[0, 255]).unwrap();
+    backend.set("b", "k1", b"b's").unwrap();
+
+    assert_eq!(backend.get("a", "k1").unwrap().as_deref(), Some(&b"one"[..]));
+    assert_eq!(backend.get("a", "k2").unwrap().as_deref(), Some(&b""[..]));
+    assert_eq!(backend.get("a", "other").unwrap().as_deref(), Some(&[0, 255][..]));
+    assert_eq!(backend.list("a", "k").unwrap(), vec!["k1", "k2"]);
+    assert_eq!(backend.list("a", "").unwrap(), vec!["k1", "k2", "other"]);
+    assert_eq!(backend.usage("a").unwrap(), KvUsage { keys: 3, bytes: 2 + 3 + 2 + 5 + 2 });
+
+    assert!(backend.delete("a", "k1").unwrap());
+    assert!(!backend.delete("a", "k1").unwrap());
+    assert_eq!(backend.get("a", "k1").unwrap(), None);
+    assert_eq!(backend.get("b", "k1").unwrap().as_deref(), Some(&b"b's"[..]));
+    assert_eq!(backend.list("c", "").unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn kv_memory_backend() {
+    exercise(&MemoryKv::new());
+}
+
+#[test]
+fn kv_file_backend() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    exercise(&FileKv::open(dir.path().join("kv")).expect("open store"));
+
+    let reopened = FileKv::open(dir.path().join("kv")).expect("reopen store");
+    assert_eq!(reopened.get("b", "k1").unwrap().as_deref(), Some(&b"b's"[..]));
+    assert!(reopened.set("../escape", "k", b"v").is_err());
+}
+
+#[test]
+fn kv_store_enforces_quotas() {
+    let backend: Arc<dyn KvBackend> = Arc::new(MemoryKv::new());
+    let quota = KvQuota { max_keys: Some(2), max_bytes: Some(16), max_value_bytes: Some(8) };
+    let kv = KvStore::new(backend.clone(), "m", quota);
+
+    kv.set("a", b"12345678").unwrap();
+    let err = kv.set("b", b"123456789").expect_err("value is too large");
+    assert!(err.to_string().contains("per value"), "unexpected message: {err}");
+    kv.set("b", b"1234").unwrap();
+    let err = kv.set("c", b"").expect_err("key count is exceeded");
+    assert!(err.to_string().contains("maximum of 2 keys"), "unexpected message: {err}");
+
+    // Replacing a value only counts the difference.
+    kv.set("a", b"1234567").unwrap();
+    let err = kv.set("b", b"12345678").expect_err("total size is exceeded");
+    assert!(err.to_string().contains("over the limit of 16 bytes"), "unexpected message: {err}");
+
+    assert!(kv.get("").is_err());
+    assert!(kv.set(&"k".repeat(257), b"").is_err());
+    // Other namespaces have their own quota.
+    KvStore::new(backend, "n", quota).set("c", b"").unwrap();
+}
+
+#[tokio::test]
+async fn api_kv_persists_state_per_module() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("counter.wat"), COUNTER_WAT).expect("write counter.wat");
+    fs::write(root.path().join("other.wat"), COUNTER_WAT).expect("write other.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_kv_dir("state");
+
+    let rt = WasmRuntime::new(cfg.clone()).expect("runtime should initialize");
+    for expected in 1..=2 {
+        let out = rt.run("counter", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+        assert_eq!(out["count"], json!(expected));
+    }
+    let out = rt.run("other", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["count"], json!(1), "modules must not share keys");
+
+    // The file store outlives the runtime.
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let out = rt.run("counter", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["count"], json!(3));
+    assert!(root.path().join("state/counter.kv.json").is_file());
+    assert_eq!(rt.kv("counter").unwrap().get("count").unwrap().as_deref(), Some(&b"3"[..]));
+}
+
+#[tokio::test]
+async fn api_kv_reports_quota_failures() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("counter.wat"), COUNTER_WAT).expect("write counter.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    rt.set_module_config("counter", ConfigOverlay { kv_quota: Some(KvQuota { max_keys: Some(0), ..KvQuota::unlimited() }), ..Default::default() });
+
+    for _ in 0..2 {
+        let out = rt.run("counter", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+        assert_eq!(out["count"], json!(1), "the write should have been refused");
+        let logs = out["__module-logs"].as_array().expect("logs");
+        assert!(logs.iter().any(|l| l.as_str().unwrap_or_default().contains("WARN: [counter] kv_set failed")), "unexpected logs: {logs:?}");
+    }
+}
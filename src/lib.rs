@@ -1,48 +1,206 @@
-use crate::cfg::WasmConfig;
-use anyhow::{Context, Result};
+use crate::call::{CallChain, Calls};
+use crate::cfg::{Mount, WasmConfig};
+use crate::deterministic::WallClock;
+use crate::fetch::HttpPolicy;
+use crate::inspect::{LinkIssue, LinkReport, ModuleInfo};
+use crate::kv::{FileKv, KvBackend, KvStore, MemoryKv};
+use crate::manifest::{Capabilities, Manifest};
+use crate::output::{GuestExit, RunOutput};
+use crate::overlay::{ConfigOverlay, RunOptions};
+use crate::schema::{ModuleSchemas, SchemaTarget};
+use crate::secrets::{EnvSecrets, RedactingWriter, Secrets, SecretsProvider};
+use anyhow::{Context, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use bytes::Bytes;
 use serde_json::Value::{self, Object};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use std::{fs, sync::Mutex};
-use wasmtime::{Config, Engine, Linker, Module, Store};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimitsBuilder};
+use wasmtime_wasi::cli::{AsyncStdinStream, AsyncStdoutStream, StdinStream, StdoutStream};
 use wasmtime_wasi::p2::pipe::{MemoryInputPipe, MemoryOutputPipe};
 use wasmtime_wasi::preview1::add_to_linker_async;
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
 
 mod apifn;
+pub mod builder;
+pub mod call;
 pub mod cfg;
+pub mod cfgfile;
+pub mod codec;
+pub mod deterministic;
+pub mod fetch;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod inspect;
+pub mod kv;
+pub mod manifest;
+pub mod netpolicy;
+pub mod output;
+pub mod overlay;
+pub mod pipeline;
+pub mod schema;
+pub mod secrets;
+#[cfg(feature = "server")]
+pub mod server;
 pub use crate::apifn::{API_NAMESPACE, HostState, output_region, request_bytes, write_error, write_json};
 
 #[cfg(test)]
 mod apifn_ut;
 #[cfg(test)]
+mod builder_ut;
+#[cfg(test)]
+mod call_ut;
+#[cfg(test)]
 mod cfg_ut;
 #[cfg(test)]
+mod cfgfile_ut;
+#[cfg(test)]
+mod codec_ut;
+#[cfg(test)]
+mod deterministic_ut;
+#[cfg(test)]
+mod fetch_ut;
+#[cfg(all(test, feature = "http"))]
+mod http_ut;
+#[cfg(test)]
+mod inspect_ut;
+#[cfg(test)]
+mod kv_ut;
+#[cfg(test)]
 mod lib_ut;
+#[cfg(test)]
+mod manifest_ut;
+#[cfg(test)]
+mod netpolicy_ut;
+#[cfg(test)]
+mod output_ut;
+#[cfg(test)]
+mod overlay_ut;
+#[cfg(test)]
+mod pipeline_ut;
+#[cfg(test)]
+mod schema_ut;
+#[cfg(test)]
+mod secrets_ut;
+#[cfg(all(test, feature = "server"))]
+mod server_ut;
 
 pub struct WasmRuntime {
     engine: Engine,
     cfg: WasmConfig,
     linker: Linker<HostState>,
     modules: Mutex<HashMap<String, Module>>,
+    manifests: Mutex<HashMap<String, Option<Manifest>>>,
+    schemas: Mutex<HashMap<String, Arc<ModuleSchemas>>>,
+    overlays: Mutex<HashMap<String, ConfigOverlay>>,
+    kv: Arc<dyn KvBackend>,
+    secrets: Arc<dyn SecretsProvider>,
     logs: Arc<Mutex<Vec<String>>>,
+    _ticker: EpochTicker,
+}
+
+/// Interval of the engine epoch. Running guests yield back to the async
+/// executor at every tick, so that run timeouts can interrupt busy guests.
+const EPOCH_TICK: Duration = Duration::from_millis(5);
+
+/// Increments the epoch of an engine every `EPOCH_TICK`, until dropped.
+struct EpochTicker {
+    stop: Arc<AtomicBool>,
+}
+
+impl EpochTicker {
+    fn start(engine: Engine) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        std::thread::Builder::new()
+            .name("wasmruntime-epoch".to_string())
+            .spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            })
+            .context("starting the epoch thread")?;
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Make a store's guest yield at every epoch tick, see `EPOCH_TICK`.
+fn yield_on_epoch<T>(store: &mut Store<T>) {
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_async_yield_and_update(1);
 }
 
+/// Bytes of guest output buffered ahead of a streaming writer.
+const STREAM_WRITE_BUDGET: usize = 64 * 1024;
+
+/// The stdio of a module run, plus the payload served by `api.data_read`
+/// when it is buffered.
+struct GuestIo<I, O> {
+    data: Option<Bytes>,
+    stdin: I,
+    stdout: O,
+}
+
+/// Temporary directories backing the ephemeral mounts of a run.
+type Scratch<'c> = Vec<(tempfile::TempDir, &'c Mount)>;
+
 impl WasmRuntime {
     pub fn new(wcfg: WasmConfig) -> Result<Self> {
         let mut cfg = Config::new();
         cfg.async_support(true);
         cfg.cranelift_opt_level(wasmtime::OptLevel::SpeedAndSize);
+        // Fuel is metered for every run, so that limits set by overlays,
+        // manifests or `api.call` budgets work with the shared engine. Runs
+        // without a fuel limit get an unlimited budget.
+        cfg.consume_fuel(true);
+        cfg.epoch_interruption(true);
+        cfg.cranelift_nan_canonicalization(wcfg.get_deterministic().is_some());
 
         let engine = Engine::new(&cfg)?;
+        let ticker = EpochTicker::start(engine.clone())?;
         let mut linker: Linker<HostState> = Linker::new(&engine);
         add_to_linker_async(&mut linker, |cx: &mut HostState| cx.wasi())?;
 
         apifn::fn_api_exec(&mut linker)?;
         apifn::fn_api_log(&mut linker)?;
         apifn::fn_api_header(&mut linker)?;
+        apifn::fn_api_data(&mut linker)?;
+        apifn::fn_api_http_fetch(&mut linker)?;
+        apifn::fn_api_kv(&mut linker)?;
+        apifn::fn_api_secret_get(&mut linker)?;
+        apifn::fn_api_call(&mut linker)?;
+
+        let kv: Arc<dyn KvBackend> = match wcfg.get_kv_dir() {
+            Some(dir) => Arc::new(FileKv::open(dir)?),
+            None => Arc::new(MemoryKv::new()),
+        };
 
-        Ok(Self { engine, linker, cfg: wcfg, modules: Mutex::new(HashMap::new()), logs: Arc::new(Mutex::new(Vec::new())) })
+        Ok(Self {
+            engine,
+            linker,
+            cfg: wcfg,
+            modules: Mutex::new(HashMap::new()),
+            manifests: Mutex::new(HashMap::new()),
+            schemas: Mutex::new(HashMap::new()),
+            overlays: Mutex::new(HashMap::new()),
+            kv,
+            secrets: Arc::new(EnvSecrets::default()),
+            logs: Arc::new(Mutex::new(Vec::new())),
+            _ticker: ticker,
+        })
     }
 
     pub fn extend_linker<F>(&mut self, extend: F) -> Result<()>
@@ -52,13 +210,47 @@ impl WasmRuntime {
         extend(&mut self.linker)
     }
 
+    /// Replace the key/value store behind the `api.kv_*` imports, which
+    /// defaults to `kv::FileKv` when a store directory is configured and to
+    /// `kv::MemoryKv` otherwise.
+    pub fn set_kv_backend(&mut self, backend: Arc<dyn KvBackend>) {
+        self.kv = backend;
+    }
+
+    /// Replace the source of the secrets served by `api.secret_get`, which
+    /// defaults to `secrets::EnvSecrets`.
+    pub fn set_secrets_provider(&mut self, provider: Arc<dyn SecretsProvider>) {
+        self.secrets = provider;
+    }
+
+    /// Get the key/value store of a module, with its configured quota.
+    pub fn kv(&self, id: &str) -> Result<KvStore> {
+        let cfg = self.module_config(id, &RunOptions::default())?;
+        Ok(KvStore::new(self.kv.clone(), id, *cfg.get_kv_quota()))
+    }
+
+    /// Get the engine modules are compiled with.
+    ///
+    /// The engine meters fuel and uses epoch interruption: stores created on
+    /// it need fuel and an epoch deadline before they run any guest code.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Drain the log lines buffered outside of a run, such as those of HTTP
+    /// handlers, see `http::HttpHandlers`. Only the newest lines are kept, see
+    /// `http::MAX_LOG_LINES`. Runs return their own lines under `__module-logs`.
+    pub fn take_logs(&self) -> Vec<String> {
+        self.logs.lock().map(|mut g| std::mem::take(&mut *g)).unwrap_or_default()
+    }
+
     pub fn objects(&self) -> Result<Vec<String>> {
         let mut ids = Vec::new();
         for entry in fs::read_dir(self.cfg.get_root_path())? {
             let entry = entry?;
             if entry.file_type()?.is_file() {
                 let p = entry.path();
-                if p.extension().and_then(|s| s.to_str()) == Some("wasm")
+                if matches!(p.extension().and_then(|s| s.to_str()), Some("wasm") | Some("wat"))
                     && let Some(stem) = p.file_stem().and_then(|s| s.to_str())
                 {
                     ids.push(stem.to_string());
@@ -66,15 +258,44 @@ impl WasmRuntime {
             }
         }
         ids.sort();
+        ids.dedup();
         Ok(ids)
     }
 
-    pub fn precompile_module(&self, id: &str) -> Result<()> {
+    /// Find the file of module `id` in the root directory, `{id}.wasm` or else
+    /// `{id}.wat`, without listing the directory. Ids that are not plain file
+    /// names are never found.
+    pub fn module_path(&self, id: &str) -> Option<PathBuf> {
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            return None;
+        }
+        let root = self.cfg.get_root_path();
+        ["wasm", "wat"].into_iter().map(|ext| root.join(format!("{id}.{ext}"))).find(|p| p.is_file())
+    }
+
+    /// Read the WebAssembly binary for a module.
+    ///
+    /// `{id}.wasm` takes precedence. Otherwise `{id}.wat` is parsed from the text
+    /// format, and parse errors carry the file path, line and column.
+    fn module_bytes(&self, id: &str) -> Result<(PathBuf, Vec<u8>)> {
         let root = self.cfg.get_root_path();
         let wasm_path: PathBuf = root.join(format!("{id}.wasm"));
+        let wat_path: PathBuf = root.join(format!("{id}.wat"));
+
+        if !wasm_path.exists() && wat_path.exists() {
+            let bytes = wat::parse_file(&wat_path).with_context(|| format!("parsing wat file {wat_path:?} for module '{id}'"))?;
+            return Ok((wat_path, bytes));
+        }
+
+        let bytes = std::fs::read(&wasm_path).with_context(|| format!("reading wasm file {wasm_path:?} for module '{id}'"))?;
+        Ok((wasm_path, bytes))
+    }
+
+    pub fn precompile_module(&self, id: &str) -> Result<()> {
+        let root = self.cfg.get_root_path();
         let cwasm_path: PathBuf = root.join(format!("{id}.cwasm"));
 
-        let wasm_bytes = std::fs::read(&wasm_path).with_context(|| format!("reading wasm file {wasm_path:?} for module '{id}'"))?;
+        let (wasm_path, wasm_bytes) = self.module_bytes(id)?;
         let compiled_bytes = self.engine.precompile_module(&wasm_bytes).with_context(|| format!("precompiling module '{id}' from {wasm_path:?}"))?;
 
         if let Some(parent) = cwasm_path.parent() {
@@ -128,51 +349,339 @@ impl WasmRuntime {
         Ok(module)
     }
 
+    /// Describe the imports and exports of a module, with their types.
+    pub fn inspect(&self, id: &str) -> Result<ModuleInfo> {
+        Ok(ModuleInfo::from_module(id, &self.get_or_load_module(id)?))
+    }
+
+    /// Check a module's imports against everything registered in the linker:
+    /// WASI preview1, the `api.*` functions and anything added via `extend_linker`.
+    ///
+    /// Every unresolved import and every signature mismatch is reported.
+    pub fn check(&self, id: &str) -> Result<LinkReport> {
+        let module = self.get_or_load_module(id)?;
+        let wasi = WasiCtxBuilder::new().build_p1();
+        let mut store: Store<HostState> = Store::new(&self.engine, HostState::new(wasi, self.logs.clone(), id.to_string(), Value::Null));
+
+        let mut issues = Vec::new();
+        for import in module.imports() {
+            let expected = import.ty();
+            let (module, name) = (import.module().to_string(), import.name().to_string());
+            match self.linker.get(&mut store, import.module(), import.name()) {
+                None => issues.push(LinkIssue::Unresolved { module, name, expected: inspect::describe(&expected) }),
+                Some(ext) => {
+                    let found = ext.ty(&store);
+                    if !inspect::satisfies(&found, &expected) {
+                        issues.push(LinkIssue::Mismatch { module, name, expected: inspect::describe(&expected), found: inspect::describe(&found) });
+                    }
+                }
+            }
+        }
+
+        Ok(LinkReport { id: id.to_string(), issues })
+    }
+
+    /// Return the capability manifest of a module, if it declares one.
+    ///
+    /// Manifests are read once per module id and cached.
+    pub fn manifest(&self, id: &str) -> Result<Option<Manifest>> {
+        if let Some(m) = self.manifests.lock().unwrap().get(id).cloned() {
+            return Ok(m);
+        }
+
+        // Only cached once the module could be read, it may still be added or fixed.
+        let wasm = self.module_bytes(id).ok().map(|(_, bytes)| bytes);
+        let manifest = Manifest::load(self.cfg.get_root_path(), id, wasm.as_deref())?;
+        if wasm.is_some() {
+            self.manifests.lock().unwrap().insert(id.to_string(), manifest.clone());
+        }
+        Ok(manifest)
+    }
+
+    /// Return the compiled header and output schemas of a module.
+    ///
+    /// Schemas are read and compiled once per module id and cached.
+    pub fn schemas(&self, id: &str) -> Result<Arc<ModuleSchemas>> {
+        if let Some(s) = self.schemas.lock().unwrap().get(id).cloned() {
+            return Ok(s);
+        }
+
+        let wasm = self.module_bytes(id).ok().map(|(_, bytes)| bytes);
+        let schemas = Arc::new(ModuleSchemas::load(self.cfg.get_root_path(), id, wasm.as_deref())?);
+        if wasm.is_some() {
+            self.schemas.lock().unwrap().insert(id.to_string(), schemas.clone());
+        }
+        Ok(schemas)
+    }
+
+    /// Resolve the effective capabilities of a module against the host policy.
+    /// Returns `None` for modules without a manifest.
+    pub fn capabilities(&self, id: &str) -> Result<Option<Capabilities>> {
+        let cfg = self.module_config(id, &RunOptions::default())?;
+        Ok(self.manifest(id)?.map(|m| cfg.get_policy().grant(&m)))
+    }
+
+    /// Register configuration overrides for a module id.
+    ///
+    /// The overlay is applied on top of the runtime configuration for every run
+    /// of that module, replacing any overlay registered before.
+    pub fn set_module_config(&self, id: &str, overlay: ConfigOverlay) {
+        self.overlays.lock().unwrap().insert(id.to_string(), overlay);
+    }
+
+    /// Remove the configuration overrides of a module id.
+    pub fn clear_module_config(&self, id: &str) -> Option<ConfigOverlay> {
+        self.overlays.lock().unwrap().remove(id)
+    }
+
+    /// Compute the effective configuration of a module run: the runtime
+    /// configuration, then the module overlay, then the per-call overrides.
+    pub fn module_config(&self, id: &str, opts: &RunOptions) -> Result<WasmConfig> {
+        let overlay = self.overlays.lock().unwrap().get(id).cloned().unwrap_or_default();
+        let cfg = overlay.merge(&opts.config).apply(&self.cfg)?;
+        if cfg.get_deterministic().is_some() && self.cfg.get_deterministic().is_none() {
+            bail!("module '{id}' runs deterministically, which needs NaN canonicalization: make the runtime configuration deterministic");
+        }
+        Ok(cfg)
+    }
+
     pub async fn run(&self, id: &str, opts: Vec<String>, args: HashMap<String, Value>, data: Vec<u8>) -> Result<Value> {
         self.run_with_header(id, serde_json::json!({ "opts": opts, "args": args }), data).await
     }
 
     pub async fn run_with_header(&self, id: &str, header: Value, data: Vec<u8>) -> Result<Value> {
-        let module = self.get_or_load_module(id)?;
-        let mut input = header.to_string().into_bytes();
-        input.push(b'\n');
+        self.run_with_options(id, header, data, &RunOptions::default()).await
+    }
+
+    /// Run a module like `run_with_header`, with per-call configuration overrides.
+    ///
+    /// Guest stdout is converted according to the configured `OutputMode`.
+    /// When the module declares schemas, the header is validated before the
+    /// module runs and the converted output afterwards. Violations are returned
+    /// as a `SchemaError`.
+    pub async fn run_with_options(&self, id: &str, header: Value, data: Vec<u8>, opts: &RunOptions) -> Result<Value> {
+        self.run_in_chain(id, header, data, opts, &CallChain::default()).await
+    }
+
+    /// Run a module like `run_with_options`, as part of a chain of `api.call`
+    /// invocations.
+    async fn run_in_chain(&self, id: &str, header: Value, data: Vec<u8>, opts: &RunOptions, chain: &CallChain) -> Result<Value> {
+        let cfg = self.module_config(id, opts)?;
+        let (output, secrets) = self.run_buffered(id, &cfg, header, data, chain).await?;
+        let val = output.value(cfg.get_output_mode()).with_context(|| format!("converting output of module '{id}'"))?;
+        let val = secrets.redact_value(val);
+        self.schemas(id)?.check(id, SchemaTarget::Output, &val)?;
+        Ok(output.attach(val))
+    }
+
+    /// Run a module like `run_with_options`, returning the exact bytes it
+    /// wrote to stdout instead of a JSON value. The output is still checked
+    /// against the module's output schema, if it declares one.
+    pub async fn run_output(&self, id: &str, header: Value, data: Vec<u8>, opts: &RunOptions) -> Result<RunOutput> {
+        let cfg = self.module_config(id, opts)?;
+        let (output, _) = self.run_buffered(id, &cfg, header, data, &CallChain::default()).await?;
+        let schemas = self.schemas(id)?;
+        if schemas.has(SchemaTarget::Output) {
+            let val = output.value(cfg.get_output_mode()).with_context(|| format!("converting output of module '{id}'"))?;
+            schemas.check(id, SchemaTarget::Output, &val)?;
+        }
+        Ok(output)
+    }
+
+    /// Run a module with in-memory stdin and stdout. Also returns the secrets
+    /// of the run, to redact the converted output with.
+    async fn run_buffered(&self, id: &str, cfg: &WasmConfig, header: Value, data: Vec<u8>, chain: &CallChain) -> Result<(RunOutput, Arc<Secrets>)> {
+        let mut input = cfg.get_framing().prefix(cfg.get_header_format(), &header, Some(data.len() as u64))?;
+        let start = input.len();
         input.extend_from_slice(&data);
+        let input = Bytes::from(input);
+        let payload = input.slice(start..);
 
-        let stdin = MemoryInputPipe::new(input);
+        let secrets = self.run_secrets(id, cfg)?;
         let stdout = MemoryOutputPipe::new(64 * 1024);
-        let stderr = MemoryOutputPipe::new(64 * 1024);
+        let io = GuestIo { data: Some(payload), stdin: MemoryInputPipe::new(input), stdout: stdout.clone() };
+        let extra = self.execute(id, cfg, &header, &secrets, chain, io).await?;
 
-        let mut wb = wasmtime_wasi::WasiCtxBuilder::new();
-        let mut wb = wb
-            .stdin(stdin)
-            .stdout(stdout.clone())
-            .stderr(stderr.clone())
-            .allow_tcp(self.cfg.get_allow_network())
-            .allow_udp(self.cfg.get_allow_network());
-
-        if self.cfg.get_allow_write() {
-            if std::fs::metadata(self.cfg.get_host_path()).is_err() {
-                std::fs::create_dir_all(self.cfg.get_host_path()).with_context(|| format!("creating host path {:?}", self.cfg.get_host_path()))?;
+        Ok((RunOutput { stdout: secrets.redact(&stdout.contents()), extra }, secrets))
+    }
+
+    /// Run a module with streamed input and output.
+    ///
+    /// The guest reads the header followed by `data` on stdin, and its
+    /// stdout is written to `out` as it is produced, so large payloads never
+    /// have to fit in memory. The returned object holds the collected files
+    /// and logs, as in `run_with_options`, but no `data`.
+    ///
+    /// Secret values are redacted from the stream, so output that could begin
+    /// a secret value is held back until the guest writes past it. Streamed
+    /// output cannot be checked before it is written, so modules that declare
+    /// an output schema are refused.
+    pub async fn run_streaming<R, W>(&self, id: &str, header: Value, data: R, out: W, opts: &RunOptions) -> Result<Value>
+    where
+        R: AsyncRead + Send + Sync + 'static,
+        W: AsyncWrite + Send + Sync + 'static,
+    {
+        let cfg = self.module_config(id, opts)?;
+        if self.schemas(id)?.has(SchemaTarget::Output) {
+            bail!("module '{id}' declares an output schema, which streamed output cannot be checked against, use run_output");
+        }
+        let prefix = cfg.get_framing().prefix(cfg.get_header_format(), &header, None)?;
+
+        let secrets = self.run_secrets(id, &cfg)?;
+        let done = Arc::new(AtomicBool::new(false));
+
+        let stdin = AsyncStdinStream::new(std::io::Cursor::new(prefix).chain(data));
+        let stdout = AsyncStdoutStream::new(STREAM_WRITE_BUDGET, RedactingWriter::new(out, secrets.clone(), done.clone()));
+        let mut sink = Box::into_pin(stdout.async_stream());
+        let extra = self.execute(id, &cfg, &header, &secrets, &CallChain::default(), GuestIo { data: None, stdin, stdout }).await?;
+        done.store(true, Ordering::Release);
+        sink.flush().await.with_context(|| format!("flushing output of module '{id}'"))?;
+
+        Ok(Object(extra))
+    }
+
+    /// Look up the secrets a module run may read.
+    fn run_secrets(&self, id: &str, cfg: &WasmConfig) -> Result<Arc<Secrets>> {
+        let secrets = Secrets::resolve(&*self.secrets, cfg.get_secrets()).with_context(|| format!("resolving secrets of module '{id}'"))?;
+        Ok(Arc::new(secrets))
+    }
+
+    /// Set up everything but stdio in a WASI context: network access, clocks
+    /// and randomness, preopens, argv and environment.
+    ///
+    /// Returns the temporary directories backing ephemeral mounts, which must
+    /// be kept alive until the guest is done, and the virtual wall clock of
+    /// deterministic runs.
+    fn configure_wasi<'c>(
+        &self, wb: &mut WasiCtxBuilder, id: &str, cfg: &'c WasmConfig, caps: Option<&Capabilities>, header: &Value,
+    ) -> Result<(Scratch<'c>, Option<WallClock>)> {
+        let network = caps.map_or(cfg.get_allow_network(), |c| c.network);
+        wb.allow_tcp(network).allow_udp(network);
+        if network && let Some(policy) = cfg.get_network_policy() {
+            policy.apply(wb);
+        }
+        let clock = cfg.get_deterministic().map(|det| det.apply(wb));
+
+        let mut scratch = Vec::new();
+        if let Some(caps) = caps {
+            for p in &caps.preopens {
+                preopen(wb, &p.host, &p.guest, p.dir_perms, p.file_perms, p.write)?;
             }
-            wb = wb.preopened_dir(self.cfg.get_host_path(), self.cfg.get_guest_path(), self.cfg.get_dir_perms(), self.cfg.get_file_perms())?;
+        } else if let Some((dir_perms, file_perms)) = cfg.get_access().perms(cfg.get_dir_perms(), cfg.get_file_perms()) {
+            preopen(wb, cfg.get_host_path(), cfg.get_guest_path(), dir_perms, file_perms, cfg.get_allow_write())?;
         }
+        // Modules with a manifest get host mounts through the policy grants
+        // above. Ephemeral mounts expose no host data, so every module gets them.
+        for m in cfg.get_mounts() {
+            let (dir_perms, file_perms) = m.effective_perms();
+            if m.ephemeral {
+                let dir = tempfile::Builder::new().prefix(&format!("wasmruntime-{id}-")).tempdir().context("creating ephemeral mount")?;
+                preopen(wb, dir.path(), &m.guest_path, dir_perms, file_perms, false)?;
+                scratch.push((dir, m));
+            } else if caps.is_none() {
+                preopen(wb, &m.host_path, &m.guest_path, dir_perms, file_perms, m.create)?;
+            }
+        }
+
+        wb.args(&guest_args(cfg, id, header)).envs(&guest_env(cfg, caps));
+        Ok((scratch, clock))
+    }
+
+    /// Instantiate and run a module with the given stdio, returning the
+    /// collected files and logs to add to its output.
+    ///
+    /// The header must not carry any of the `secrets`, which are redacted
+    /// from the logs and stderr. Runs started by `api.call` get the fuel,
+    /// memory and deadline left in their `chain`, and report the fuel they burnt.
+    async fn execute(
+        &self, id: &str, cfg: &WasmConfig, header: &Value, secrets: &Arc<Secrets>, chain: &CallChain,
+        io: GuestIo<impl StdinStream + 'static, impl StdoutStream + 'static>,
+    ) -> Result<serde_json::Map<String, Value>> {
+        secrets.check_header(header).with_context(|| format!("refusing to run module '{id}'"))?;
+        self.schemas(id)?.check(id, SchemaTarget::Header, header)?;
+        let module = self.get_or_load_module(id)?;
+        let stderr = MemoryOutputPipe::new(64 * 1024);
+
+        let caps = self.manifest(id)?.map(|m| cfg.get_policy().grant(&m));
+
+        let mut wb = WasiCtxBuilder::new();
+        wb.stdin(io.stdin).stdout(io.stdout).stderr(stderr.clone());
+        let (scratch, clock) = self.configure_wasi(&mut wb, id, cfg, caps.as_ref(), header)?;
 
         let wasi = wb.build_p1();
-        let mut store: Store<HostState> = Store::new(&self.engine, HostState::new(wasi, self.logs.clone(), id.to_string(), header.clone()));
+        let limits = caps.as_ref().map_or(*cfg.get_limits(), |c| c.limits.intersect(cfg.get_limits()));
+        let fuel = match (limits.fuel, chain.fuel) {
+            (Some(own), Some(left)) => own.min(left),
+            (own, left) => own.or(left).unwrap_or(u64::MAX),
+        };
+        let deadline = match (limits.timeout().map(|t| Instant::now() + t), chain.deadline) {
+            (Some(own), Some(caller)) => Some(own.min(caller)),
+            (own, caller) => own.or(caller),
+        };
+        let memory = match (limits.memory, chain.memory) {
+            (Some(own), Some(left)) => Some(own.min(left)),
+            (own, left) => own.or(left),
+        };
+
+        let (calls_tx, calls_rx) = tokio::sync::mpsc::channel(1);
+        let mut path = chain.callers.clone();
+        path.push(id.to_string());
+
+        let mut state = HostState::new(wasi, chain.logs.clone(), id.to_string(), header.clone());
+        state.set_allow_exec(caps.as_ref().is_none_or(|c| c.exec));
+        state.set_header_format(cfg.get_header_format());
+        state.set_data(io.data);
+        state.set_clock(clock);
+        state.set_http_policy(http_policy(cfg, caps.as_ref()));
+        state.set_secrets(secrets.clone());
+        state.set_kv(Some(KvStore::new(self.kv.clone(), id, *cfg.get_kv_quota())));
+        state.set_calls(Some(Calls {
+            tx: calls_tx,
+            path,
+            deadline,
+            memory,
+            max_depth: cfg.get_max_call_depth(),
+            callees: cfg.get_callees().to_vec(),
+        }));
+        if let Some(memory) = memory {
+            state.set_store_limits(StoreLimitsBuilder::new().memory_size(memory).build());
+        }
+
+        let mut store: Store<HostState> = Store::new(&self.engine, state);
+        store.limiter(|s| s.limiter());
+        store.set_fuel(fuel)?;
+        yield_on_epoch(&mut store);
 
         let instance = self.linker.instantiate_async(&mut store, &module).await?;
         let start = instance.get_typed_func::<(), ()>(&mut store, "_start").context("module missing _start")?;
 
-        match start.call_async(&mut store, ()).await {
+        let started = Instant::now();
+        let guest = async {
+            let call = start.call_async(&mut store, ());
+            tokio::pin!(call);
+            tokio::select! {
+                outcome = &mut call => outcome,
+                () = self.serve_calls(calls_rx) => call.await,
+            }
+        };
+        let outcome = match deadline {
+            Some(d) => tokio::time::timeout_at(d, guest).await,
+            None => Ok(guest.await),
+        };
+        chain.record_fuel(fuel - store.get_fuel().unwrap_or(0));
+        let outcome = outcome
+            .map_err(|_| anyhow::anyhow!("module '{id}' timed out after {:?}", deadline.unwrap_or(started).saturating_duration_since(started)))?;
+
+        match outcome {
             Ok(()) => {}
             Err(e) => {
                 if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
                     if exit.0 != 0 {
-                        anyhow::bail!("module exited with status {}", exit.0);
+                        return Err(GuestExit { code: exit.0 }.into());
                     }
                 } else if let Some(exit) = e.downcast_ref::<wasi_common::I32Exit>() {
                     if exit.0 != 0 {
-                        anyhow::bail!("module exited with status {}", exit.0);
+                        return Err(GuestExit { code: exit.0 }.into());
                     }
                 } else {
                     return Err(e);
@@ -180,29 +689,105 @@ impl WasmRuntime {
             }
         }
 
-        let text = String::from_utf8(stdout.contents().to_vec())?;
-        let val = if text.trim().is_empty() {
-            serde_json::json!(null)
-        } else {
-            match serde_json::from_str(&text) {
-                Ok(v) => v,
-                Err(_) => serde_json::json!({ "data": text }),
-            }
-        };
-
         let err = stderr.contents();
         if !err.is_empty() {
-            eprintln!("guest stderr:\n{}", String::from_utf8_lossy(&err));
+            eprintln!("guest stderr:\n{}", String::from_utf8_lossy(&secrets.redact(&err)));
+        }
+
+        let mut extra = serde_json::Map::new();
+        let files = collect_files(&scratch, secrets);
+        if !files.is_empty() {
+            extra.insert("__module-files".into(), Object(files));
         }
+        extra.insert("__module-logs".into(), serde_json::json!(store.data().logs()));
 
-        let mut val = match val {
-            Object(o) => Object(o),
-            other => serde_json::json!({ "data": other }),
+        Ok(extra)
+    }
+}
+
+/// Read the requested output files out of ephemeral mounts before they are deleted.
+///
+/// Files are keyed by their guest path. UTF-8 files become JSON strings, anything
+/// else becomes `{ "base64": ... }`. Missing files, names that would escape the
+/// mount and anything reached through a symlink the guest may have planted are
+/// skipped. Secret values are redacted from the contents.
+fn collect_files(scratch: &[(tempfile::TempDir, &Mount)], secrets: &Secrets) -> serde_json::Map<String, Value> {
+    let mut files = serde_json::Map::new();
+    for (dir, m) in scratch {
+        for name in &m.collect {
+            let Some(path) = scratch_file(dir.path(), name) else {
+                continue;
+            };
+            let Ok(bytes) = fs::read(path) else {
+                continue;
+            };
+            let value = match String::from_utf8(secrets.redact(&bytes)) {
+                Ok(text) => Value::String(text),
+                Err(err) => serde_json::json!({ "base64": BASE64.encode(err.into_bytes()) }),
+            };
+            files.insert(format!("{}/{name}", m.guest_path.trim_end_matches('/')), value);
+        }
+    }
+    files
+}
+
+/// The path of `name` in a scratch directory, when every directory on the way
+/// is a real directory and `name` a regular file. Symlinks are not followed.
+fn scratch_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    let mut parts = Path::new(name).components().peekable();
+    while let Some(part) = parts.next() {
+        let Component::Normal(part) = part else {
+            return None;
         };
-        if let Object(ref mut obj) = val {
-            obj.insert("__module-logs".into(), serde_json::json!(store.data().logs()));
+        path.push(part);
+        let meta = fs::symlink_metadata(&path).ok()?;
+        let expected = if parts.peek().is_some() { meta.is_dir() } else { meta.is_file() };
+        if !expected {
+            return None;
         }
+    }
+    Some(path)
+}
+
+/// Build the guest argv: the module id, the configured arguments and, when
+/// enabled, the header `opts`.
+fn guest_args(cfg: &WasmConfig, id: &str, header: &Value) -> Vec<String> {
+    let mut args = vec![id.to_string()];
+    args.extend(cfg.get_args().iter().cloned());
+    if cfg.get_opts_as_argv()
+        && let Some(opts) = header.get("opts").and_then(Value::as_array)
+    {
+        args.extend(opts.iter().map(|o| o.as_str().map_or_else(|| o.to_string(), str::to_string)));
+    }
+    args
+}
+
+/// The policy for outbound HTTP of a run. Modules with a capability manifest
+/// only get it when they are granted network access.
+fn http_policy(cfg: &WasmConfig, caps: Option<&Capabilities>) -> Option<HttpPolicy> {
+    cfg.get_http_policy().filter(|_| caps.is_none_or(|c| c.network)).cloned()
+}
+
+/// Build the guest environment: inherited host variables (or those granted by
+/// the capability manifest), overridden by explicitly configured variables.
+fn guest_env(cfg: &WasmConfig, caps: Option<&Capabilities>) -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = match caps {
+        Some(caps) => caps.env.clone(),
+        None => cfg.get_inherit_env().iter().filter_map(|k| std::env::var(k).ok().map(|v| (k.clone(), v))).collect(),
+    };
+    for (k, v) in cfg.get_env() {
+        env.retain(|(ek, _)| ek != k);
+        env.push((k.clone(), v.clone()));
+    }
+    env
+}
 
-        Ok(val)
+/// Preopen a host directory in the guest, creating it first when `create` is set.
+fn preopen(wb: &mut WasiCtxBuilder, host: &Path, guest: &str, dir_perms: DirPerms, file_perms: FilePerms, create: bool) -> Result<()> {
+    if create && fs::metadata(host).is_err() {
+        fs::create_dir_all(host).with_context(|| format!("creating host path {host:?}"))?;
     }
+    wb.preopened_dir(host, guest, dir_perms, file_perms).with_context(|| format!("preopening host path {host:?} as {guest:?}"))?;
+    Ok(())
 }
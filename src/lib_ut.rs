@@ -1,4 +1,8 @@
-use crate::{WasmRuntime, cfg::WasmConfig};
+use crate::{
+    WasmRuntime,
+    cfg::{AccessMode, Mount, WasmConfig},
+    overlay::RunOptions,
+};
 use serde_json::json;
 use std::{
     collections::HashMap,
@@ -112,6 +116,133 @@ static SILENT_MAIN_RS: &str = r##"
 fn main() {}
 "##;
 
+static HELLO_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "{\"ok\":true}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 16))
+    (i32.store (i32.const 4) (i32.const 11))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"##;
+
+static PREOPENS_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "fd_prestat_get" (func $prestat_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $prestat_dir_name (param i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 32) "\n")
+  (func $print (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+  (func (export "_start")
+    (local $fd i32)
+    (local.set $fd (i32.const 3))
+    (block $done
+      (loop $next
+        (br_if $done (call $prestat_get (local.get $fd) (i32.const 16)))
+        (drop (call $prestat_dir_name (local.get $fd) (i32.const 64) (i32.load (i32.const 20))))
+        (call $print (i32.const 64) (i32.load (i32.const 20)))
+        (call $print (i32.const 32) (i32.const 1))
+        (local.set $fd (i32.add (local.get $fd) (i32.const 1)))
+        (br $next)))))
+"##;
+
+static READWRITE_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "input.txt")
+  (data (i32.const 120) "out.txt")
+  (data (i32.const 140) "{\"errno\":")
+  (data (i32.const 160) ",\"read\":")
+  (data (i32.const 180) "}")
+  (func $print (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+  (func $digit (param $d i32)
+    (i32.store8 (i32.const 200) (i32.add (i32.const 48) (local.get $d)))
+    (call $print (i32.const 200) (i32.const 1)))
+  (func (export "_start")
+    (local $errno i32)
+    (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 100) (i32.const 9) (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 24)))
+    (i32.store (i32.const 32) (i32.const 1024))
+    (i32.store (i32.const 36) (i32.const 256))
+    (drop (call $fd_read (i32.load (i32.const 24)) (i32.const 32) (i32.const 1) (i32.const 12)))
+    (local.set $errno (call $path_open (i32.const 3) (i32.const 0) (i32.const 120) (i32.const 7) (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 28)))
+    (call $print (i32.const 140) (i32.const 9))
+    (if (i32.ge_u (local.get $errno) (i32.const 10))
+      (then (call $digit (i32.div_u (local.get $errno) (i32.const 10)))))
+    (call $digit (i32.rem_u (local.get $errno) (i32.const 10)))
+    (call $print (i32.const 160) (i32.const 8))
+    (call $print (i32.const 1024) (i32.load (i32.const 12)))
+    (call $print (i32.const 180) (i32.const 1))))
+"##;
+
+static SCRATCH_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "result.txt")
+  (data (i32.const 120) "scratch data")
+  (func (export "_start")
+    (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 100) (i32.const 10) (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 24)))
+    (i32.store (i32.const 0) (i32.const 120))
+    (i32.store (i32.const 4) (i32.const 12))
+    (drop (call $fd_write (i32.load (i32.const 24)) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"##;
+
+static ARGV_ENV_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func $print (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+  (func (export "_start")
+    (drop (call $args_sizes_get (i32.const 16) (i32.const 20)))
+    (drop (call $args_get (i32.const 1024) (i32.const 4096)))
+    (call $print (i32.const 4096) (i32.load (i32.const 20)))
+    (drop (call $environ_sizes_get (i32.const 16) (i32.const 20)))
+    (drop (call $environ_get (i32.const 1024) (i32.const 8192)))
+    (call $print (i32.const 8192) (i32.load (i32.const 20)))))
+"##;
+
+static ECHO_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 4096))
+    (i32.store (i32.const 4) (i32.const 4096))
+    (i32.store (i32.const 32) (i32.const 4096))
+    (block $eof
+      (loop $copy
+        (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+        (br_if $eof (i32.eqz (i32.load (i32.const 16))))
+        (i32.store (i32.const 36) (i32.load (i32.const 16)))
+        (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 20)))
+        (br $copy)))))
+"##;
+
+static BROKEN_WAT: &str = r##"(module
+  (func (export "_start")
+    (i32.bogus)))
+"##;
+
 fn wasm_cache_dir() -> &'static Path {
     static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
     CACHE_DIR.get_or_init(|| {
@@ -260,3 +391,294 @@ async fn runtime_wraps_empty_stdout_as_null_data() {
     let out = rt.run_with_header("silent", json!({}), Vec::new()).await.expect("module should run");
     assert_eq!(out, json!({ "data": null, "__module-logs": [] }));
 }
+
+#[test]
+fn runtime_lists_wat_objects_alongside_wasm() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("alpha.wasm"), b"wasm").unwrap_or_else(|err| panic!("failed to write alpha.wasm: {err}"));
+    fs::write(root.path().join("alpha.wat"), HELLO_WAT).unwrap_or_else(|err| panic!("failed to write alpha.wat: {err}"));
+    fs::write(root.path().join("beta.wat"), HELLO_WAT).unwrap_or_else(|err| panic!("failed to write beta.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    assert_eq!(rt.objects().expect("objects should list"), vec!["alpha".to_string(), "beta".to_string()]);
+}
+
+#[test]
+fn runtime_reports_wat_parse_errors_with_position() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("broken.wat"), BROKEN_WAT).unwrap_or_else(|err| panic!("failed to write broken.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let err = format!("{:#}", rt.precompile_module("broken").expect_err("broken wat should not compile"));
+    assert!(err.contains("broken.wat:3:"), "error should point at line 3: {err}");
+    assert!(!root.path().join("broken.cwasm").exists());
+}
+
+#[tokio::test]
+async fn runtime_runs_wat_modules_and_caches_them() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("hello.wat"), HELLO_WAT).unwrap_or_else(|err| panic!("failed to write hello.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run_with_header("hello", json!({}), Vec::new()).await.expect("module should run");
+    assert_eq!(out, json!({ "ok": true, "__module-logs": [] }));
+    assert!(root.path().join("hello.cwasm").exists());
+}
+
+#[tokio::test]
+async fn runtime_preopens_every_mount() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("preopens.wat"), PREOPENS_WAT).unwrap_or_else(|err| panic!("failed to write preopens.wat: {err}"));
+    fs::create_dir(root.path().join("data")).unwrap_or_else(|err| panic!("failed to create data dir: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.add_mount(Mount::new("data", "/data").read_only(true));
+    cfg.add_mount(Mount::new("out", "/out").create(true));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run_with_header("preopens", json!({}), Vec::new()).await.expect("module should run");
+    assert_eq!(out, json!({ "data": "/data\n/out\n", "__module-logs": [] }));
+    assert!(root.path().join("out").is_dir());
+}
+
+#[tokio::test]
+async fn runtime_fails_on_missing_mount_without_create() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("preopens.wat"), PREOPENS_WAT).unwrap_or_else(|err| panic!("failed to write preopens.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.add_mount(Mount::new("missing", "/missing"));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    assert!(rt.run_with_header("preopens", json!({}), Vec::new()).await.is_err());
+}
+
+fn readwrite_runtime(access: AccessMode) -> (TempDir, WasmRuntime) {
+    let root = mk_tmp_runtime_root();
+    let data = root.path().join("data");
+    fs::write(root.path().join("readwrite.wat"), READWRITE_WAT).unwrap_or_else(|err| panic!("failed to write readwrite.wat: {err}"));
+    fs::create_dir(&data).unwrap_or_else(|err| panic!("failed to create data dir: {err}"));
+    fs::write(data.join("input.txt"), "\"hello\"").unwrap_or_else(|err| panic!("failed to write input.txt: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_host_path(&data).expect("host path should be set");
+    cfg.set_guest_path("/");
+    cfg.set_access(access);
+    (root, WasmRuntime::new(cfg).expect("runtime should initialize"))
+}
+
+#[tokio::test]
+async fn runtime_read_only_access_reads_but_refuses_writes() {
+    let (root, rt) = readwrite_runtime(AccessMode::ReadOnly);
+
+    let out = rt.run_with_header("readwrite", json!({}), Vec::new()).await.expect("module should run");
+
+    // wasmtime-wasi reports permission denials from preopen permissions as EPERM (63)
+    assert_eq!(out, json!({ "errno": 63, "read": "hello", "__module-logs": [] }));
+    assert!(!root.path().join("data/out.txt").exists());
+}
+
+#[tokio::test]
+async fn runtime_read_write_access_allows_writes() {
+    let (root, rt) = readwrite_runtime(AccessMode::ReadWrite);
+
+    let out = rt.run_with_header("readwrite", json!({}), Vec::new()).await.expect("module should run");
+    assert_eq!(out, json!({ "errno": 0, "read": "hello", "__module-logs": [] }));
+    assert!(root.path().join("data/out.txt").exists());
+}
+
+#[tokio::test]
+async fn runtime_no_access_preopens_nothing() {
+    let (_root, rt) = readwrite_runtime(AccessMode::None);
+
+    let out = rt.run_with_header("readwrite", json!({}), Vec::new()).await.expect("module should run");
+    assert_ne!(out.get("errno"), Some(&json!(0)));
+}
+
+fn scratch_dirs(id: &str) -> Vec<PathBuf> {
+    fs::read_dir(std::env::temp_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(&format!("wasmruntime-{id}-"))))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[test]
+fn runtime_finds_module_files_by_id() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("text.wat"), "(module)").unwrap_or_else(|err| panic!("failed to write text.wat: {err}"));
+    fs::write(root.path().join("both.wat"), "(module)").unwrap_or_else(|err| panic!("failed to write both.wat: {err}"));
+    fs::write(root.path().join("both.wasm"), wat::parse_str("(module)").expect("wat should parse"))
+        .unwrap_or_else(|err| panic!("failed to write both.wasm: {err}"));
+    fs::create_dir(root.path().join("dir.wasm")).unwrap_or_else(|err| panic!("failed to create dir.wasm: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    assert_eq!(rt.module_path("text"), Some(root.path().join("text.wat")));
+    assert_eq!(rt.module_path("both"), Some(root.path().join("both.wasm")));
+    for id in ["missing", "dir", "", ".hidden", "../text", "sub/text"] {
+        assert_eq!(rt.module_path(id), None, "id {id:?} should not be found");
+    }
+}
+
+#[tokio::test]
+async fn runtime_ephemeral_mount_collects_files_and_cleans_up() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("scratchwriter.wat"), SCRATCH_WAT).unwrap_or_else(|err| panic!("failed to write scratchwriter.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.add_mount(Mount::ephemeral("/out").collect_files(&["result.txt", "missing.txt", "../escape.txt"]));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let (first, second) =
+        tokio::join!(rt.run_with_header("scratchwriter", json!({}), Vec::new()), rt.run_with_header("scratchwriter", json!({}), Vec::new()));
+    for out in [first, second] {
+        assert_eq!(
+            out.expect("module should run"),
+            json!({ "data": null, "__module-files": { "/out/result.txt": "scratch data" }, "__module-logs": [] })
+        );
+    }
+    assert!(scratch_dirs("scratchwriter").is_empty());
+}
+
+/// Links `out.txt` to `{target}/secret.txt` and `sub` to `{target}` in the
+/// first preopen, then writes `result.txt` like `SCRATCH_WAT`.
+fn symlinker_wat(target: &str) -> String {
+    let file = format!("{target}/secret.txt");
+    format!(
+        r#"
+(module
+  (import "wasi_snapshot_preview1" "path_symlink" (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "result.txt")
+  (data (i32.const 120) "scratch data")
+  (data (i32.const 140) "out.txt")
+  (data (i32.const 150) "sub")
+  (data (i32.const 200) "{file}")
+  (data (i32.const 400) "{target}")
+  (func (export "_start")
+    (drop (call $path_symlink (i32.const 200) (i32.const {}) (i32.const 3) (i32.const 140) (i32.const 7)))
+    (drop (call $path_symlink (i32.const 400) (i32.const {}) (i32.const 3) (i32.const 150) (i32.const 3)))
+    (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 100) (i32.const 10) (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 24)))
+    (i32.store (i32.const 0) (i32.const 120))
+    (i32.store (i32.const 4) (i32.const 12))
+    (drop (call $fd_write (i32.load (i32.const 24)) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"#,
+        file.len(),
+        target.len()
+    )
+}
+
+#[tokio::test]
+async fn runtime_ephemeral_mount_does_not_follow_guest_symlinks() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("secret.txt"), "host secret").unwrap_or_else(|err| panic!("failed to write secret.txt: {err}"));
+    // The scratch directory is a sibling of the runtime root in the temp dir.
+    let target = format!("../{}", root.path().file_name().and_then(|n| n.to_str()).expect("utf-8 root name"));
+    fs::write(root.path().join("symlinker.wat"), symlinker_wat(&target)).unwrap_or_else(|err| panic!("failed to write symlinker.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.add_mount(Mount::ephemeral("/out").collect_files(&["result.txt", "out.txt", "sub/secret.txt"]));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run_with_header("symlinker", json!({}), Vec::new()).await.expect("module should run");
+    assert_eq!(out["__module-files"], json!({ "/out/result.txt": "scratch data" }), "unexpected output: {out}");
+}
+
+#[tokio::test]
+async fn runtime_ephemeral_mount_applies_to_modules_with_manifest() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("sandboxedwriter.wat"), SCRATCH_WAT).unwrap_or_else(|err| panic!("failed to write sandboxedwriter.wat: {err}"));
+    fs::write(root.path().join("sandboxedwriter.toml"), "exec = false\n").unwrap_or_else(|err| panic!("failed to write sandboxedwriter.toml: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.add_mount(Mount::ephemeral("/out").collect_files(&["result.txt"]));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run_with_header("sandboxedwriter", json!({}), Vec::new()).await.expect("module should run");
+    assert_eq!(out["__module-files"], json!({ "/out/result.txt": "scratch data" }));
+    assert!(scratch_dirs("sandboxedwriter").is_empty());
+}
+
+#[test]
+fn runtime_builds_guest_args_from_config_and_header_opts() {
+    let mut cfg = WasmConfig::default();
+    cfg.set_args(&["--mode", "batch"]);
+    let header = json!({ "opts": ["--fast", 3], "args": {} });
+
+    assert_eq!(crate::guest_args(&cfg, "demo", &header), vec!["demo", "--mode", "batch"]);
+
+    cfg.set_opts_as_argv(true);
+    assert_eq!(crate::guest_args(&cfg, "demo", &header), vec!["demo", "--mode", "batch", "--fast", "3"]);
+}
+
+#[test]
+fn runtime_builds_guest_env_from_allowlist_and_explicit_values() {
+    let mut cfg = WasmConfig::default();
+    cfg.set_inherit_env(&["PATH", "WASMRUNTIME_UT_SURELY_UNSET"]);
+    cfg.set_env("PATH", "/guest/bin");
+    cfg.set_env("GREETING", "hi");
+
+    assert_eq!(crate::guest_env(&cfg, None), vec![("PATH".to_string(), "/guest/bin".to_string()), ("GREETING".to_string(), "hi".to_string())]);
+}
+
+#[tokio::test]
+async fn runtime_passes_argv_and_env_to_guest() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("argvenv.wat"), ARGV_ENV_WAT).unwrap_or_else(|err| panic!("failed to write argvenv.wat: {err}"));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_opts_as_argv(true);
+    cfg.set_env("GREETING", "hi");
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let out = rt.run("argvenv", vec!["--fast".to_string()], HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out, json!({ "data": "argvenv\u{0}--fast\u{0}GREETING=hi\u{0}", "__module-logs": [] }));
+}
+
+#[tokio::test]
+async fn runtime_streams_stdin_and_stdout() {
+    let root = mk_tmp_runtime_root();
+    fs::write(root.path().join("echo.wat"), ECHO_WAT).unwrap_or_else(|err| panic!("failed to write echo.wat: {err}"));
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    let out_path = root.path().join("echo.out");
+    let out = tokio::fs::File::create(&out_path).await.expect("create output file");
+
+    let header = json!({ "opts": ["--stream"] });
+    let res =
+        rt.run_streaming("echo", header.clone(), std::io::Cursor::new(data.clone()), out, &RunOptions::default()).await.expect("module should run");
+    assert_eq!(res, json!({ "__module-logs": [] }));
+
+    let mut expected = header.to_string().into_bytes();
+    expected.push(b'\n');
+    expected.extend_from_slice(&data);
+    assert_eq!(fs::read(&out_path).expect("read output file"), expected);
+}
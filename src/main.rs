@@ -1,32 +1,243 @@
-use serde_json::json;
-use std::collections::HashMap;
-use wasmruntime::{WasmRuntime, cfg::WasmConfig};
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use wasmruntime::{
+    WasmRuntime,
+    cfg::{AccessMode, WasmConfig},
+    output::GuestExit,
+};
+
+#[cfg(test)]
+mod main_ut;
+
+/// Run WASI modules that take a JSON header on stdin and answer in JSON.
+#[derive(Parser)]
+#[command(name = "wasmruntime", version)]
+struct Cli {
+    /// Configuration file (.toml or .json), the flags below override it
+    #[arg(long, global = true, value_name = "FILE", env = "WASMRUNTIME_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Directory holding the .wasm and .wat modules, defaults to the current directory
+    #[arg(long, global = true)]
+    rootdir: Option<PathBuf>,
+
+    /// Host directory to expose to guests, read-only unless --allow-write is set
+    #[arg(long, global = true)]
+    host_path: Option<PathBuf>,
+
+    /// Where guests see the host directory
+    #[arg(long, global = true)]
+    guest_path: Option<String>,
+
+    /// Let guests write to the host directory, requires --host-path
+    #[arg(long, global = true, requires = "host_path")]
+    allow_write: bool,
+
+    /// Allow guest network access
+    #[arg(long, global = true)]
+    network: bool,
+
+    /// Print compact instead of pretty JSON
+    #[arg(long, global = true)]
+    compact: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the modules in the root directory
+    List,
+
+    /// Run a module and print its output
+    Run {
+        id: String,
+
+        /// Header argument, the value is parsed as JSON or else taken as a string
+        #[arg(long = "arg", value_name = "KEY=VALUE")]
+        args: Vec<String>,
+
+        /// Header option, may be repeated
+        #[arg(long = "opt", value_name = "OPT", allow_hyphen_values = true)]
+        opts: Vec<String>,
+
+        /// JSON file with the base header, --arg and --opt are added to it
+        #[arg(long, value_name = "FILE")]
+        header_file: Option<PathBuf>,
+
+        /// Data file, defaults to stdin when it is not a terminal
+        #[arg(long, value_name = "FILE")]
+        data: Option<PathBuf>,
+    },
+
+    /// Run a pipeline declared in the configuration file and print its output and stage reports
+    Pipeline {
+        name: String,
+
+        /// Pipeline argument, referred to as {{args.KEY}} in stage headers
+        #[arg(long = "arg", value_name = "KEY=VALUE")]
+        args: Vec<String>,
+
+        /// Data file for the first stage, defaults to stdin when it is not a terminal
+        #[arg(long, value_name = "FILE")]
+        data: Option<PathBuf>,
+    },
+
+    /// Precompile modules to .cwasm files
+    Precompile {
+        ids: Vec<String>,
+
+        /// Precompile every module in the root directory
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+    },
+
+    /// Print the imports and exports of a module
+    Inspect { id: String },
+
+    /// Check that every import of a module can be linked, exits with 1 when not
+    Check { id: String },
+}
 
 #[tokio::main]
-pub async fn main() -> anyhow::Result<()> {
-    let mut cfg = WasmConfig::default();
-    cfg.set_rootdir("./wasm_bins");
-    cfg.set_host_path("/tmp/wasm-playground")?;
-    cfg.set_allow_write(true);
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            match err.downcast_ref::<GuestExit>() {
+                Some(exit) => ExitCode::from(u8::try_from(exit.code).unwrap_or(1)),
+                None => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode> {
+    let rt = WasmRuntime::new(config(&cli)?)?;
+    let compact = cli.compact;
+
+    match &cli.command {
+        Command::List => print(&rt.objects()?, compact)?,
+        Command::Run { id, args, opts, header_file, data } => {
+            let header = header(args, opts, header_file.as_ref())?;
+            let data = data_input(data.as_ref())?;
+            print(&rt.run_with_header(id, header, data).await?, compact)?;
+        }
+        Command::Pipeline { name, args, data } => {
+            let args = parse_args(args)?.into_iter().collect();
+            let data = data_input(data.as_ref())?;
+            print(&rt.run_pipeline(name, args, data).await?, compact)?;
+        }
+        Command::Precompile { ids, all } => {
+            let ids = if *all { rt.objects()? } else { ids.clone() };
+            if ids.is_empty() {
+                bail!("nothing to precompile, pass module ids or --all");
+            }
+            for id in &ids {
+                rt.precompile_module(id)?;
+                eprintln!("precompiled {id}");
+            }
+        }
+        Command::Inspect { id } => print(&rt.inspect(id)?, compact)?,
+        Command::Check { id } => {
+            let report = rt.check(id)?;
+            print(&report, compact)?;
+            if !report.is_ok() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Build the runtime configuration from the config file and the global flags,
+/// and validate the result.
+fn config(cli: &Cli) -> Result<WasmConfig> {
+    let mut cfg = match &cli.config {
+        Some(path) => WasmConfig::from_file(path)?,
+        None => WasmConfig::default(),
+    };
+    if let Some(dir) = &cli.rootdir {
+        cfg.set_rootdir(std::path::absolute(dir).with_context(|| format!("resolving rootdir {dir:?}"))?);
+    }
+    if let Some(path) = &cli.host_path {
+        cfg.set_host_path(path)?;
+        cfg.set_access(AccessMode::ReadOnly);
+    }
+    if let Some(path) = &cli.guest_path {
+        cfg.set_guest_path(path);
+    }
+    if cli.allow_write {
+        cfg.set_access(AccessMode::ReadWrite);
+    }
+    if cli.network {
+        cfg.set_allow_network(true);
+    }
+    cfg.validate().context("invalid command line settings")?;
+    Ok(cfg)
+}
+
+/// Build the run header from a header file plus `--arg` and `--opt` flags.
+fn header(args: &[String], opts: &[String], file: Option<&PathBuf>) -> Result<Value> {
+    let mut header = match file {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).with_context(|| format!("reading header file {path:?}"))?;
+            serde_json::from_str(&text).with_context(|| format!("parsing header file {path:?}"))?
+        }
+        None => Value::Object(Map::new()),
+    };
+    let Value::Object(obj) = &mut header else {
+        bail!("header must be a JSON object");
+    };
 
-    let rt = WasmRuntime::new(cfg)?;
-    let ids = rt.objects()?;
-    if ids.is_empty() {
-        println!("no .wasm files found in ./wasm_bins — put one there, e.g. wasm_bins/echo.wasm");
-        return Ok(());
+    if !opts.is_empty() || !obj.contains_key("opts") {
+        obj.insert("opts".into(), opts.iter().cloned().map(Value::String).collect());
     }
-    println!("found: {ids:?}");
+    let Value::Object(header_args) = obj.entry("args").or_insert_with(|| Value::Object(Map::new())) else {
+        bail!("header 'args' must be a JSON object");
+    };
+    header_args.extend(parse_args(args)?);
 
-    // demo inputs
-    let opts = vec!["--demo".into(), "fast".into()];
-    let args: HashMap<_, _> = [("msg".to_string(), json!("hi from host")), ("n".to_string(), json!(3))].into_iter().collect();
+    Ok(header)
+}
 
-    // run the first id
-    for id in &ids {
-        println!("running {id}...");
-        let out = rt.run(id, opts.clone(), args.clone(), Vec::new()).await?;
-        println!("{} -> {}", id, out);
+/// Parse `--arg KEY=VALUE` flags, the value is parsed as JSON or else taken as a string.
+fn parse_args(args: &[String]) -> Result<Map<String, Value>> {
+    let mut parsed = Map::new();
+    for arg in args {
+        let Some((key, value)) = arg.split_once('=') else {
+            bail!("invalid --arg '{arg}', expected KEY=VALUE");
+        };
+        parsed.insert(key.to_string(), serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())));
     }
+    Ok(parsed)
+}
+
+/// Read the run data from a file, or from stdin when it is not a terminal.
+fn data_input(file: Option<&PathBuf>) -> Result<Vec<u8>> {
+    match file {
+        Some(path) => std::fs::read(path).with_context(|| format!("reading data file {path:?}")),
+        None if std::io::stdin().is_terminal() => Ok(Vec::new()),
+        None => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf).context("reading data from stdin")?;
+            Ok(buf)
+        }
+    }
+}
 
+/// Print a value as pretty or compact JSON.
+fn print<T: Serialize>(value: &T, compact: bool) -> Result<()> {
+    let text = if compact { serde_json::to_string(value)? } else { serde_json::to_string_pretty(value)? };
+    println!("{text}");
     Ok(())
 }
@@ -0,0 +1,168 @@
+use crate::{Cli, Command, config, header, parse_args, run};
+use clap::Parser;
+use serde_json::json;
+use std::{fs, process::ExitCode};
+use wasmruntime::cfg::AccessMode;
+
+#[test]
+fn cli_parses_run_flags() {
+    let cli = Cli::try_parse_from([
+        "wasmruntime",
+        "--compact",
+        "run",
+        "hello",
+        "--arg",
+        "n=3",
+        "--arg",
+        "name=bob",
+        "--opt",
+        "-v",
+        "--opt",
+        "--long",
+        "--data",
+        "in.bin",
+    ])
+    .expect("run flags should parse");
+
+    assert!(cli.compact);
+    let Command::Run { id, args, opts, header_file, data } = cli.command else {
+        panic!("expected the run subcommand");
+    };
+    assert_eq!(id, "hello");
+    assert_eq!(args, ["n=3", "name=bob"]);
+    assert_eq!(opts, ["-v", "--long"]);
+    assert!(header_file.is_none());
+    assert_eq!(data.expect("data file").to_str(), Some("in.bin"));
+}
+
+#[test]
+fn cli_allow_write_requires_host_path() {
+    assert!(Cli::try_parse_from(["wasmruntime", "--allow-write", "list"]).is_err());
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let host = dir.path().to_str().expect("utf-8 path");
+    let cli = Cli::try_parse_from(["wasmruntime", "list", "--host-path", host, "--allow-write"]).expect("flags should parse");
+    let cfg = config(&cli).expect("config should build");
+    assert_eq!(cfg.get_host_path(), dir.path());
+    assert_eq!(cfg.get_access(), AccessMode::ReadWrite);
+}
+
+#[test]
+fn cli_host_path_is_read_only_by_default() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let cli = Cli::try_parse_from(["wasmruntime", "--host-path", dir.path().to_str().expect("utf-8 path"), "list"]).expect("flags should parse");
+    assert_eq!(config(&cli).expect("config should build").get_access(), AccessMode::ReadOnly);
+}
+
+#[test]
+fn cli_flags_are_validated() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let host = dir.path().to_str().expect("utf-8 path");
+    let load = |argv: &[&str]| {
+        let cli = Cli::try_parse_from(["wasmruntime"].iter().chain(argv).chain(&["list"])).expect("flags should parse");
+        config(&cli).map_err(|err| format!("{err:#}"))
+    };
+
+    let err = load(&["--host-path", host, "--guest-path", "data"]).expect_err("relative guest path");
+    assert!(err.contains("invalid guest path"), "unexpected error: {err}");
+    let missing = dir.path().join("missing");
+    let err = load(&["--rootdir", missing.to_str().expect("utf-8 path")]).expect_err("missing rootdir");
+    assert!(err.contains("is not an existing directory"), "unexpected error: {err}");
+
+    let file = dir.path().join("wasmruntime.toml");
+    fs::write(&file, format!("rootdir = {host:?}\n\n[[mounts]]\nhost_path = {host:?}\nguest_path = \"/data\"\n")).expect("write config");
+    let err =
+        load(&["--config", file.to_str().expect("utf-8 path"), "--host-path", host, "--guest-path", "/data"]).expect_err("duplicate guest path");
+    assert!(err.contains("preopened twice"), "unexpected error: {err}");
+    assert!(load(&["--config", file.to_str().expect("utf-8 path"), "--host-path", host, "--guest-path", "/in"]).is_ok());
+}
+
+#[test]
+fn header_from_flags() {
+    let built = header(&["n=3".into(), "name=bob".into(), "list=[1,2]".into()], &["-v".into()], None).expect("header should build");
+    assert_eq!(built, json!({ "opts": ["-v"], "args": { "n": 3, "name": "bob", "list": [1, 2] } }));
+}
+
+#[test]
+fn header_flags_extend_header_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let file = dir.path().join("header.json");
+    fs::write(&file, r#"{"opts": ["-q"], "args": {"n": 1, "keep": true}, "extra": "x"}"#).expect("write header file");
+
+    let merged = header(&["n=2".into()], &[], Some(&file)).expect("header should build");
+    assert_eq!(merged, json!({ "opts": ["-q"], "args": { "n": 2, "keep": true }, "extra": "x" }));
+
+    let merged = header(&[], &["-v".into()], Some(&file)).expect("header should build");
+    assert_eq!(merged["opts"], json!(["-v"]));
+}
+
+#[test]
+fn header_rejects_non_objects() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let file = dir.path().join("header.json");
+
+    fs::write(&file, "[1, 2]").expect("write header file");
+    assert!(header(&[], &[], Some(&file)).is_err());
+
+    fs::write(&file, r#"{"args": "n=1"}"#).expect("write header file");
+    assert!(header(&["n=2".into()], &[], Some(&file)).is_err());
+}
+
+#[test]
+fn parse_args_needs_key_value_pairs() {
+    let parsed = parse_args(&["a=1".into(), "b=x=y".into(), "c=".into()]).expect("args should parse");
+    assert_eq!(serde_json::Value::Object(parsed), json!({ "a": 1, "b": "x=y", "c": "" }));
+    assert!(parse_args(&["novalue".into()]).is_err());
+}
+
+/// Prints its data payload.
+static ECHO_WAT: &str = r#"
+(module
+  (import "api" "data_read" (func $read (param i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (call $read (i64.const 0) (i32.const 1024) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+#[tokio::test]
+async fn cli_runs_a_configured_pipeline() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("echo.wat"), ECHO_WAT).expect("write module");
+    let file = dir.path().join("wasmruntime.toml");
+    let text = r#"
+[[pipelines.twice.stages]]
+module = "echo"
+
+[[pipelines.twice.stages]]
+module = "echo"
+"#;
+    fs::write(&file, text).expect("write config");
+    let data = dir.path().join("in.json");
+    let root = dir.path().to_str().expect("utf-8 path");
+    let cli = |name: &str, input: &str| {
+        fs::write(&data, input).expect("write data");
+        let argv = ["wasmruntime", "--config", file.to_str().expect("utf-8 path"), "--rootdir", root, "pipeline", name, "--arg", "n=1"];
+        Cli::try_parse_from(argv.into_iter().chain(["--data", data.to_str().expect("utf-8 path")])).expect("pipeline flags should parse")
+    };
+
+    let parsed = cli("twice", "{}");
+    let Command::Pipeline { name, args, .. } = &parsed.command else {
+        panic!("expected the pipeline subcommand");
+    };
+    assert_eq!((name.as_str(), args.as_slice()), ("twice", ["n=1".to_string()].as_slice()));
+    assert_eq!(run(parsed).await.expect("pipeline should run"), ExitCode::SUCCESS);
+
+    let err = run(cli("missing", "{}")).await.expect_err("unknown pipeline");
+    assert!(format!("{err:#}").contains("unknown pipeline 'missing'"), "unexpected error: {err:#}");
+
+    let mut bad = cli("twice", "{}");
+    let Command::Pipeline { args, .. } = &mut bad.command else {
+        panic!("expected the pipeline subcommand");
+    };
+    args.push("novalue".to_string());
+    let err = run(bad).await.expect_err("invalid pipeline arg");
+    assert!(format!("{err:#}").contains("invalid --arg 'novalue'"), "unexpected error: {err:#}");
+}
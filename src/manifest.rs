@@ -0,0 +1,219 @@
+use crate::cfg::{AccessMode, Limits, WasmConfig};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+/// Name of the custom section that may carry an embedded capability manifest.
+pub const MANIFEST_SECTION: &str = "wasmruntime.manifest";
+
+/// A filesystem capability requested by a module: a guest path and whether
+/// the module needs to write to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsRequest {
+    pub path: String,
+    #[serde(default)]
+    pub write: bool,
+}
+
+/// Capabilities a module declares it needs.
+///
+/// The manifest is TOML, read either from a sidecar `{id}.toml` next to the
+/// module or from the `wasmruntime.manifest` custom section. The sidecar wins
+/// when both are present.
+///
+/// ```toml
+/// network = false
+/// exec = false
+/// env = ["LANG"]
+///
+/// [[fs]]
+/// path = "/data"
+/// write = false
+///
+/// [limits]
+/// fuel = 10000000
+/// memory = 67108864
+/// timeout_ms = 5000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub fs: Vec<FsRequest>,
+    /// Guest sockets, and outbound HTTP when the runtime has an HTTP policy.
+    #[serde(default)]
+    pub network: bool,
+    #[serde(default)]
+    pub exec: bool,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+impl Manifest {
+    /// Parse a manifest from its TOML text.
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).context("parsing capability manifest")
+    }
+
+    /// Load the manifest of module `id` from `root`.
+    ///
+    /// `wasm` is the module binary, used to look up the custom section when
+    /// there is no sidecar file. Returns `None` when the module has no manifest.
+    pub fn load(root: &Path, id: &str, wasm: Option<&[u8]>) -> Result<Option<Self>> {
+        let sidecar = root.join(format!("{id}.toml"));
+        if sidecar.exists() {
+            let text = std::fs::read_to_string(&sidecar).with_context(|| format!("reading manifest {sidecar:?} for module '{id}'"))?;
+            return Self::parse(&text).with_context(|| format!("in manifest {sidecar:?}")).map(Some);
+        }
+
+        match wasm.and_then(|w| custom_section(w, MANIFEST_SECTION)) {
+            Some(section) => {
+                let text = std::str::from_utf8(section).with_context(|| format!("manifest section of module '{id}' is not UTF-8"))?;
+                Self::parse(text).with_context(|| format!("in manifest section of module '{id}'")).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// A filesystem grant: a host directory the host is willing to expose at
+/// `guest`, and whether it may be written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FsGrant {
+    pub host: PathBuf,
+    pub guest: String,
+    pub write: bool,
+}
+
+/// Host-side capability policy.
+///
+/// This is the upper bound of what any module may get. A module's effective
+/// capabilities are the intersection of its manifest and this policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub fs: Vec<FsGrant>,
+    pub network: bool,
+    pub exec: bool,
+    /// Host environment variables that modules may ask for.
+    pub env: Vec<String>,
+    pub limits: Limits,
+}
+
+impl Policy {
+    /// Build a policy equivalent to the global sandbox settings of a config:
+    /// the host path is granted at the guest path unless the access mode is
+    /// none (writable in read-write mode), each non-ephemeral mount is granted
+    /// at its guest path (writable unless read-only), network follows
+    /// `allow_network` and `api.exec` stays available.
+    pub fn from_config(cfg: &WasmConfig) -> Self {
+        let mut fs = Vec::new();
+        if cfg.get_access() != AccessMode::None {
+            fs.push(FsGrant { host: cfg.get_host_path().to_path_buf(), guest: cfg.get_guest_path().to_string(), write: cfg.get_allow_write() });
+        }
+        fs.extend(cfg.get_mounts().iter().filter(|m| !m.ephemeral).map(|m| FsGrant {
+            host: m.host_path.clone(),
+            guest: m.guest_path.clone(),
+            write: !m.read_only,
+        }));
+
+        Self { fs, network: cfg.get_allow_network(), exec: true, env: Vec::new(), limits: Limits::default() }
+    }
+
+    /// Intersect a module manifest with this policy.
+    ///
+    /// Requested paths without a matching grant are dropped, write access is
+    /// only kept when both sides allow it, env vars must be allowlisted and set
+    /// on the host, and the stricter of each limit applies.
+    pub fn grant(&self, manifest: &Manifest) -> Capabilities {
+        let mut preopens = Vec::new();
+        for req in &manifest.fs {
+            if let Some(g) = self.fs.iter().find(|g| g.guest == req.path) {
+                let write = req.write && g.write;
+                let (dir_perms, file_perms) = if write { (DirPerms::all(), FilePerms::all()) } else { (DirPerms::READ, FilePerms::READ) };
+                preopens.push(Preopen { host: g.host.clone(), guest: g.guest.clone(), dir_perms, file_perms, write });
+            }
+        }
+
+        let env = manifest.env.iter().filter(|k| self.env.contains(k)).filter_map(|k| std::env::var(k).ok().map(|v| (k.clone(), v))).collect();
+
+        Capabilities {
+            preopens,
+            network: manifest.network && self.network,
+            exec: manifest.exec && self.exec,
+            env,
+            limits: manifest.limits.intersect(&self.limits),
+        }
+    }
+}
+
+/// A directory to preopen for a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preopen {
+    pub host: PathBuf,
+    pub guest: String,
+    pub dir_perms: DirPerms,
+    pub file_perms: FilePerms,
+    pub write: bool,
+}
+
+/// Effective capabilities of a single module run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub preopens: Vec<Preopen>,
+    pub network: bool,
+    pub exec: bool,
+    pub env: Vec<(String, String)>,
+    pub limits: Limits,
+}
+
+/// Find the payload of a named custom section in a WebAssembly binary.
+pub(crate) fn custom_section<'a>(wasm: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    if wasm.len() < 8 || &wasm[..4] != b"\0asm" {
+        return None;
+    }
+
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let size = read_leb_u32(wasm, &mut pos)? as usize;
+        let end = pos.checked_add(size)?;
+        if end > wasm.len() {
+            return None;
+        }
+
+        if id == 0 {
+            let mut p = pos;
+            let name_len = read_leb_u32(wasm, &mut p)? as usize;
+            let name_end = p.checked_add(name_len)?;
+            if name_end <= end && &wasm[p..name_end] == name.as_bytes() {
+                return Some(&wasm[name_end..end]);
+            }
+        }
+        pos = end;
+    }
+
+    None
+}
+
+/// Decode an unsigned LEB128 `u32`, advancing `pos` past it.
+fn read_leb_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut result = 0u32;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        result |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+        shift += 7;
+        if shift > 28 {
+            return None;
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    WasmRuntime,
+    cfg::{Limits, WasmConfig},
+    manifest::{FsGrant, FsRequest, MANIFEST_SECTION, Manifest, Policy, custom_section},
+};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+static SPIN_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (loop $spin (br $spin))))
+"#;
+
+static EMBEDDED_WAT: &str = r#"
+(module
+  (@custom "wasmruntime.manifest" "network = true\nexec = true\n")
+  (memory (export "memory") 1)
+  (func (export "_start")))
+"#;
+
+#[test]
+fn manifest_parses_all_capabilities() {
+    let m = Manifest::parse(
+        r#"
+network = true
+env = ["LANG"]
+
+[[fs]]
+path = "/data"
+
+[[fs]]
+path = "/out"
+write = true
+
+[limits]
+fuel = 1000
+timeout_ms = 50
+"#,
+    )
+    .expect("manifest should parse");
+
+    assert!(m.network);
+    assert!(!m.exec);
+    assert_eq!(m.env, vec!["LANG".to_string()]);
+    assert_eq!(m.fs, vec![FsRequest { path: "/data".into(), write: false }, FsRequest { path: "/out".into(), write: true }]);
+    assert_eq!(m.limits, Limits { fuel: Some(1000), memory: None, timeout_ms: Some(50) });
+}
+
+#[test]
+fn manifest_rejects_unknown_keys() {
+    assert!(Manifest::parse("teleport = true").is_err());
+}
+
+#[test]
+fn manifest_is_read_from_custom_section() {
+    let wasm = wat::parse_str(EMBEDDED_WAT).expect("wat should parse");
+    assert_eq!(custom_section(&wasm, MANIFEST_SECTION), Some(&b"network = true\nexec = true\n"[..]));
+    assert_eq!(custom_section(&wasm, "missing"), None);
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let m = Manifest::load(dir.path(), "embedded", Some(&wasm)).expect("load should succeed").expect("manifest should exist");
+    assert!(m.network && m.exec);
+}
+
+#[test]
+fn manifest_is_not_cached_before_the_module_can_be_read() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    assert_eq!(rt.manifest("embedded").expect("missing module has no manifest"), None);
+    fs::write(root.path().join("embedded.wat"), "(module").expect("write broken embedded.wat");
+    assert_eq!(rt.manifest("embedded").expect("broken module has no manifest"), None);
+    fs::write(root.path().join("embedded.wat"), EMBEDDED_WAT).expect("write embedded.wat");
+    assert!(rt.manifest("embedded").expect("manifest should load").is_some_and(|m| m.network && m.exec));
+}
+
+#[test]
+fn manifest_sidecar_wins_over_custom_section() {
+    let wasm = wat::parse_str(EMBEDDED_WAT).expect("wat should parse");
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("embedded.toml"), "network = false\n").expect("write sidecar");
+
+    let m = Manifest::load(dir.path(), "embedded", Some(&wasm)).expect("load should succeed").expect("manifest should exist");
+    assert!(!m.network && !m.exec);
+}
+
+#[test]
+fn policy_grants_intersection_of_manifest() {
+    let policy = Policy {
+        fs: vec![
+            FsGrant { host: PathBuf::from("/srv/data"), guest: "/data".into(), write: false },
+            FsGrant { host: PathBuf::from("/srv/out"), guest: "/out".into(), write: true },
+        ],
+        network: false,
+        exec: true,
+        env: vec!["PATH".into()],
+        limits: Limits { fuel: Some(500), memory: None, timeout_ms: Some(1000) },
+    };
+    let manifest = Manifest {
+        fs: vec![
+            FsRequest { path: "/data".into(), write: true },
+            FsRequest { path: "/out".into(), write: true },
+            FsRequest { path: "/etc".into(), write: false },
+        ],
+        network: true,
+        exec: true,
+        env: vec!["PATH".into(), "HOME".into()],
+        limits: Limits { fuel: Some(1000), memory: Some(1 << 20), timeout_ms: None },
+    };
+
+    let caps = policy.grant(&manifest);
+    assert_eq!(caps.preopens.len(), 2);
+    assert_eq!((caps.preopens[0].guest.as_str(), caps.preopens[0].write), ("/data", false));
+    assert_eq!((caps.preopens[0].dir_perms, caps.preopens[0].file_perms), (DirPerms::READ, FilePerms::READ));
+    assert_eq!((caps.preopens[1].guest.as_str(), caps.preopens[1].write), ("/out", true));
+    assert!(!caps.network);
+    assert!(caps.exec);
+    assert!(caps.env.iter().all(|(k, _)| k == "PATH"));
+    assert_eq!(caps.limits, Limits { fuel: Some(500), memory: Some(1 << 20), timeout_ms: Some(1000) });
+}
+
+#[tokio::test]
+async fn manifest_fuel_limit_stops_runaway_guest() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+    fs::write(root.path().join("spin.toml"), "[limits]\nfuel = 100000\n").expect("write spin.toml");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    assert!(rt.run_with_header("spin", json!({}), Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn manifest_timeout_interrupts_runaway_guest() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+    fs::write(root.path().join("spin.toml"), "[limits]\ntimeout_ms = 100\n").expect("write spin.toml");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let err = rt.run_with_header("spin", json!({}), Vec::new()).await.expect_err("run should time out");
+    assert!(err.to_string().contains("timed out"), "unexpected error: {err:#}");
+}
@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use wasmtime_wasi::{SocketAddrUse, WasiCtxBuilder};
+
+/// Fine-grained policy for guest sockets.
+///
+/// Everything is denied by default. A socket address is permitted when its
+/// protocol is enabled, it falls inside one of the allowed CIDR ranges, its
+/// port is allowed (an empty port list allows any port), and, for binding or
+/// listening, binding is enabled.
+///
+/// The policy is enforced through the WASI socket address check, so it covers
+/// `wasi:sockets` (components). Preview 1 modules cannot open sockets at all.
+///
+/// ```
+/// use wasmruntime::netpolicy::NetworkPolicy;
+///
+/// // Reach the internal API on 10.1.2.3:8443 over TCP and nothing else
+/// let policy = NetworkPolicy::new().tcp(true).allow_cidr("10.1.2.3/32").unwrap().allow_port(8443);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetworkPolicy {
+    pub tcp: bool,
+    pub udp: bool,
+    pub name_resolution: bool,
+    pub bind: bool,
+    pub cidrs: Vec<IpNet>,
+    pub ports: Vec<RangeInclusive<u16>>,
+}
+
+impl NetworkPolicy {
+    /// Create a policy that denies everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable or disable TCP.
+    pub fn tcp(mut self, enable: bool) -> Self {
+        self.tcp = enable;
+        self
+    }
+
+    /// Enable or disable UDP.
+    pub fn udp(mut self, enable: bool) -> Self {
+        self.udp = enable;
+        self
+    }
+
+    /// Allow or deny name resolution through `wasi:sockets/ip-name-lookup`.
+    pub fn name_resolution(mut self, enable: bool) -> Self {
+        self.name_resolution = enable;
+        self
+    }
+
+    /// Allow or deny binding (and thereby listening on) local addresses.
+    pub fn bind(mut self, enable: bool) -> Self {
+        self.bind = enable;
+        self
+    }
+
+    /// Allow addresses in a CIDR range, e.g. "10.0.0.0/8" or "::1/128".
+    pub fn allow_cidr(mut self, cidr: &str) -> Result<Self> {
+        self.cidrs.push(cidr.parse().with_context(|| format!("invalid CIDR range '{cidr}'"))?);
+        Ok(self)
+    }
+
+    /// Allow a single port.
+    pub fn allow_port(self, port: u16) -> Self {
+        self.allow_ports(port..=port)
+    }
+
+    /// Allow a range of ports.
+    pub fn allow_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports.push(ports);
+        self
+    }
+
+    /// Check whether a guest may use `addr` for `usage`.
+    pub fn permits(&self, addr: SocketAddr, usage: SocketAddrUse) -> bool {
+        let proto = match usage {
+            SocketAddrUse::TcpBind | SocketAddrUse::TcpConnect => self.tcp,
+            SocketAddrUse::UdpBind | SocketAddrUse::UdpConnect | SocketAddrUse::UdpOutgoingDatagram => self.udp,
+        };
+        let bind = !matches!(usage, SocketAddrUse::TcpBind | SocketAddrUse::UdpBind) || self.bind;
+
+        proto
+            && bind
+            && self.cidrs.iter().any(|net| net.contains(&addr.ip()))
+            && (self.ports.is_empty() || self.ports.iter().any(|r| r.contains(&addr.port())))
+    }
+
+    /// Install this policy on a WASI context builder.
+    pub fn apply(&self, wb: &mut WasiCtxBuilder) {
+        let policy = self.clone();
+        wb.allow_tcp(self.tcp).allow_udp(self.udp).allow_ip_name_lookup(self.name_resolution).socket_addr_check(move |addr, usage| {
+            let permitted = policy.permits(addr, usage);
+            Box::pin(async move { permitted })
+        });
+    }
+}
@@ -0,0 +1,226 @@
+use crate::netpolicy::NetworkPolicy;
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener},
+};
+use wasmtime::{
+    Config, Engine, Store,
+    component::{Component, Linker, ResourceTable},
+};
+use wasmtime_wasi::{SocketAddrUse, WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView};
+
+/// Connects to 127.0.0.1 on the given port through wasi:sockets.
+/// Returns 0 once connected, otherwise the error-code case plus one.
+static CONNECT_WAT: &str = r#"
+(component $C
+  (import "wasi:io/poll@0.2.0" (instance $poll
+    (export "pollable" (type $p (sub resource)))
+    (type $bp (borrow $p))
+    (export "[method]pollable.block" (func (param "self" $bp)))))
+  (alias export $poll "pollable" (type $pollable))
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export "input-stream" (type (sub resource)))
+    (export "output-stream" (type (sub resource)))))
+  (alias export $streams "input-stream" (type $input))
+  (alias export $streams "output-stream" (type $output))
+  (import "wasi:sockets/network@0.2.0" (instance $network
+    (export "network" (type (sub resource)))))
+  (alias export $network "network" (type $net))
+  (import "wasi:sockets/instance-network@0.2.0" (instance $inet
+    (alias outer $C $net (type $n))
+    (export "network" (type $network (eq $n)))
+    (type $own (own $network))
+    (export "instance-network" (func (result $own)))))
+  (import "wasi:sockets/tcp@0.2.0" (instance $tcp
+    (alias outer $C $net (type $n))
+    (alias outer $C $pollable (type $p))
+    (alias outer $C $input (type $in))
+    (alias outer $C $output (type $out))
+    (export "network" (type $network (eq $n)))
+    (export "pollable" (type $pollable (eq $p)))
+    (export "input-stream" (type $input-stream (eq $in)))
+    (export "output-stream" (type $output-stream (eq $out)))
+    (type $error-code (enum "unknown" "access-denied" "not-supported" "invalid-argument" "out-of-memory" "timeout"
+      "concurrency-conflict" "not-in-progress" "would-block" "invalid-state" "new-socket-limit" "address-not-bindable"
+      "address-in-use" "remote-unreachable" "connection-refused" "connection-reset" "connection-aborted"
+      "datagram-too-large" "name-unresolvable" "temporary-resolver-failure" "permanent-resolver-failure"))
+    (export "error-code" (type $ec (eq $error-code)))
+    (type $v4 (tuple u8 u8 u8 u8))
+    (type $v6 (tuple u16 u16 u16 u16 u16 u16 u16 u16))
+    (type $ipv4-socket-address (record (field "port" u16) (field "address" $v4)))
+    (export "ipv4-socket-address" (type $v4sa (eq $ipv4-socket-address)))
+    (type $ipv6-socket-address (record (field "port" u16) (field "flow-info" u32) (field "address" $v6) (field "scope-id" u32)))
+    (export "ipv6-socket-address" (type $v6sa (eq $ipv6-socket-address)))
+    (type $ip-socket-address (variant (case "ipv4" $v4sa) (case "ipv6" $v6sa)))
+    (export "ip-socket-address" (type $addr (eq $ip-socket-address)))
+    (export "tcp-socket" (type $sock (sub resource)))
+    (type $bsock (borrow $sock))
+    (type $bnet (borrow $network))
+    (type $started (result (error $ec)))
+    (export "[method]tcp-socket.start-connect" (func (param "self" $bsock) (param "network" $bnet) (param "remote-address" $addr) (result $started)))
+    (type $own-in (own $input-stream))
+    (type $own-out (own $output-stream))
+    (type $streams (tuple $own-in $own-out))
+    (type $finished (result $streams (error $ec)))
+    (export "[method]tcp-socket.finish-connect" (func (param "self" $bsock) (result $finished)))
+    (type $own-p (own $pollable))
+    (export "[method]tcp-socket.subscribe" (func (param "self" $bsock) (result $own-p)))))
+  (alias export $tcp "tcp-socket" (type $socket))
+  (import "wasi:sockets/tcp-create-socket@0.2.0" (instance $create
+    (alias outer $C $socket (type $s))
+    (export "tcp-socket" (type $tcp-socket (eq $s)))
+    (type $error-code (enum "unknown" "access-denied" "not-supported" "invalid-argument" "out-of-memory" "timeout"
+      "concurrency-conflict" "not-in-progress" "would-block" "invalid-state" "new-socket-limit" "address-not-bindable"
+      "address-in-use" "remote-unreachable" "connection-refused" "connection-reset" "connection-aborted"
+      "datagram-too-large" "name-unresolvable" "temporary-resolver-failure" "permanent-resolver-failure"))
+    (export "error-code" (type $ec (eq $error-code)))
+    (type $ip-address-family (enum "ipv4" "ipv6"))
+    (export "ip-address-family" (type $family (eq $ip-address-family)))
+    (type $own (own $tcp-socket))
+    (type $created (result $own (error $ec)))
+    (export "create-tcp-socket" (func (param "address-family" $family) (result $created)))))
+  (core module $memory
+    (memory (export "memory") 1))
+  (core instance $memory (instantiate $memory))
+  (alias core export $memory "memory" (core memory $mem))
+  (core func $instance-network (canon lower (func $inet "instance-network")))
+  (core func $create-tcp-socket (canon lower (func $create "create-tcp-socket") (memory $mem)))
+  (core func $start-connect (canon lower (func $tcp "[method]tcp-socket.start-connect") (memory $mem)))
+  (core func $finish-connect (canon lower (func $tcp "[method]tcp-socket.finish-connect") (memory $mem)))
+  (core func $subscribe (canon lower (func $tcp "[method]tcp-socket.subscribe")))
+  (core func $block (canon lower (func $poll "[method]pollable.block")))
+  (core module $m
+    (import "env" "memory" (memory 1))
+    (import "wasi" "instance-network" (func $instance-network (result i32)))
+    (import "wasi" "create-tcp-socket" (func $create-tcp-socket (param i32 i32)))
+    (import "wasi" "start-connect" (func $start-connect (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)))
+    (import "wasi" "finish-connect" (func $finish-connect (param i32 i32)))
+    (import "wasi" "subscribe" (func $subscribe (param i32) (result i32)))
+    (import "wasi" "block" (func $block (param i32)))
+    (func (export "connect") (param $port i32) (result i32)
+      (local $net i32) (local $sock i32) (local $ready i32)
+      (local.set $net (call $instance-network))
+      (call $create-tcp-socket (i32.const 0) (i32.const 16))
+      (if (i32.load8_u (i32.const 16))
+        (then (return (i32.add (i32.load8_u (i32.const 20)) (i32.const 1)))))
+      (local.set $sock (i32.load (i32.const 20)))
+      (call $start-connect (local.get $sock) (local.get $net)
+        (i32.const 0) (local.get $port) (i32.const 127) (i32.const 0) (i32.const 0) (i32.const 1)
+        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+        (i32.const 16))
+      (if (i32.load8_u (i32.const 16))
+        (then (return (i32.add (i32.load8_u (i32.const 17)) (i32.const 1)))))
+      (local.set $ready (call $subscribe (local.get $sock)))
+      (loop $wait
+        (call $block (local.get $ready))
+        (call $finish-connect (local.get $sock) (i32.const 16))
+        (if (i32.eqz (i32.load8_u (i32.const 16)))
+          (then (return (i32.const 0))))
+        ;; would-block
+        (br_if $wait (i32.eq (i32.load8_u (i32.const 20)) (i32.const 8))))
+      (i32.add (i32.load8_u (i32.const 20)) (i32.const 1))))
+  (core instance $i (instantiate $m
+    (with "env" (instance $memory))
+    (with "wasi" (instance
+      (export "instance-network" (func $instance-network))
+      (export "create-tcp-socket" (func $create-tcp-socket))
+      (export "start-connect" (func $start-connect))
+      (export "finish-connect" (func $finish-connect))
+      (export "subscribe" (func $subscribe))
+      (export "block" (func $block))))))
+  (func (export "connect") (param "port" u16) (result u8)
+    (canon lift (core func $i "connect"))))
+"#;
+
+/// error-code case `access-denied`, plus one.
+const ACCESS_DENIED: u8 = 2;
+
+struct SocketsState {
+    wasi: WasiCtx,
+    table: ResourceTable,
+}
+
+impl WasiView for SocketsState {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        WasiCtxView { ctx: &mut self.wasi, table: &mut self.table }
+    }
+}
+
+/// Run CONNECT_WAT against `port` with `policy` installed the way the runtime does it.
+async fn guest_connect(policy: &NetworkPolicy, port: u16) -> u8 {
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config).expect("engine");
+    let component = Component::new(&engine, CONNECT_WAT).expect("connect component should compile");
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::p2::add_to_linker_async(&mut linker).expect("wasi linker");
+
+    let mut wb = WasiCtxBuilder::new();
+    policy.apply(&mut wb);
+    let mut store = Store::new(&engine, SocketsState { wasi: wb.build(), table: ResourceTable::new() });
+    let instance = linker.instantiate_async(&mut store, &component).await.expect("instantiate");
+    let connect = instance.get_typed_func::<(u16,), (u8,)>(&mut store, "connect").expect("connect export");
+    let (code,) = connect.call_async(&mut store, (port,)).await.expect("connect call");
+    connect.post_return_async(&mut store).await.expect("post return");
+    code
+}
+
+fn localhost_listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("localhost listener should bind");
+    let addr = listener.local_addr().expect("listener should have an address");
+    (listener, addr)
+}
+
+#[test]
+fn network_policy_denies_everything_by_default() {
+    let (_listener, addr) = localhost_listener();
+    let policy = NetworkPolicy::new();
+
+    assert!(!policy.permits(addr, SocketAddrUse::TcpConnect));
+    assert!(!policy.permits(addr, SocketAddrUse::UdpConnect));
+}
+
+#[test]
+fn network_policy_allows_single_port_on_localhost() {
+    let (_listener, addr) = localhost_listener();
+    let policy = NetworkPolicy::new().tcp(true).allow_cidr("127.0.0.1/32").expect("valid CIDR").allow_port(addr.port());
+
+    assert!(policy.permits(addr, SocketAddrUse::TcpConnect));
+    assert!(!policy.permits(SocketAddr::new(addr.ip(), addr.port().wrapping_add(1)), SocketAddrUse::TcpConnect));
+    assert!(!policy.permits(SocketAddr::new("10.0.0.1".parse().expect("ip"), addr.port()), SocketAddrUse::TcpConnect));
+    assert!(!policy.permits(addr, SocketAddrUse::UdpConnect));
+    assert!(!policy.permits(addr, SocketAddrUse::TcpBind));
+}
+
+#[test]
+fn network_policy_separates_protocols_and_binding() {
+    let (_listener, addr) = localhost_listener();
+    let policy = NetworkPolicy::new().udp(true).bind(true).allow_cidr("127.0.0.0/8").expect("valid CIDR").allow_ports(1024..=65535);
+
+    assert!(policy.permits(addr, SocketAddrUse::UdpConnect));
+    assert!(policy.permits(addr, SocketAddrUse::UdpBind));
+    assert!(policy.permits(addr, SocketAddrUse::UdpOutgoingDatagram));
+    assert!(!policy.permits(addr, SocketAddrUse::TcpConnect));
+    assert!(!policy.permits(SocketAddr::new(addr.ip(), 80), SocketAddrUse::UdpConnect));
+}
+
+#[test]
+fn network_policy_rejects_bad_cidr() {
+    assert!(NetworkPolicy::new().allow_cidr("127.0.0.1/99").is_err());
+    assert!(NetworkPolicy::new().allow_cidr("localhost").is_err());
+}
+
+#[tokio::test]
+async fn network_policy_gates_guest_connections() {
+    let (allowed, allowed_addr) = localhost_listener();
+    let (denied, denied_addr) = localhost_listener();
+    denied.set_nonblocking(true).expect("nonblocking listener");
+    let policy = NetworkPolicy::new().tcp(true).allow_cidr("127.0.0.1/32").expect("valid CIDR").allow_port(allowed_addr.port());
+
+    assert_eq!(guest_connect(&policy, allowed_addr.port()).await, 0);
+    let (_conn, peer) = allowed.accept().expect("guest connection should reach the allowed listener");
+    assert!(peer.ip().is_loopback());
+
+    assert_eq!(guest_connect(&policy, denied_addr.port()).await, ACCESS_DENIED);
+    assert_eq!(denied.accept().map(|_| ()).unwrap_err().kind(), ErrorKind::WouldBlock);
+}
@@ -0,0 +1,85 @@
+use crate::cfg::OutputMode;
+use anyhow::{Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde_json::{Map, Value, json};
+use std::fmt;
+
+/// A guest that exited with a non-zero status.
+///
+/// Returned through `anyhow`, callers can get the status back with
+/// `err.downcast_ref::<GuestExit>()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuestExit {
+    pub code: i32,
+}
+
+impl fmt::Display for GuestExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "module exited with status {}", self.code)
+    }
+}
+
+impl std::error::Error for GuestExit {}
+
+/// The unconverted result of a module run: the exact bytes the guest wrote to
+/// stdout, plus the collected files and logs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunOutput {
+    pub stdout: Vec<u8>,
+    /// `__module-files` and `__module-logs`, as added to the JSON result.
+    pub extra: Map<String, Value>,
+}
+
+impl RunOutput {
+    /// The raw guest stdout.
+    pub fn bytes(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// Take the raw guest stdout.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.stdout
+    }
+
+    /// Lines logged by the guest during the run.
+    pub fn logs(&self) -> Vec<String> {
+        self.extra.get("__module-logs").and_then(|v| serde_json::from_value(v.clone()).ok()).unwrap_or_default()
+    }
+
+    /// Convert into the JSON run result for the given output mode.
+    pub fn into_value(self, mode: OutputMode) -> Result<Value> {
+        let val = self.value(mode)?;
+        Ok(self.attach(val))
+    }
+
+    /// Convert guest stdout for the given output mode, without the collected
+    /// files and logs.
+    pub fn value(&self, mode: OutputMode) -> Result<Value> {
+        let val = match mode {
+            OutputMode::Json => {
+                let Ok(text) = std::str::from_utf8(&self.stdout) else {
+                    bail!("module output is not valid UTF-8, use OutputMode::Raw or OutputMode::Base64 for binary output");
+                };
+                if text.trim().is_empty() { json!(null) } else { serde_json::from_str(text).unwrap_or_else(|_| json!({ "data": text })) }
+            }
+            OutputMode::Raw => match std::str::from_utf8(&self.stdout) {
+                Ok(text) => json!({ "data": text }),
+                Err(_) => json!({ "base64": BASE64.encode(&self.stdout) }),
+            },
+            OutputMode::Base64 => json!({ "base64": BASE64.encode(&self.stdout) }),
+        };
+
+        Ok(match val {
+            Value::Object(o) => Value::Object(o),
+            other => json!({ "data": other }),
+        })
+    }
+
+    /// Add the collected files and logs to a converted output value.
+    pub(crate) fn attach(self, mut val: Value) -> Value {
+        if let Value::Object(ref mut obj) = val {
+            obj.extend(self.extra);
+        }
+        val
+    }
+}
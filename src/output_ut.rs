@@ -0,0 +1,66 @@
+use crate::{
+    WasmRuntime,
+    cfg::{OutputMode, WasmConfig},
+    output::RunOutput,
+    overlay::{ConfigOverlay, RunOptions},
+};
+use serde_json::json;
+use std::fs;
+
+/// Writes four bytes that are not valid UTF-8.
+static BINARY_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 64) "\89PNG")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 64))
+    (i32.store (i32.const 4) (i32.const 4))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"##;
+
+fn output(stdout: &[u8]) -> RunOutput {
+    let mut extra = serde_json::Map::new();
+    extra.insert("__module-logs".into(), json!(["hello"]));
+    RunOutput { stdout: stdout.to_vec(), extra }
+}
+
+#[test]
+fn output_json_mode_parses_or_wraps_text() {
+    assert_eq!(output(br#"{"a":1}"#).into_value(OutputMode::Json).expect("json"), json!({ "a": 1, "__module-logs": ["hello"] }));
+    assert_eq!(output(b"plain").into_value(OutputMode::Json).expect("text"), json!({ "data": "plain", "__module-logs": ["hello"] }));
+    assert_eq!(output(b"").into_value(OutputMode::Json).expect("empty"), json!({ "data": null, "__module-logs": ["hello"] }));
+
+    let err = output(b"\x89PNG").into_value(OutputMode::Json).expect_err("binary output should fail in JSON mode");
+    assert!(err.to_string().contains("not valid UTF-8"), "unexpected error: {err:#}");
+}
+
+#[test]
+fn output_raw_and_base64_modes_keep_bytes() {
+    assert_eq!(output(br#"{"a":1}"#).into_value(OutputMode::Raw).expect("raw"), json!({ "data": "{\"a\":1}", "__module-logs": ["hello"] }));
+    assert_eq!(output(b"\x89PNG").into_value(OutputMode::Raw).expect("raw"), json!({ "base64": "iVBORw==", "__module-logs": ["hello"] }));
+    assert_eq!(output(b"hi").into_value(OutputMode::Base64).expect("base64"), json!({ "base64": "aGk=", "__module-logs": ["hello"] }));
+
+    let out = output(b"\x89PNG");
+    assert_eq!(out.bytes(), b"\x89PNG");
+    assert_eq!(out.logs(), vec!["hello".to_string()]);
+}
+
+#[tokio::test]
+async fn runtime_returns_binary_output() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("binary.wat"), BINARY_WAT).expect("write binary.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let raw = rt.run_output("binary", json!({}), Vec::new(), &RunOptions::default()).await.expect("module should run");
+    assert_eq!(raw.into_bytes(), b"\x89PNG");
+
+    assert!(rt.run_with_options("binary", json!({}), Vec::new(), &RunOptions::default()).await.is_err());
+
+    rt.set_module_config("binary", ConfigOverlay { output_mode: Some(OutputMode::Base64), ..Default::default() });
+    let out = rt.run_with_options("binary", json!({}), Vec::new(), &RunOptions::default()).await.expect("module should run");
+    assert_eq!(out, json!({ "base64": "iVBORw==", "__module-logs": [] }));
+}
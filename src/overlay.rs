@@ -0,0 +1,210 @@
+use crate::cfg::{AccessMode, Limits, Mount, OutputMode, WasmConfig};
+use crate::cfgfile::{opt_env, opt_perms};
+use crate::codec::{Framing, HeaderFormat};
+use crate::deterministic::Deterministic;
+use crate::fetch::HttpPolicy;
+use crate::kv::KvQuota;
+use crate::manifest::Policy;
+use crate::netpolicy::NetworkPolicy;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use wasmtime_wasi::{DirPerms, FilePerms};
+
+/// A partial `WasmConfig`: every field is optional and only set fields
+/// override the configuration they are applied to.
+///
+/// Overlays are registered per module id with `WasmRuntime::set_module_config`
+/// and passed per call through `RunOptions`. The root directory is not part of
+/// an overlay, since it decides where modules are found in the first place.
+///
+/// Overlays are (de)serializable, this is also the format of configuration
+/// files, see `WasmConfig::from_file`. The network policy, deterministic mode
+/// and capability policy can only be set in code.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigOverlay {
+    pub host_path: Option<PathBuf>,
+    pub guest_path: Option<String>,
+    #[serde(with = "opt_perms")]
+    pub dir_perms: Option<DirPerms>,
+    #[serde(with = "opt_perms")]
+    pub file_perms: Option<FilePerms>,
+    pub allow_write: Option<bool>,
+    /// Applied after `allow_write`, so it wins when both are set in the same
+    /// overlay. The two are merged as a pair, see `merge`.
+    pub access: Option<AccessMode>,
+    pub allow_network: Option<bool>,
+    #[serde(skip)]
+    pub network_policy: Option<NetworkPolicy>,
+    pub http_policy: Option<HttpPolicy>,
+    /// Replaces the whole mount list when set.
+    pub mounts: Option<Vec<Mount>>,
+    /// Replaces the extra guest arguments when set.
+    pub args: Option<Vec<String>>,
+    /// Merged per variable: these are set on top of the existing variables.
+    #[serde(with = "opt_env")]
+    pub env: Option<Vec<(String, String)>>,
+    /// Replaces the inherited host variable allowlist when set.
+    pub inherit_env: Option<Vec<String>>,
+    pub opts_as_argv: Option<bool>,
+    pub output_mode: Option<OutputMode>,
+    pub header_format: Option<HeaderFormat>,
+    pub framing: Option<Framing>,
+    pub limits: Option<Limits>,
+    /// Replaces the whole quota when set.
+    pub kv_quota: Option<KvQuota>,
+    /// Replaces the readable secret names when set.
+    pub secrets: Option<Vec<String>>,
+    pub max_call_depth: Option<usize>,
+    /// Replaces the callable module ids when set.
+    pub callees: Option<Vec<String>>,
+    /// Needs a deterministic runtime configuration, see `WasmConfig::set_deterministic`.
+    #[serde(skip)]
+    pub deterministic: Option<Deterministic>,
+    #[serde(skip)]
+    pub policy: Option<Policy>,
+}
+
+impl ConfigOverlay {
+    /// Combine two overlays field by field, `other` wins where both are set.
+    ///
+    /// Limits are merged per limit, so an overlay may only change the fuel
+    /// budget and keep the timeout of the layer below. `allow_write` and
+    /// `access` both set the access mode, so they are taken together from
+    /// `other` when it sets either of them.
+    pub fn merge(&self, other: &ConfigOverlay) -> ConfigOverlay {
+        let access_layer = if other.allow_write.is_some() || other.access.is_some() { other } else { self };
+        ConfigOverlay {
+            host_path: other.host_path.clone().or_else(|| self.host_path.clone()),
+            guest_path: other.guest_path.clone().or_else(|| self.guest_path.clone()),
+            dir_perms: other.dir_perms.or(self.dir_perms),
+            file_perms: other.file_perms.or(self.file_perms),
+            allow_write: access_layer.allow_write,
+            access: access_layer.access,
+            allow_network: other.allow_network.or(self.allow_network),
+            network_policy: other.network_policy.clone().or_else(|| self.network_policy.clone()),
+            http_policy: other.http_policy.clone().or_else(|| self.http_policy.clone()),
+            mounts: other.mounts.clone().or_else(|| self.mounts.clone()),
+            args: other.args.clone().or_else(|| self.args.clone()),
+            env: match (&self.env, &other.env) {
+                (Some(a), Some(b)) => Some(a.iter().filter(|(k, _)| !b.iter().any(|(bk, _)| bk == k)).chain(b).cloned().collect()),
+                (a, b) => b.clone().or_else(|| a.clone()),
+            },
+            inherit_env: other.inherit_env.clone().or_else(|| self.inherit_env.clone()),
+            opts_as_argv: other.opts_as_argv.or(self.opts_as_argv),
+            output_mode: other.output_mode.or(self.output_mode),
+            header_format: other.header_format.or(self.header_format),
+            framing: other.framing.or(self.framing),
+            limits: match (self.limits, other.limits) {
+                (Some(a), Some(b)) => Some(merge_limits(&a, &b)),
+                (a, b) => b.or(a),
+            },
+            kv_quota: other.kv_quota.or(self.kv_quota),
+            secrets: other.secrets.clone().or_else(|| self.secrets.clone()),
+            max_call_depth: other.max_call_depth.or(self.max_call_depth),
+            callees: other.callees.clone().or_else(|| self.callees.clone()),
+            deterministic: other.deterministic.or(self.deterministic),
+            policy: other.policy.clone().or_else(|| self.policy.clone()),
+        }
+    }
+
+    /// Apply the set fields of this overlay on top of `cfg`.
+    pub fn apply(&self, cfg: &WasmConfig) -> Result<WasmConfig> {
+        let mut cfg = cfg.clone();
+        if let Some(p) = &self.host_path {
+            cfg.set_host_path(p)?;
+        }
+        if let Some(p) = &self.guest_path {
+            cfg.set_guest_path(p);
+        }
+        if let Some(p) = self.dir_perms {
+            cfg.set_dir_perms(p);
+        }
+        if let Some(p) = self.file_perms {
+            cfg.set_file_perms(p);
+        }
+        if let Some(allow) = self.allow_write {
+            cfg.set_allow_write(allow);
+        }
+        if let Some(access) = self.access {
+            cfg.set_access(access);
+        }
+        if let Some(allow) = self.allow_network {
+            cfg.set_allow_network(allow);
+        }
+        if let Some(policy) = &self.network_policy {
+            cfg.set_network_policy(policy.clone());
+        }
+        if let Some(policy) = &self.http_policy {
+            cfg.set_http_policy(policy.clone());
+        }
+        if let Some(mounts) = &self.mounts {
+            cfg.set_mounts(mounts.clone());
+        }
+        if let Some(args) = &self.args {
+            cfg.set_args(args);
+        }
+        for (k, v) in self.env.iter().flatten() {
+            cfg.set_env(k, v);
+        }
+        if let Some(names) = &self.inherit_env {
+            cfg.set_inherit_env(names);
+        }
+        if let Some(enable) = self.opts_as_argv {
+            cfg.set_opts_as_argv(enable);
+        }
+        if let Some(mode) = self.output_mode {
+            cfg.set_output_mode(mode);
+        }
+        if let Some(format) = self.header_format {
+            cfg.set_header_format(format);
+        }
+        if let Some(framing) = self.framing {
+            cfg.set_framing(framing);
+        }
+        if let Some(limits) = &self.limits {
+            let merged = merge_limits(cfg.get_limits(), limits);
+            cfg.set_limits(merged);
+        }
+        if let Some(quota) = self.kv_quota {
+            cfg.set_kv_quota(quota);
+        }
+        if let Some(names) = &self.secrets {
+            cfg.set_secrets(names);
+        }
+        if let Some(depth) = self.max_call_depth {
+            cfg.set_max_call_depth(depth);
+        }
+        if let Some(ids) = &self.callees {
+            cfg.set_callees(ids);
+        }
+        if let Some(det) = self.deterministic {
+            cfg.set_deterministic(det);
+        }
+        if let Some(policy) = &self.policy {
+            cfg.set_policy(policy.clone());
+        }
+        Ok(cfg)
+    }
+}
+
+/// Per-limit override: each limit set in `over` replaces the one in `base`.
+fn merge_limits(base: &Limits, over: &Limits) -> Limits {
+    Limits { fuel: over.fuel.or(base.fuel), memory: over.memory.or(base.memory), timeout_ms: over.timeout_ms.or(base.timeout_ms) }
+}
+
+/// Per-call options for `WasmRuntime::run_with_options`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    /// Configuration overrides for this call only. They are applied on top of
+    /// the runtime configuration and the module's registered overlay.
+    pub config: ConfigOverlay,
+}
+
+impl RunOptions {
+    /// Create run options with the given configuration overrides.
+    pub fn with_config(config: ConfigOverlay) -> Self {
+        Self { config }
+    }
+}
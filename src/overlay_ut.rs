@@ -0,0 +1,113 @@
+use crate::{
+    WasmRuntime,
+    cfg::{AccessMode, Limits, WasmConfig},
+    overlay::{ConfigOverlay, RunOptions},
+};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+
+static SPIN_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (loop $spin (br $spin))))
+"#;
+
+#[test]
+fn overlay_merge_prefers_later_fields() {
+    let base = ConfigOverlay {
+        guest_path: Some("/data".into()),
+        allow_write: Some(true),
+        limits: Some(Limits { fuel: Some(10), memory: None, timeout_ms: Some(100) }),
+        ..Default::default()
+    };
+    let over = ConfigOverlay { allow_write: Some(false), limits: Some(Limits { fuel: Some(20), ..Default::default() }), ..Default::default() };
+
+    let merged = base.merge(&over);
+    assert_eq!(merged.guest_path.as_deref(), Some("/data"));
+    assert_eq!(merged.allow_write, Some(false));
+    assert_eq!(merged.limits, Some(Limits { fuel: Some(20), memory: None, timeout_ms: Some(100) }));
+}
+
+#[test]
+fn overlay_merge_keeps_allow_write_and_access_together() {
+    let module = ConfigOverlay { access: Some(AccessMode::ReadOnly), ..Default::default() };
+    let call = ConfigOverlay { allow_write: Some(true), ..Default::default() };
+
+    let merged = module.merge(&call);
+    assert_eq!((merged.allow_write, merged.access), (Some(true), None));
+    let cfg = merged.apply(&WasmConfig::default()).expect("overlay should apply");
+    assert_eq!(cfg.get_access(), AccessMode::ReadWrite, "the per-call allow_write must win over the module access mode");
+
+    let merged = call.merge(&ConfigOverlay { guest_path: Some("/data".into()), ..Default::default() });
+    assert_eq!((merged.allow_write, merged.access), (Some(true), None));
+}
+
+#[test]
+fn overlay_apply_only_touches_set_fields() {
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir("/tmp/wasmruntime-root");
+    cfg.set_allow_network(true);
+
+    let overlay = ConfigOverlay { host_path: Some(PathBuf::from("out")), allow_write: Some(true), ..Default::default() };
+    let out = overlay.apply(&cfg).expect("overlay should apply");
+
+    assert_eq!(out.get_host_path(), PathBuf::from("/tmp/wasmruntime-root/out").as_path());
+    assert!(out.get_allow_write());
+    assert!(out.get_allow_network());
+    assert_eq!(out.get_guest_path(), cfg.get_guest_path());
+}
+
+#[test]
+fn runtime_layers_module_overlay_and_run_options() {
+    let mut cfg = WasmConfig::default();
+    cfg.set_limits(Limits { timeout_ms: Some(1000), ..Default::default() });
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    rt.set_module_config("writer", ConfigOverlay { guest_path: Some("/out".into()), allow_write: Some(true), ..Default::default() });
+
+    let module_cfg = rt.module_config("writer", &RunOptions::default()).expect("config should resolve");
+    assert_eq!(module_cfg.get_guest_path(), "/out");
+    assert!(module_cfg.get_allow_write());
+
+    let opts = RunOptions::with_config(ConfigOverlay { allow_write: Some(false), ..Default::default() });
+    let call_cfg = rt.module_config("writer", &opts).expect("config should resolve");
+    assert_eq!(call_cfg.get_guest_path(), "/out");
+    assert!(!call_cfg.get_allow_write());
+    assert_eq!(call_cfg.get_limits().timeout_ms, Some(1000));
+
+    let other_cfg = rt.module_config("reader", &RunOptions::default()).expect("config should resolve");
+    assert!(!other_cfg.get_allow_write());
+
+    assert!(rt.clear_module_config("writer").is_some());
+    assert!(!rt.module_config("writer", &RunOptions::default()).expect("config should resolve").get_allow_write());
+}
+
+#[tokio::test]
+async fn runtime_applies_per_call_limits() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+
+    let opts = RunOptions::with_config(ConfigOverlay { limits: Some(Limits { timeout_ms: Some(100), ..Default::default() }), ..Default::default() });
+    let err = rt.run_with_options("spin", json!({}), Vec::new(), &opts).await.expect_err("run should time out");
+    assert!(err.to_string().contains("timed out"), "unexpected error: {err:#}");
+}
+
+#[test]
+fn overlay_merges_env_per_variable() {
+    let base = ConfigOverlay { env: Some(vec![("A".into(), "1".into()), ("B".into(), "2".into())]), ..Default::default() };
+    let over = ConfigOverlay { env: Some(vec![("B".into(), "3".into())]), args: Some(vec!["-v".into()]), ..Default::default() };
+
+    let merged = base.merge(&over);
+    assert_eq!(merged.env, Some(vec![("A".into(), "1".into()), ("B".into(), "3".into())]));
+
+    let mut cfg = WasmConfig::default();
+    cfg.set_env("C", "4");
+    let out = merged.apply(&cfg).expect("overlay should apply");
+    assert_eq!(out.get_env(), &[("C".to_string(), "4".to_string()), ("A".to_string(), "1".to_string()), ("B".to_string(), "3".to_string())]);
+    assert_eq!(out.get_args(), &["-v".to_string()]);
+}
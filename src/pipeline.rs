@@ -0,0 +1,238 @@
+use crate::WasmRuntime;
+use crate::overlay::RunOptions;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+/// How a stage receives the output of the stage before it.
+/// - Data: the exact stdout of the previous stage is the data of this stage,
+///   as if the two were connected by a pipe
+/// - Header: the previous output, which must be a JSON object, is merged into the header
+///   args of this stage, which runs without data
+///
+/// The first stage always gets the data passed to the pipeline
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageInput {
+    #[default]
+    Data,
+    Header,
+}
+
+/// One step of a pipeline: a module and the template of its header.
+///
+/// Strings in the header template may refer to the pipeline args as
+/// `{{args.name}}` and to the JSON output of the previous stage as `{{prev}}`
+/// or `{{prev.field.0}}`. A string that is nothing but a reference is replaced
+/// by the referenced JSON value, other strings get its text interpolated.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage {
+    pub module: String,
+    #[serde(default = "default_header")]
+    pub header: Value,
+    #[serde(default)]
+    pub input: StageInput,
+}
+
+impl Stage {
+    /// A stage running `module` with the default header, fed with the stdout
+    /// of the previous stage.
+    pub fn new(module: &str) -> Self {
+        Self { module: module.to_string(), header: default_header(), input: StageInput::Data }
+    }
+
+    /// Set the header template.
+    pub fn header(mut self, header: Value) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Set how the stage receives the previous output.
+    pub fn input(mut self, input: StageInput) -> Self {
+        self.input = input;
+        self
+    }
+}
+
+fn default_header() -> Value {
+    json!({ "opts": [], "args": {} })
+}
+
+/// A named chain of stages, as declared in a configuration file:
+///
+/// ```toml
+/// [[pipelines.thumbnail.stages]]
+/// module = "decode"
+///
+/// [[pipelines.thumbnail.stages]]
+/// module = "resize"
+/// input = "header"
+/// header = { opts = [], args = { width = "{{args.width}}" } }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+/// What one stage of a pipeline run did.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StageReport {
+    pub module: String,
+    /// Lines logged by the module.
+    pub logs: Vec<String>,
+    pub elapsed_ms: u64,
+}
+
+/// The result of a pipeline run: the output of the last stage, without
+/// collected files and logs, and a report per stage.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PipelineOutput {
+    pub output: Value,
+    pub stages: Vec<StageReport>,
+}
+
+/// The stage a pipeline broke at, with the reports of the stages that
+/// completed before it.
+///
+/// Returned as the context of the stage error, callers can get it back with
+/// `err.downcast_ref::<PipelineError>()`. The original error, such as a
+/// `GuestExit` or a `SchemaError`, stays reachable the same way.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PipelineError {
+    /// Index of the failing stage, starting at 0.
+    pub stage: usize,
+    pub module: String,
+    pub completed: Vec<StageReport>,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pipeline stage {} (module '{}') failed", self.stage + 1, self.module)
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+impl WasmRuntime {
+    /// Run `stages` one after the other, feeding each stage the output of the
+    /// previous one, see `StageInput`.
+    ///
+    /// `args` are the values `{{args.name}}` refers to in the header templates,
+    /// and `data` is the data of the first stage. A failing stage ends the run
+    /// with a `PipelineError`.
+    pub async fn pipeline(&self, stages: &[Stage], args: HashMap<String, Value>, data: Vec<u8>) -> Result<PipelineOutput> {
+        if stages.is_empty() {
+            bail!("pipeline has no stages");
+        }
+        let args = Value::Object(args.into_iter().collect());
+
+        let mut reports: Vec<StageReport> = Vec::new();
+        let mut prev: Option<Value> = None;
+        let mut data = data;
+        for (i, stage) in stages.iter().enumerate() {
+            let started = Instant::now();
+            let step = self.run_stage(stage, &args, prev.as_ref(), std::mem::take(&mut data)).await;
+            let (value, stdout, logs) =
+                step.map_err(|err| err.context(PipelineError { stage: i, module: stage.module.clone(), completed: reports.clone() }))?;
+            reports.push(StageReport {
+                module: stage.module.clone(),
+                logs,
+                elapsed_ms: started.elapsed().as_millis().try_into().unwrap_or(u64::MAX),
+            });
+            prev = Some(value);
+            data = stdout;
+        }
+
+        Ok(PipelineOutput { output: prev.unwrap_or(Value::Null), stages: reports })
+    }
+
+    /// Run the pipeline named `name` in the runtime configuration.
+    pub async fn run_pipeline(&self, name: &str, args: HashMap<String, Value>, data: Vec<u8>) -> Result<PipelineOutput> {
+        let Some(pipeline) = self.cfg.get_pipeline(name) else {
+            bail!("unknown pipeline '{name}'");
+        };
+        self.pipeline(&pipeline.stages, args, data).await.with_context(|| format!("in pipeline '{name}'"))
+    }
+
+    /// Run one stage, returning its JSON output, its stdout and its logs.
+    pub(crate) async fn run_stage(&self, stage: &Stage, args: &Value, prev: Option<&Value>, data: Vec<u8>) -> Result<(Value, Vec<u8>, Vec<String>)> {
+        let mut header = render(&stage.header, args, prev).context("rendering the header template")?;
+        let data = match (stage.input, prev) {
+            (StageInput::Header, Some(Value::Object(fields))) => {
+                let Some(obj) = header.as_object_mut() else {
+                    bail!("header template must be a JSON object to take the previous output");
+                };
+                let Value::Object(header_args) = obj.entry("args").or_insert_with(|| Value::Object(Map::new())) else {
+                    bail!("header 'args' must be a JSON object to take the previous output");
+                };
+                header_args.extend(fields.clone());
+                Vec::new()
+            }
+            (StageInput::Header, Some(other)) => bail!("previous output must be a JSON object to go into the header, got {other}"),
+            _ => data,
+        };
+
+        let opts = RunOptions::default();
+        let cfg = self.module_config(&stage.module, &opts)?;
+        let output = self.run_output(&stage.module, header, data, &opts).await?;
+        let value = output.value(cfg.get_output_mode()).with_context(|| format!("converting output of module '{}'", stage.module))?;
+        let logs = output.logs();
+        Ok((value, output.into_bytes(), logs))
+    }
+}
+
+/// Fill in the `{{...}}` references of a header template.
+fn render(template: &Value, args: &Value, prev: Option<&Value>) -> Result<Value> {
+    Ok(match template {
+        Value::String(s) => render_str(s, args, prev)?,
+        Value::Array(items) => Value::Array(items.iter().map(|v| render(v, args, prev)).collect::<Result<_>>()?),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| Ok((k.clone(), render(v, args, prev)?))).collect::<Result<_>>()?),
+        other => other.clone(),
+    })
+}
+
+fn render_str(s: &str, args: &Value, prev: Option<&Value>) -> Result<Value> {
+    if let Some(path) = s.strip_prefix("{{").and_then(|r| r.strip_suffix("}}")).filter(|p| !p.contains("{{")) {
+        return lookup(path.trim(), args, prev).cloned();
+    }
+
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            bail!("unterminated reference in '{s}'");
+        };
+        out.push_str(&rest[..start]);
+        match lookup(rest[start + 2..start + end].trim(), args, prev)? {
+            Value::String(text) => out.push_str(text),
+            other => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(Value::String(out))
+}
+
+/// Resolve a reference such as `args.width` or `prev.items.0`.
+fn lookup<'a>(path: &str, args: &'a Value, prev: Option<&'a Value>) -> Result<&'a Value> {
+    let mut parts = path.split('.');
+    let mut node = match parts.next() {
+        Some("args") => args,
+        Some("prev") => prev.with_context(|| format!("'{path}' refers to the previous stage, the first stage has none"))?,
+        _ => bail!("unknown reference '{path}', expected args.<name> or prev"),
+    };
+    for part in parts {
+        let next = match node {
+            Value::Object(map) => map.get(part),
+            Value::Array(items) => part.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        };
+        node = next.with_context(|| format!("'{path}' is not set"))?;
+    }
+    Ok(node)
+}
@@ -0,0 +1,153 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    output::GuestExit,
+    pipeline::{PipelineError, Stage, StageInput},
+};
+use serde_json::{Value, json};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Logs "echo called" and prints its data payload.
+static ECHO_WAT: &str = r#"
+(module
+  (import "api" "data_read" (func $read (param i64 i32 i32) (result i32)))
+  (import "api" "log" (func $log (param i32 i32 i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "echo called")
+  (func (export "_start")
+    (call $log (i32.const 1) (i32.const 100) (i32.const 11))
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (call $read (i64.const 0) (i32.const 1024) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+/// Prints its header as JSON.
+static HEADER_WAT: &str = r#"
+(module
+  (import "api" "header" (func $header (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (call $header (i32.const 1024) (i32.const 4096)))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))))
+"#;
+
+/// Exits with status 3.
+static FAIL_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (call $exit (i32.const 3))))
+"#;
+
+fn runtime(root: &Path) -> WasmRuntime {
+    for (id, wat) in [("echo", ECHO_WAT), ("header", HEADER_WAT), ("fail", FAIL_WAT)] {
+        fs::write(root.join(format!("{id}.wat")), wat).expect("write module");
+    }
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root);
+    WasmRuntime::new(cfg).expect("runtime should initialize")
+}
+
+#[tokio::test]
+async fn pipeline_pipes_stdout_to_the_next_stage() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let rt = runtime(root.path());
+
+    let out = rt.pipeline(&[Stage::new("echo"), Stage::new("echo")], HashMap::new(), br#"{"n":1}"#.to_vec()).await.expect("pipeline should run");
+    assert_eq!(out.output, json!({ "n": 1 }));
+    assert_eq!(out.stages.len(), 2);
+    for stage in &out.stages {
+        assert_eq!(stage.module, "echo");
+        assert_eq!(stage.logs.len(), 1, "unexpected logs: {:?}", stage.logs);
+        assert!(stage.logs[0].ends_with("INFO: [echo] echo called"), "unexpected logs: {:?}", stage.logs);
+    }
+}
+
+#[tokio::test]
+async fn pipeline_renders_header_templates() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let rt = runtime(root.path());
+
+    let stages = [
+        Stage::new("echo"),
+        Stage::new("header")
+            .header(json!({ "opts": ["{{args.mode}}"], "args": { "width": "{{args.width}}", "label": "n={{prev.n}}, tags={{prev.tags}}" } }))
+            .input(StageInput::Header),
+    ];
+    let args = HashMap::from([("width".to_string(), json!(3)), ("mode".to_string(), json!("fast"))]);
+    let out = rt.pipeline(&stages, args, br#"{"n":1,"tags":["a"]}"#.to_vec()).await.expect("pipeline should run");
+    assert_eq!(
+        out.output,
+        json!({ "opts": ["fast"], "args": { "width": 3, "label": "n=1, tags=[\"a\"]", "n": 1, "tags": ["a"] } }),
+        "the previous output is merged into the header args"
+    );
+}
+
+#[tokio::test]
+async fn pipeline_reports_the_failing_stage() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let rt = runtime(root.path());
+
+    let err =
+        rt.pipeline(&[Stage::new("echo"), Stage::new("fail"), Stage::new("echo")], HashMap::new(), b"{}".to_vec()).await.expect_err("stage 2 fails");
+    let failed = err.downcast_ref::<PipelineError>().expect("pipeline error");
+    assert_eq!((failed.stage, failed.module.as_str(), failed.completed.len()), (1, "fail", 1));
+    assert_eq!(err.downcast_ref::<GuestExit>(), Some(&GuestExit { code: 3 }));
+    assert!(format!("{err:#}").starts_with("pipeline stage 2 (module 'fail') failed"), "unexpected message: {err:#}");
+
+    let stage = Stage::new("header").header(json!({ "args": { "x": "{{prev.n}}" } }));
+    let err = rt.pipeline(&[stage], HashMap::new(), Vec::new()).await.expect_err("the first stage has no previous output");
+    assert_eq!(err.downcast_ref::<PipelineError>().map(|e| e.stage), Some(0));
+    assert!(format!("{err:#}").contains("the first stage has none"), "unexpected message: {err:#}");
+
+    let stages = [Stage::new("echo"), Stage::new("header").header(json!({ "args": { "x": "{{prev.missing}}" } }))];
+    let err = rt.pipeline(&stages, HashMap::new(), b"{}".to_vec()).await.expect_err("missing field");
+    assert!(format!("{err:#}").contains("'prev.missing' is not set"), "unexpected message: {err:#}");
+
+    // Module outputs are objects, but a stage only merges objects into its header.
+    let stage = Stage::new("header").input(StageInput::Header);
+    let err = rt.run_stage(&stage, &json!({}), Some(&json!([1, 2])), Vec::new()).await.expect_err("an array does not go into the header");
+    assert!(format!("{err:#}").contains("previous output must be a JSON object to go into the header, got [1,2]"), "unexpected message: {err:#}");
+
+    assert!(rt.pipeline(&[], HashMap::new(), Vec::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn pipeline_from_config_file() {
+    let root = tempfile::tempdir().expect("tempdir");
+    runtime(root.path());
+    let file = root.path().join("wasmruntime.toml");
+    let text = format!(
+        r#"
+rootdir = {:?}
+
+[[pipelines.wrap.stages]]
+module = "echo"
+
+[[pipelines.wrap.stages]]
+module = "header"
+input = "header"
+header = {{ opts = [], args = {{ name = "{{{{args.name}}}}" }} }}
+"#,
+        root.path().to_str().unwrap()
+    );
+    fs::write(&file, text).expect("write config");
+
+    let cfg = WasmConfig::from_file(&file).expect("config should load");
+    assert_eq!(cfg.get_pipeline("wrap").map(|p| p.stages.len()), Some(2));
+    let rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    let out = rt.run_pipeline("wrap", HashMap::from([("name".to_string(), json!("x"))]), br#"{"n":2}"#.to_vec()).await.expect("pipeline should run");
+    assert_eq!(out.output["args"], json!({ "name": "x", "n": 2 }));
+    assert_eq!(serde_json::to_value(&out).unwrap()["stages"][1]["module"], Value::from("header"));
+
+    let err = rt.run_pipeline("other", HashMap::new(), Vec::new()).await.expect_err("unknown pipeline");
+    assert!(err.to_string().contains("unknown pipeline 'other'"), "unexpected message: {err}");
+
+    fs::write(&file, format!("rootdir = {:?}\n[pipelines.empty]\nstages = []\n", root.path().to_str().unwrap())).expect("write config");
+    let err = WasmConfig::from_file(&file).expect_err("empty pipeline");
+    assert!(format!("{err:#}").contains("pipeline 'empty' has no stages"), "unexpected message: {err:#}");
+}
@@ -0,0 +1,126 @@
+use crate::manifest::custom_section;
+use anyhow::{Context, Result};
+use jsonschema::Validator;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+
+/// What a schema applies to: the run header or the module output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaTarget {
+    Header,
+    Output,
+}
+
+impl SchemaTarget {
+    /// Name used in sidecar files and custom sections.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchemaTarget::Header => "header",
+            SchemaTarget::Output => "output",
+        }
+    }
+
+    /// Sidecar file holding the schema of module `id`, e.g. `hello.header.schema.json`.
+    pub fn sidecar(&self, id: &str) -> String {
+        format!("{id}.{}.schema.json", self.name())
+    }
+
+    /// Custom section holding the schema, e.g. `wasmruntime.schema.header`.
+    pub fn section(&self) -> String {
+        format!("wasmruntime.schema.{}", self.name())
+    }
+}
+
+/// A single schema violation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value, "" for the root.
+    pub pointer: String,
+    /// JSON pointer to the schema keyword that failed.
+    pub schema_path: String,
+    pub message: String,
+}
+
+/// A header or output that does not match the module's schema.
+///
+/// Returned through `anyhow`, callers can get the violations back with
+/// `err.downcast_ref::<SchemaError>()`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SchemaError {
+    pub module: String,
+    pub target: SchemaTarget,
+    pub violations: Vec<SchemaViolation>,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of module '{}' does not match its schema:", self.target.name(), self.module)?;
+        for v in &self.violations {
+            let pointer = if v.pointer.is_empty() { "/" } else { &v.pointer };
+            write!(f, "\n  {pointer}: {}", v.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// Compiled header and output schemas of a module.
+#[derive(Default)]
+pub struct ModuleSchemas {
+    header: Option<Validator>,
+    output: Option<Validator>,
+}
+
+impl ModuleSchemas {
+    /// Load and compile the schemas of module `id` from `root`.
+    ///
+    /// Each schema comes from its sidecar file, or else from its custom section
+    /// in `wasm`. A module without schemas accepts anything.
+    pub fn load(root: &Path, id: &str, wasm: Option<&[u8]>) -> Result<Self> {
+        Ok(Self { header: load_one(root, id, wasm, SchemaTarget::Header)?, output: load_one(root, id, wasm, SchemaTarget::Output)? })
+    }
+
+    /// Return whether the module declares a schema for `target`.
+    pub fn has(&self, target: SchemaTarget) -> bool {
+        self.validator(target).is_some()
+    }
+
+    /// Validate `instance` against the schema for `target`, if there is one.
+    pub fn check(&self, id: &str, target: SchemaTarget, instance: &Value) -> Result<(), SchemaError> {
+        let Some(validator) = self.validator(target) else {
+            return Ok(());
+        };
+        let violations: Vec<SchemaViolation> = validator
+            .iter_errors(instance)
+            .map(|e| SchemaViolation { pointer: e.instance_path.to_string(), schema_path: e.schema_path.to_string(), message: e.to_string() })
+            .collect();
+        if violations.is_empty() { Ok(()) } else { Err(SchemaError { module: id.to_string(), target, violations }) }
+    }
+
+    fn validator(&self, target: SchemaTarget) -> Option<&Validator> {
+        match target {
+            SchemaTarget::Header => self.header.as_ref(),
+            SchemaTarget::Output => self.output.as_ref(),
+        }
+    }
+}
+
+fn load_one(root: &Path, id: &str, wasm: Option<&[u8]>, target: SchemaTarget) -> Result<Option<Validator>> {
+    let sidecar = root.join(target.sidecar(id));
+    let schema: Value = if sidecar.exists() {
+        let text = std::fs::read_to_string(&sidecar).with_context(|| format!("reading schema {sidecar:?} for module '{id}'"))?;
+        serde_json::from_str(&text).with_context(|| format!("parsing schema {sidecar:?}"))?
+    } else {
+        match wasm.and_then(|w| custom_section(w, &target.section())) {
+            Some(section) => serde_json::from_slice(section).with_context(|| format!("parsing {} schema section of module '{id}'", target.name()))?,
+            None => return Ok(None),
+        }
+    };
+
+    let validator = jsonschema::validator_for(&schema).map_err(|e| anyhow::anyhow!("invalid {} schema of module '{id}': {e}", target.name()))?;
+    Ok(Some(validator))
+}
@@ -0,0 +1,122 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    overlay::RunOptions,
+    schema::{ModuleSchemas, SchemaError, SchemaTarget},
+};
+use serde_json::json;
+use std::{collections::HashMap, fs, path::Path};
+
+/// Prints `{"ok":1}`, and declares an output schema that wants a boolean.
+static REPLY_WAT: &str = r##"
+(module
+  (@custom "wasmruntime.schema.output" "{\"type\": \"object\", \"properties\": {\"ok\": {\"type\": \"boolean\"}}, \"required\": [\"ok\"]}")
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 64) "{\"ok\":1}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 64))
+    (i32.store (i32.const 4) (i32.const 8))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"##;
+
+static HEADER_SCHEMA: &str = r#"{
+  "type": "object",
+  "properties": {
+    "args": {
+      "type": "object",
+      "properties": { "name": { "type": "string" }, "count": { "type": "integer", "minimum": 1 } },
+      "required": ["name"]
+    }
+  }
+}"#;
+
+fn runtime(root: &Path) -> WasmRuntime {
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root);
+    WasmRuntime::new(cfg).expect("runtime should initialize")
+}
+
+#[test]
+fn schemas_report_violation_pointers() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("reply.header.schema.json"), HEADER_SCHEMA).expect("write header schema");
+
+    let schemas = ModuleSchemas::load(root.path(), "reply", None).expect("schemas should load");
+    assert!(schemas.has(SchemaTarget::Header));
+    assert!(!schemas.has(SchemaTarget::Output));
+
+    assert!(schemas.check("reply", SchemaTarget::Header, &json!({ "args": { "name": "x", "count": 2 } })).is_ok());
+    assert!(schemas.check("reply", SchemaTarget::Output, &json!("anything")).is_ok());
+
+    let err = schemas.check("reply", SchemaTarget::Header, &json!({ "args": { "name": 5, "count": 0 } })).expect_err("header should be rejected");
+    let mut pointers: Vec<&str> = err.violations.iter().map(|v| v.pointer.as_str()).collect();
+    pointers.sort();
+    assert_eq!(pointers, ["/args/count", "/args/name"]);
+    assert_eq!(err.target, SchemaTarget::Header);
+    assert!(err.to_string().contains("/args/name"), "unexpected message: {err}");
+}
+
+#[test]
+fn schemas_reject_invalid_schema_files() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("bad.output.schema.json"), r#"{"type": 12}"#).expect("write schema");
+    assert!(ModuleSchemas::load(root.path(), "bad", None).is_err());
+}
+
+#[tokio::test]
+async fn runtime_validates_header_before_running() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("reply.header.schema.json"), HEADER_SCHEMA).expect("write header schema");
+    let rt = runtime(root.path());
+
+    // No module file exists: a rejected header must fail before loading it.
+    let err = rt.run_with_header("reply", json!({ "args": {} }), Vec::new()).await.expect_err("header should be rejected");
+    let schema_err = err.downcast_ref::<SchemaError>().expect("error should be a SchemaError");
+    assert_eq!(schema_err.target, SchemaTarget::Header);
+    assert_eq!(schema_err.violations[0].pointer, "/args");
+}
+
+#[tokio::test]
+async fn runtime_validates_output_from_custom_section_schema() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("reply.wat"), REPLY_WAT).expect("write reply.wat");
+    let rt = runtime(root.path());
+
+    let err = rt.run("reply", Vec::new(), HashMap::new(), Vec::new()).await.expect_err("output should be rejected");
+    let schema_err = err.downcast_ref::<SchemaError>().expect("error should be a SchemaError");
+    assert_eq!(schema_err.target, SchemaTarget::Output);
+    assert_eq!(schema_err.violations[0].pointer, "/ok");
+
+    fs::write(root.path().join("reply.output.schema.json"), r#"{"properties": {"ok": {"type": "integer"}}}"#).expect("write output schema");
+    let rt = runtime(root.path());
+    let out = rt.run("reply", Vec::new(), HashMap::new(), Vec::new()).await.expect("sidecar schema should win");
+    assert_eq!(out, json!({ "ok": 1, "__module-logs": [] }));
+}
+
+#[tokio::test]
+async fn schemas_are_not_cached_before_the_module_can_be_read() {
+    let root = tempfile::tempdir().expect("tempdir");
+    let rt = runtime(root.path());
+    rt.schemas("reply").expect("missing module has no schemas");
+
+    fs::write(root.path().join("reply.wat"), REPLY_WAT).expect("write reply.wat");
+    let err = rt.run("reply", Vec::new(), HashMap::new(), Vec::new()).await.expect_err("output should be rejected");
+    assert_eq!(err.downcast_ref::<SchemaError>().map(|e| e.target), Some(SchemaTarget::Output));
+}
+
+#[tokio::test]
+async fn every_entry_point_checks_output_schemas() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("reply.wat"), REPLY_WAT).expect("write reply.wat");
+    let rt = runtime(root.path());
+
+    let err = rt.run_output("reply", json!({}), Vec::new(), &RunOptions::default()).await.expect_err("output should be rejected");
+    assert_eq!(err.downcast_ref::<SchemaError>().map(|e| e.target), Some(SchemaTarget::Output));
+
+    let err = rt
+        .run_streaming("reply", json!({}), tokio::io::empty(), tokio::io::sink(), &RunOptions::default())
+        .await
+        .expect_err("streamed output cannot be checked");
+    assert!(format!("{err:#}").contains("declares an output schema"), "unexpected error: {err:#}");
+}
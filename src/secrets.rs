@@ -0,0 +1,270 @@
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context as TaskContext, Poll, ready};
+use tokio::io::AsyncWrite;
+
+/// Prefix of the environment variables read by `EnvSecrets::default`.
+pub const ENV_SECRET_PREFIX: &str = "WASM_SECRET_";
+
+/// Replacement of secret values in logs and outputs.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Values shorter than this are refused: scrubbing every occurrence of a
+/// two-letter value would garble the output, and not scrubbing it would leak it.
+pub const MIN_REDACTED_LEN: usize = 4;
+
+/// Source of the secrets served by `api.secret_get`.
+///
+/// Modules only get the secrets named in their configuration, see
+/// `WasmConfig::set_secrets`, so a provider may hold more than any single
+/// module is allowed to read.
+pub trait SecretsProvider: Send + Sync {
+    /// Look up a secret, `None` when the provider does not know it.
+    fn get(&self, name: &str) -> Result<Option<String>>;
+}
+
+/// Secrets held in memory, e.g. loaded by the host from a vault at startup.
+#[derive(Clone, Default)]
+pub struct StaticSecrets {
+    values: HashMap<String, String>,
+}
+
+impl StaticSecrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a secret, replacing any previous value.
+    pub fn with(mut self, name: &str, value: &str) -> Self {
+        self.values.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+impl SecretsProvider for StaticSecrets {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.values.get(name).cloned())
+    }
+}
+
+/// Secrets read from host environment variables: the secret "db_password"
+/// is read from `WASM_SECRET_DB_PASSWORD` by default. This is the provider
+/// of a runtime until another one is set.
+#[derive(Clone, Debug)]
+pub struct EnvSecrets {
+    prefix: String,
+}
+
+impl EnvSecrets {
+    pub fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string() }
+    }
+}
+
+impl Default for EnvSecrets {
+    fn default() -> Self {
+        Self::new(ENV_SECRET_PREFIX)
+    }
+}
+
+impl SecretsProvider for EnvSecrets {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(std::env::var(format!("{}{}", self.prefix, name.to_ascii_uppercase())).ok())
+    }
+}
+
+/// The secrets of one module run, looked up when the run starts.
+///
+/// They are served by `api.secret_get` and scrubbed from everything the run
+/// hands back: log lines, stdout, stderr and collected files. Values are also
+/// scrubbed in their JSON-escaped form. Not `Debug`, to keep the values out of
+/// debug output.
+#[derive(Default)]
+pub struct Secrets {
+    values: BTreeMap<String, String>,
+    /// Redacted values and their JSON-escaped forms, longest first so that a
+    /// value containing another one is replaced as a whole.
+    redact: Vec<Vec<u8>>,
+}
+
+impl Secrets {
+    /// Look up every name in `names`, failing when one is unknown or shorter
+    /// than `MIN_REDACTED_LEN`.
+    pub fn resolve(provider: &dyn SecretsProvider, names: &[String]) -> Result<Self> {
+        let mut values = BTreeMap::new();
+        for name in names {
+            let value = provider.get(name).with_context(|| format!("reading secret '{name}'"))?;
+            let Some(value) = value else {
+                bail!("secret '{name}' is not known to the secrets provider");
+            };
+            if value.len() < MIN_REDACTED_LEN {
+                bail!("secret '{name}' is shorter than {MIN_REDACTED_LEN} bytes, too short to be redacted");
+            }
+            values.insert(name.clone(), value);
+        }
+
+        let mut redact = Vec::new();
+        for value in values.values() {
+            let escaped = serde_json::to_string(value)?;
+            let escaped = &escaped[1..escaped.len() - 1];
+            if escaped != value {
+                redact.push(escaped.as_bytes().to_vec());
+            }
+            redact.push(value.as_bytes().to_vec());
+        }
+        redact.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        redact.dedup();
+        Ok(Self { values, redact })
+    }
+
+    /// Get the value of a secret the run may read.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Fail when the header carries a secret value, in any string.
+    pub fn check_header(&self, header: &Value) -> Result<()> {
+        fn strings<'a>(v: &'a Value, out: &mut Vec<&'a str>) {
+            match v {
+                Value::String(s) => out.push(s),
+                Value::Array(items) => items.iter().for_each(|i| strings(i, out)),
+                Value::Object(map) => map.iter().for_each(|(k, v)| {
+                    out.push(k);
+                    strings(v, out);
+                }),
+                _ => {}
+            }
+        }
+
+        let mut found = Vec::new();
+        strings(header, &mut found);
+        for (name, value) in &self.values {
+            if found.iter().any(|s| s.contains(value.as_str())) {
+                bail!("the header contains the value of secret '{name}', modules must read it with api.secret_get");
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace every secret value in `bytes` with `[REDACTED]`.
+    pub fn redact(&self, bytes: &[u8]) -> Vec<u8> {
+        if self.redact.is_empty() {
+            return bytes.to_vec();
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match self.redact.iter().find(|v| bytes[i..].starts_with(v)) {
+                Some(v) => {
+                    out.extend_from_slice(REDACTED.as_bytes());
+                    i += v.len();
+                }
+                None => {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// Replace every secret value in `text` with `[REDACTED]`.
+    pub fn redact_str(&self, text: &str) -> String {
+        // Values are whole UTF-8 strings, so replacing them keeps the text valid.
+        String::from_utf8(self.redact(text.as_bytes())).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    }
+
+    /// Replace every secret value in the strings of a JSON value, object keys
+    /// included. This catches values the guest wrote with JSON escapes.
+    pub fn redact_value(&self, value: Value) -> Value {
+        if self.redact.is_empty() {
+            return value;
+        }
+        match value {
+            Value::String(s) => Value::String(self.redact_str(&s)),
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.redact_value(v)).collect()),
+            Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (self.redact_str(&k), self.redact_value(v))).collect()),
+            other => other,
+        }
+    }
+
+    /// Length of the longest suffix of `bytes` that could be the start of a
+    /// secret value, and must be held back until more output arrives.
+    fn partial_tail(&self, bytes: &[u8]) -> usize {
+        let longest = self.redact.first().map_or(0, Vec::len);
+        (1..longest.min(bytes.len() + 1))
+            .rev()
+            .find(|&n| self.redact.iter().any(|v| v.len() > n && v.starts_with(&bytes[bytes.len() - n..])))
+            .unwrap_or(0)
+    }
+}
+
+/// A writer that redacts secret values from streamed output.
+///
+/// Output that could be the start of a secret value is held back until the
+/// next write decides it, or until the stream is flushed after `done` is set.
+pub(crate) struct RedactingWriter<W> {
+    inner: Pin<Box<W>>,
+    secrets: Arc<Secrets>,
+    pending: Vec<u8>,
+    ready: Vec<u8>,
+    done: Arc<AtomicBool>,
+}
+
+impl<W: AsyncWrite> RedactingWriter<W> {
+    pub(crate) fn new(inner: W, secrets: Arc<Secrets>, done: Arc<AtomicBool>) -> Self {
+        Self { inner: Box::pin(inner), secrets, pending: Vec::new(), ready: Vec::new(), done }
+    }
+
+    /// Write out everything that is ready.
+    fn poll_drain(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while !self.ready.is_empty() {
+            let n = ready!(self.inner.as_mut().poll_write(cx, &self.ready))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.ready.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Move the pending output to the ready output, keeping back `keep` bytes.
+    fn release(&mut self, keep: usize) {
+        let split = self.pending.len() - keep;
+        let scrubbed = self.secrets.redact(&self.pending[..split]);
+        self.ready.extend_from_slice(&scrubbed);
+        self.pending.drain(..split);
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for RedactingWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        this.pending.extend_from_slice(buf);
+        let keep = this.secrets.partial_tail(&this.pending);
+        this.release(keep);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.done.load(Ordering::Acquire) {
+            this.release(0);
+        }
+        ready!(this.poll_drain(cx))?;
+        this.inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.release(0);
+        ready!(this.poll_drain(cx))?;
+        this.inner.as_mut().poll_shutdown(cx)
+    }
+}
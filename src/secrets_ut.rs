@@ -0,0 +1,176 @@
+use crate::{
+    WasmRuntime,
+    cfg::{Mount, WasmConfig},
+    collect_files,
+    overlay::{ConfigOverlay, RunOptions},
+    secrets::{RedactingWriter, Secrets, StaticSecrets},
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, atomic::AtomicBool},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Reads the secret "api_key", logs "using <value>" and prints `{"key":"<value>"}`
+/// in three writes. A secret it may not read leaves the value empty.
+static SECRET_WAT: &str = r#"
+(module
+  (import "api" "secret_get" (func $secret (param i32 i32 i32 i32) (result i32)))
+  (import "api" "log" (func $log (param i32 i32 i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "api_key")
+  (data (i32.const 200) "using ")
+  (data (i32.const 300) "{\"key\":\"")
+  (data (i32.const 400) "\"}")
+  (func (export "_start")
+    (local $n i32)
+    (drop (call $secret (i32.const 100) (i32.const 7) (i32.const 206) (i32.const 64)))
+    (local.set $n (call $secret (i32.const 100) (i32.const 7) (i32.const 308) (i32.const 64)))
+    (local.set $n (select (local.get $n) (i32.const 0) (i32.ge_s (local.get $n) (i32.const 0))))
+    (call $log (i32.const 1) (i32.const 200) (i32.add (i32.const 6) (local.get $n)))
+    (i32.store (i32.const 0) (i32.const 300))
+    (i32.store (i32.const 4) (i32.const 8))
+    (i32.store (i32.const 8) (i32.const 308))
+    (i32.store (i32.const 12) (local.get $n))
+    (i32.store (i32.const 16) (i32.const 400))
+    (i32.store (i32.const 20) (i32.const 2))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 24)))
+    (drop (call $fd_write (i32.const 1) (i32.const 8) (i32.const 1) (i32.const 24)))
+    (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))))
+"#;
+
+/// Prints the secret "sk-live-1234" with its dash as a JSON escape.
+static ESCAPED_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "{\"key\":\"sk-live\\u002d1234\"}")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 100))
+    (i32.store (i32.const 4) (i32.const 27))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"#;
+
+fn secrets(pairs: &[(&str, &str)]) -> Secrets {
+    let provider = pairs.iter().fold(StaticSecrets::new(), |p, (name, value)| p.with(name, value));
+    let names: Vec<String> = pairs.iter().map(|(name, _)| name.to_string()).collect();
+    Secrets::resolve(&provider, &names).expect("secrets should resolve")
+}
+
+#[test]
+fn secrets_redact_known_values() {
+    let s = secrets(&[("token", "s3cr3t"), ("long", "s3cr3t-and-more")]);
+    assert_eq!(s.get("token"), Some("s3cr3t"));
+    assert_eq!(s.get("other"), None);
+
+    assert_eq!(s.redact_str("a s3cr3t, s3cr3t-and-more, abc"), "a [REDACTED], [REDACTED], abc");
+    assert_eq!(s.redact(b"\xffs3cr3t\xff"), b"\xff[REDACTED]\xff".to_vec());
+
+    let err = s.check_header(&json!({ "opts": [], "args": { "auth": ["Bearer s3cr3t"] } })).expect_err("header carries a secret");
+    assert!(err.to_string().contains("secret 'token'"), "unexpected message: {err}");
+    assert!(s.check_header(&json!({ "args": { "n": "abc" } })).is_ok());
+
+    let err = Secrets::resolve(&StaticSecrets::new(), &["missing".to_string()]).err().expect("unknown secret");
+    assert!(err.to_string().contains("secret 'missing' is not known"), "unexpected message: {err}");
+
+    let err = Secrets::resolve(&StaticSecrets::new().with("pin", "123"), &["pin".to_string()]).err().expect("short secret");
+    assert!(err.to_string().contains("secret 'pin' is shorter than 4 bytes"), "unexpected message: {err}");
+}
+
+#[test]
+fn secrets_redact_json_escaped_values() {
+    let s = secrets(&[("quoted", r#"pa"ss\word"#)]);
+    let json = serde_json::to_string(&json!({ "k": r#"pa"ss\word"# })).expect("serialize");
+    assert_eq!(s.redact_str(&json), r#"{"k":"[REDACTED]"}"#);
+
+    let value = json!({ "a": [r#"x pa"ss\word"#, 1], r#"pa"ss\word"#: null });
+    assert_eq!(s.redact_value(value), json!({ "a": ["x [REDACTED]", 1], "[REDACTED]": null }));
+}
+
+#[test]
+fn secrets_redact_collected_files() {
+    let s = secrets(&[("token", "s3cr3t")]);
+    let dir = tempfile::tempdir().expect("tempdir");
+    fs::write(dir.path().join("out.txt"), "token=s3cr3t").expect("write out.txt");
+    fs::write(dir.path().join("out.bin"), b"\xff s3cr3t").expect("write out.bin");
+    let mount = Mount::ephemeral("/out").collect_files(&["out.txt", "out.bin"]);
+
+    let files = collect_files(&[(dir, &mount)], &s);
+    assert_eq!(files["/out/out.txt"], json!("token=[REDACTED]"));
+    let binary = files["/out/out.bin"]["base64"].as_str().expect("base64 file");
+    assert_eq!(BASE64.decode(binary).expect("valid base64"), b"\xff [REDACTED]");
+}
+
+#[tokio::test]
+async fn secrets_redact_split_writes() {
+    let (tx, mut rx) = tokio::io::duplex(1024);
+    let done = Arc::new(AtomicBool::new(false));
+    let mut w = RedactingWriter::new(tx, Arc::new(secrets(&[("token", "secret1")])), done.clone());
+
+    w.write_all(b"token=sec").await.unwrap();
+    w.write_all(b"ret1 and sec").await.unwrap();
+    w.flush().await.unwrap();
+    let mut buf = vec![0u8; 64];
+    let n = rx.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"token=[REDACTED] and ", "a possible secret prefix is held back");
+
+    done.store(true, std::sync::atomic::Ordering::Release);
+    w.flush().await.unwrap();
+    drop(w);
+    let mut rest = Vec::new();
+    rx.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, b"sec");
+}
+
+#[tokio::test]
+async fn api_secret_get_serves_allowed_secrets_and_redacts_them() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("secret.wat"), SECRET_WAT).expect("write secret.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_secrets(&["api_key"]);
+    let mut rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    rt.set_secrets_provider(Arc::new(StaticSecrets::new().with("api_key", "sk-live-1234").with("unused", "other-value")));
+
+    let out = rt.run("secret", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["key"], json!("[REDACTED]"), "unexpected output: {out}");
+    let logs = out["__module-logs"].to_string();
+    assert!(logs.contains("INFO: [secret] using [REDACTED]"), "unexpected logs: {logs}");
+    assert!(!logs.contains("sk-live"), "secret leaked into the logs: {logs}");
+
+    let (tx, mut rx) = tokio::io::duplex(1024);
+    rt.run_streaming("secret", json!({}), tokio::io::empty(), tx, &RunOptions::default()).await.expect("module should run");
+    let mut streamed = String::new();
+    rx.read_to_string(&mut streamed).await.unwrap();
+    assert_eq!(streamed, r#"{"key":"[REDACTED]"}"#);
+
+    let err = rt.run_with_header("secret", json!({ "args": { "token": "sk-live-1234" } }), Vec::new()).await.expect_err("header carries a secret");
+    assert!(format!("{err:#}").contains("value of secret 'api_key'"), "unexpected message: {err:#}");
+
+    rt.set_module_config("secret", ConfigOverlay { secrets: Some(Vec::new()), ..Default::default() });
+    let out = rt.run("secret", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["key"], json!(""));
+    assert!(out["__module-logs"].to_string().contains("secret_get 'api_key' denied"), "unexpected output: {out}");
+
+    rt.set_module_config("secret", ConfigOverlay { secrets: Some(vec!["missing".into()]), ..Default::default() });
+    let err = rt.run("secret", Vec::new(), HashMap::new(), Vec::new()).await.expect_err("unknown secrets fail the run");
+    assert!(format!("{err:#}").contains("secret 'missing' is not known"), "unexpected message: {err:#}");
+}
+
+#[tokio::test]
+async fn secrets_are_redacted_from_json_escapes_in_output() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("escaped.wat"), ESCAPED_WAT).expect("write escaped.wat");
+    let mut cfg = WasmConfig::default();
+    cfg.set_rootdir(root.path());
+    cfg.set_secrets(&["api_key"]);
+    let mut rt = WasmRuntime::new(cfg).expect("runtime should initialize");
+    rt.set_secrets_provider(Arc::new(StaticSecrets::new().with("api_key", "sk-live-1234")));
+
+    let out = rt.run("escaped", Vec::new(), HashMap::new(), Vec::new()).await.expect("module should run");
+    assert_eq!(out["key"], json!("[REDACTED]"), "unexpected output: {out}");
+}
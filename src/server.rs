@@ -0,0 +1,236 @@
+use crate::WasmRuntime;
+use crate::output::GuestExit;
+use crate::schema::SchemaError;
+use anyhow::{Context, Result};
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fmt::Display;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Settings of the HTTP server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Address to listen on. Default: 127.0.0.1:8080.
+    pub listen: SocketAddr,
+    /// Runs and precompilations in flight at once, requests beyond that are
+    /// answered with 503. Default: 16.
+    pub max_concurrency: usize,
+    /// Deadline of a run or precompilation, answered with 504 when exceeded.
+    /// Module limits still apply on top. Default: 30 seconds.
+    pub request_timeout: Duration,
+    /// Largest accepted request body. Default: 16 MiB.
+    pub max_body_bytes: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            max_concurrency: 16,
+            request_timeout: Duration::from_secs(30),
+            max_body_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    rt: Arc<WasmRuntime>,
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+/// Build the HTTP routes serving `rt`:
+///
+/// - `GET /health`: liveness, with the number of free run slots
+/// - `GET /modules`: the module ids, see `WasmRuntime::objects`
+/// - `POST /modules/{id}/run`: run a module and answer with its output. The
+///   body is either JSON, `{"header": {...}, "data": "<base64>"}`, or a
+///   multipart form with a JSON `header` field and a binary `data` field.
+///   Both fields are optional.
+/// - `POST /modules/{id}/precompile`: precompile a module to `.cwasm`
+///
+/// Errors are answered as `{"error": "..."}`. A header or output rejected by
+/// the module schema gets 422 and the `violations`, a guest that exits with a
+/// non-zero status gets 500 and its `exit_code`.
+pub fn router(rt: Arc<WasmRuntime>, cfg: &ServerConfig) -> Router {
+    let state = AppState { rt, permits: Arc::new(Semaphore::new(cfg.max_concurrency)), timeout: cfg.request_timeout };
+    Router::new()
+        .route("/health", get(health))
+        .route("/modules", get(list))
+        .route("/modules/{id}/run", post(run))
+        .route("/modules/{id}/precompile", post(precompile))
+        .layer(DefaultBodyLimit::max(cfg.max_body_bytes))
+        .with_state(state)
+}
+
+/// Listen on `cfg.listen` and serve `rt` until Ctrl-C.
+pub async fn serve(rt: Arc<WasmRuntime>, cfg: &ServerConfig) -> Result<()> {
+    let listener = TcpListener::bind(cfg.listen).await.with_context(|| format!("binding {}", cfg.listen))?;
+    axum::serve(listener, router(rt, cfg))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .context("serving HTTP")
+}
+
+/// Serve `rt` on an already bound listener, until the returned future is dropped.
+pub async fn serve_on(listener: TcpListener, rt: Arc<WasmRuntime>, cfg: &ServerConfig) -> Result<()> {
+    axum::serve(listener, router(rt, cfg)).await.context("serving HTTP")
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    #[serde(default = "default_header")]
+    header: Value,
+    /// Base64 encoded data for guest stdin.
+    #[serde(default)]
+    data: Option<String>,
+}
+
+fn default_header() -> Value {
+    json!({ "opts": [], "args": {} })
+}
+
+async fn health(State(st): State<AppState>) -> Json<Value> {
+    Json(json!({ "status": "ok", "available": st.permits.available_permits() }))
+}
+
+async fn list(State(st): State<AppState>) -> Result<Json<Vec<String>>, ApiError> {
+    Ok(Json(st.rt.objects()?))
+}
+
+async fn run(State(st): State<AppState>, Path(id): Path<String>, req: Request) -> Result<Json<Value>, ApiError> {
+    st.known(&id)?;
+    let (header, data) = run_input(req).await?;
+    let (rt, module, permit) = (st.rt.clone(), id.clone(), st.permit()?);
+    let out = st
+        .timed(async {
+            // Compiling blocks, so a module missing from the cache is loaded on
+            // the blocking pool, which keeps the slot until it is done.
+            let (_permit, loaded) = tokio::task::spawn_blocking(move || {
+                let loaded = rt.get_or_load_module(&module);
+                (permit, loaded)
+            })
+            .await
+            .context("compile task failed")?;
+            loaded?;
+            st.rt.run_with_header(&id, header, data).await
+        })
+        .await?;
+    Ok(Json(out))
+}
+
+async fn precompile(State(st): State<AppState>, Path(id): Path<String>) -> Result<Json<Value>, ApiError> {
+    st.known(&id)?;
+    let rt = st.rt.clone();
+    let module = id.clone();
+    let permit = st.permit()?;
+    // The blocking task keeps the slot until compilation is done, a timeout
+    // only stops waiting for it.
+    let task = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        rt.precompile_module(&module)
+    });
+    st.timed(async { task.await.context("precompile task failed")? }).await?;
+    Ok(Json(json!({ "precompiled": id })))
+}
+
+impl AppState {
+    /// Answer 404 for ids that are not in the root directory. This also keeps
+    /// path components out of the module id.
+    fn known(&self, id: &str) -> Result<(), ApiError> {
+        match self.rt.module_path(id) {
+            Some(_) => Ok(()),
+            None => Err(ApiError::new(StatusCode::NOT_FOUND, format!("unknown module '{id}'"))),
+        }
+    }
+
+    /// Take one of the concurrency slots, or answer 503 when all are in use.
+    fn permit(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        self.permits.clone().try_acquire_owned().map_err(|_| ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "server is busy, retry later"))
+    }
+
+    /// Run `fut` within the request timeout.
+    async fn timed<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T, ApiError> {
+        match tokio::time::timeout(self.timeout, fut).await {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(ApiError::new(StatusCode::GATEWAY_TIMEOUT, format!("request timed out after {:?}", self.timeout))),
+        }
+    }
+}
+
+/// Read the header and data of a run request, from JSON or a multipart form.
+async fn run_input(req: Request) -> Result<(Value, Vec<u8>), ApiError> {
+    let multipart = req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(|ct| ct.starts_with("multipart/form-data"));
+
+    if !multipart {
+        let Json(body) = Json::<RunRequest>::from_request(req, &()).await.map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+        let data = match body.data {
+            Some(text) => BASE64.decode(text).map_err(|e| ApiError::bad_request(format!("invalid base64 data: {e}")))?,
+            None => Vec::new(),
+        };
+        return Ok((body.header, data));
+    }
+
+    let mut form = Multipart::from_request(req, &()).await.map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+    let (mut header, mut data) = (default_header(), Vec::new());
+    while let Some(field) = form.next_field().await.map_err(|e| ApiError::new(e.status(), e.body_text()))? {
+        let name = field.name().map(str::to_string);
+        let bytes = field.bytes().await.map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+        match name.as_deref() {
+            Some("header") => header = serde_json::from_slice(&bytes).map_err(|e| ApiError::bad_request(format!("invalid header field: {e}")))?,
+            Some("data") => data = bytes.to_vec(),
+            other => return Err(ApiError::bad_request(format!("unexpected form field {other:?}, expected \"header\" or \"data\""))),
+        }
+    }
+    Ok((header, data))
+}
+
+/// An error answered with a status and a JSON body.
+struct ApiError {
+    status: StatusCode,
+    body: Value,
+}
+
+impl ApiError {
+    fn new<M: Display>(status: StatusCode, message: M) -> Self {
+        Self { status, body: json!({ "error": message.to_string() }) }
+    }
+
+    fn bad_request<M: Display>(message: M) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        if let Some(schema) = err.downcast_ref::<SchemaError>() {
+            return Self { status: StatusCode::UNPROCESSABLE_ENTITY, body: json!({ "error": format!("{err:#}"), "violations": schema.violations }) };
+        }
+        if let Some(exit) = err.downcast_ref::<GuestExit>() {
+            return Self { status: StatusCode::INTERNAL_SERVER_ERROR, body: json!({ "error": format!("{err:#}"), "exit_code": exit.code }) };
+        }
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}"))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    WasmRuntime,
+    cfg::WasmConfig,
+    server::{ServerConfig, serve_on},
+};
+use serde_json::{Value, json};
+use std::{fs, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Copies stdin to stdout.
+static ECHO_WAT: &str = r##"
+(module
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 4096))
+    (i32.store (i32.const 4) (i32.const 4096))
+    (i32.store (i32.const 32) (i32.const 4096))
+    (block $eof
+      (loop $copy
+        (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+        (br_if $eof (i32.eqz (i32.load (i32.const 16))))
+        (i32.store (i32.const 36) (i32.load (i32.const 16)))
+        (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 20)))
+        (br $copy)))))
+"##;
+
+static SPIN_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (loop $spin (br $spin))))
+"#;
+
+/// Start a server for `root` on an ephemeral localhost port.
+async fn start(root: &Path, cfg: ServerConfig) -> SocketAddr {
+    let mut wcfg = WasmConfig::default();
+    wcfg.set_rootdir(root);
+    let rt = Arc::new(WasmRuntime::new(wcfg).expect("runtime should initialize"));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind localhost");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move { serve_on(listener, rt, &cfg).await });
+    addr
+}
+
+/// Send one HTTP/1.1 request and return the status and JSON body.
+async fn request(addr: SocketAddr, method: &str, path: &str, content_type: &str, body: &[u8]) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.expect("connect to server");
+    let head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.expect("write request head");
+    stream.write_all(body).await.expect("write request body");
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await.expect("read response");
+    let text = String::from_utf8(raw).expect("utf-8 response");
+    let (head, body) = text.split_once("\r\n\r\n").expect("response head");
+    let status = head.split(' ').nth(1).and_then(|s| s.parse().ok()).expect("status code");
+    (status, serde_json::from_str(body).unwrap_or_else(|_| panic!("JSON body expected, got {body:?}")))
+}
+
+async fn post_json(addr: SocketAddr, path: &str, body: Value) -> (u16, Value) {
+    request(addr, "POST", path, "application/json", body.to_string().as_bytes()).await
+}
+
+#[tokio::test]
+async fn server_lists_runs_and_precompiles_modules() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("echo.wat"), ECHO_WAT).expect("write echo.wat");
+    let addr = start(root.path(), ServerConfig::default()).await;
+
+    let (status, body) = request(addr, "GET", "/health", "text/plain", b"").await;
+    assert_eq!((status, &body["status"]), (200, &json!("ok")));
+
+    let (status, body) = request(addr, "GET", "/modules", "text/plain", b"").await;
+    assert_eq!((status, body), (200, json!(["echo"])));
+
+    // JSON body with base64 data: "hello wasm".
+    let header = json!({ "opts": [], "args": { "name": "x" } });
+    let (status, body) = post_json(addr, "/modules/echo/run", json!({ "header": header, "data": "aGVsbG8gd2FzbQ==" })).await;
+    assert_eq!(status, 200, "unexpected body: {body}");
+    assert_eq!(body["data"], json!(format!("{header}\nhello wasm")));
+
+    let (status, body) = post_json(addr, "/modules/echo/precompile", json!({})).await;
+    assert_eq!((status, body), (200, json!({ "precompiled": "echo" })));
+    assert!(root.path().join("echo.cwasm").exists());
+}
+
+#[tokio::test]
+async fn server_accepts_multipart_data() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("echo.wat"), ECHO_WAT).expect("write echo.wat");
+    let addr = start(root.path(), ServerConfig::default()).await;
+
+    let form = concat!(
+        "--XBOUNDARY\r\n",
+        "Content-Disposition: form-data; name=\"header\"\r\n\r\n",
+        "{\"opts\":[\"-v\"]}\r\n",
+        "--XBOUNDARY\r\n",
+        "Content-Disposition: form-data; name=\"data\"; filename=\"in.bin\"\r\n",
+        "Content-Type: application/octet-stream\r\n\r\n",
+        "raw bytes\r\n",
+        "--XBOUNDARY--\r\n",
+    );
+    let (status, body) = request(addr, "POST", "/modules/echo/run", "multipart/form-data; boundary=XBOUNDARY", form.as_bytes()).await;
+    assert_eq!(status, 200, "unexpected body: {body}");
+    assert_eq!(body["data"], json!("{\"opts\":[\"-v\"]}\nraw bytes"));
+
+    let bad = "--XBOUNDARY\r\nContent-Disposition: form-data; name=\"other\"\r\n\r\nx\r\n--XBOUNDARY--\r\n";
+    let (status, body) = request(addr, "POST", "/modules/echo/run", "multipart/form-data; boundary=XBOUNDARY", bad.as_bytes()).await;
+    assert_eq!(status, 400);
+    assert!(body["error"].as_str().unwrap_or_default().contains("unexpected form field"), "unexpected body: {body}");
+}
+
+#[tokio::test]
+async fn server_reports_errors_with_statuses() {
+    let root = tempfile::tempdir().expect("tempdir");
+    fs::write(root.path().join("spin.wat"), SPIN_WAT).expect("write spin.wat");
+    let addr = start(root.path(), ServerConfig { request_timeout: Duration::from_millis(200), ..Default::default() }).await;
+
+    let (status, _) = post_json(addr, "/modules/missing/run", json!({})).await;
+    assert_eq!(status, 404);
+
+    let (status, _) = post_json(addr, "/modules/spin/run", json!({ "data": "not base64!" })).await;
+    assert_eq!(status, 400);
+
+    let (status, _) = post_json(addr, "/modules/spin/run", json!({ "unknown": 1 })).await;
+    assert_eq!(status, 422);
+
+    let (status, body) = post_json(addr, "/modules/spin/run", json!({})).await;
+    assert_eq!(status, 504, "unexpected body: {body}");
+    assert!(body["error"].as_str().unwrap_or_default().contains("timed out"));
+
+    let busy = start(root.path(), ServerConfig { max_concurrency: 0, ..Default::default() }).await;
+    let (status, body) = post_json(busy, "/modules/spin/run", json!({})).await;
+    assert_eq!(status, 503, "unexpected body: {body}");
+    let (status, body) = request(busy, "GET", "/health", "text/plain", b"").await;
+    assert_eq!((status, &body["available"]), (200, &json!(0)));
+}
+
+#[tokio::test]
+async fn server_compiles_off_the_executor_and_keeps_its_slot_after_a_timeout() {
+    let funcs: String =
+        (0..500).map(|i| format!("(func (export \"f{i}\") (param i64) (result i64) (i64.mul (local.get 0) (i64.const {i})))\n")).collect();
+    for path in ["/modules/big/precompile", "/modules/big/run"] {
+        let root = tempfile::tempdir().expect("tempdir");
+        fs::write(root.path().join("big.wat"), format!("(module\n{funcs})")).expect("write big.wat");
+        let addr = start(root.path(), ServerConfig { max_concurrency: 1, request_timeout: Duration::from_millis(1), ..Default::default() }).await;
+
+        let (status, body) = post_json(addr, path, json!({})).await;
+        assert_eq!(status, 504, "unexpected body for {path}: {body}");
+        let (_, body) = request(addr, "GET", "/health", "text/plain", b"").await;
+        assert_eq!(body["available"], json!(0), "the compile for {path} is still running");
+
+        let mut released = false;
+        for _ in 0..600 {
+            let (_, body) = request(addr, "GET", "/health", "text/plain", b"").await;
+            if body["available"] == json!(1) {
+                released = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(released, "the slot was not released after the compile for {path} finished");
+    }
+}